@@ -0,0 +1,124 @@
+use std::io::{self, Write};
+
+// Output filter for `--annotate-instructions`: appends a plain-language
+// explanation to every Hack instruction written through it.
+// Comments and labels pass through untouched.
+pub struct Explainer<W: Write> {
+    out: W,
+    pending: Vec<u8>,
+    a_symbol: Option<String>, // what A holds, if it was set by an A-instruction
+}
+
+impl<W: Write> Explainer<W> {
+    pub fn new(out: W) -> Self {
+        Explainer {
+            out,
+            pending: vec![],
+            a_symbol: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let instruction = line.trim();
+        if instruction.is_empty() || instruction.starts_with("//") {
+            return writeln!(self.out, "{}", line);
+        }
+        if instruction.starts_with('(') {
+            // control can arrive here from anywhere
+            self.a_symbol = None;
+            return writeln!(self.out, "{}", line);
+        }
+        let explanation = self.explain(instruction);
+        writeln!(self.out, "{:<16}// {}", line, explanation)
+    }
+
+    fn explain(&mut self, instruction: &str) -> String {
+        if let Some(value) = instruction.strip_prefix('@') {
+            self.a_symbol = Some(value.to_string());
+            return if value.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("A = {}", value)
+            } else {
+                format!("A = {}", describe_symbol(value))
+            };
+        }
+
+        let (dest, rest) = match instruction.find('=') {
+            Some(n) => (&instruction[..n], &instruction[n + 1..]),
+            None => ("", instruction),
+        };
+        let (comp, jump) = match rest.find(';') {
+            Some(n) => (&rest[..n], &rest[n + 1..]),
+            None => (rest, ""),
+        };
+        let memory = match &self.a_symbol {
+            Some(symbol) => format!("RAM[{}]", symbol),
+            None => String::from("RAM[A]"),
+        };
+        let value = comp.replace('M', &memory);
+
+        let mut parts = vec![];
+        if !dest.is_empty() {
+            let targets: Vec<String> = dest.chars().map(|c| match c {
+                'M' => memory.clone(),
+                c => c.to_string(),
+            }).collect();
+            parts.push(format!("{} = {}", targets.join(", "), value));
+        }
+        if !jump.is_empty() {
+            let target = match &self.a_symbol {
+                Some(symbol) => symbol.clone(),
+                None => String::from("A"),
+            };
+            let condition = match jump {
+                "JGT" => " > 0",
+                "JEQ" => " == 0",
+                "JGE" => " >= 0",
+                "JLT" => " < 0",
+                "JNE" => " != 0",
+                "JLE" => " <= 0",
+                _ => "",
+            };
+            if condition.is_empty() {
+                parts.push(format!("goto {}", target));
+            } else {
+                parts.push(format!("if {}{} goto {}", value, condition, target));
+            }
+        }
+        if dest.contains('A') {
+            self.a_symbol = None;
+        }
+        parts.join("; ")
+    }
+}
+
+impl<W: Write> Write for Explainer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(n) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=n).collect();
+            let line = String::from_utf8_lossy(&line[..n]).to_string();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let line = String::from_utf8_lossy(&self.pending).to_string();
+            self.pending.clear();
+            self.write_line(&line)?;
+        }
+        self.out.flush()
+    }
+}
+
+fn describe_symbol(symbol: &str) -> String {
+    match symbol {
+        "R13" | "R14" | "R15" => format!("&{} (scratch register)", symbol),
+        _ => format!("&{}", symbol),
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+// Hack assembler, used for `--emit hack`.
+// The translator output needs the standard instruction set unless it targets
+// Cpu::Extended, but hand-written .asm is accepted as well, so errors are reported
+// with their source line.
+
+// the instruction set a program is written for
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Cpu {
+    Standard,
+    // adds the shift computations A<<, D<<, M<< (left) and A>>, D>>, M>>
+    // (arithmetic right), encoded with the prefix 101 instead of 111
+    Extended,
+}
+
+impl Cpu {
+    // the CPU named by a --cpu option
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "standard" => Ok(Cpu::Standard),
+            "extended" => Ok(Cpu::Extended),
+            _ => Err(format!("unknown CPU '{}' (expected standard or extended)", name)),
+        }
+    }
+}
+
+pub fn assemble(source: &str, cpu: Cpu) -> Result<Vec<u16>, String> {
+    assemble_with_symbols(source, cpu).map(|(code, _)| code)
+}
+
+// also returns the symbol table: labels, variables and the predefined symbols
+pub fn assemble_with_symbols(source: &str, cpu: Cpu) -> Result<(Vec<u16>, HashMap<String, u16>), String> {
+    let mut symbols = predefined_symbols();
+
+    // first pass: strip comments and whitespace, bind labels to ROM addresses
+    let mut instructions: Vec<(usize, String)> = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let l = match line.find("//") {
+            Some(n) => &line[..n],
+            None => line,
+        };
+        let l: String = l.chars().filter(|c| !c.is_whitespace()).collect();
+        if l.is_empty() {
+            continue;
+        }
+        if l.starts_with('(') {
+            if !l.ends_with(')') || l.len() < 3 {
+                return Err(format!("line {}: malformed label '{}'", i + 1, l));
+            }
+            let label = &l[1..l.len() - 1];
+            if symbols.insert(label.to_string(), instructions.len() as u16).is_some() {
+                return Err(format!("line {}: duplicate label '{}'", i + 1, label));
+            }
+        } else {
+            instructions.push((i + 1, l));
+        }
+    }
+    if instructions.len() > 32768 {
+        return Err(format!("program has {} instructions but the ROM holds 32768", instructions.len()));
+    }
+
+    // second pass: encode, allocating variables from RAM[16]
+    let mut next_variable = 16;
+    let mut code = vec![];
+    for (line, instruction) in instructions.iter() {
+        let word = if let Some(value) = instruction.strip_prefix('@') {
+            if value.starts_with(|c: char| c.is_ascii_digit()) {
+                match value.parse::<u16>() {
+                    Ok(n) if n <= 0x7fff => n,
+                    _ => return Err(format!("line {}: constant '{}' is out of range", line, value)),
+                }
+            } else if let Some(&address) = symbols.get(value) {
+                address
+            } else {
+                symbols.insert(value.to_string(), next_variable);
+                next_variable += 1;
+                next_variable - 1
+            }
+        } else {
+            match encode_c_instruction(instruction) {
+                Some(word) if is_shift(word) && cpu == Cpu::Standard => {
+                    return Err(format!("line {}: '{}' needs the extended CPU", line, instruction));
+                },
+                Some(word) => word,
+                None => return Err(format!("line {}: invalid instruction '{}'", line, instruction)),
+            }
+        };
+        code.push(word);
+    }
+    Ok((code, symbols))
+}
+
+// .hack text format: one 16-digit binary word per line
+pub fn to_hack_text(code: &[u16]) -> String {
+    let mut s = String::new();
+    for word in code.iter() {
+        s.push_str(&format!("{:016b}\n", word));
+    }
+    s
+}
+
+// reads the .hack text format back
+pub fn parse_hack_text(text: &str) -> Result<Vec<u16>, String> {
+    let mut code = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let l = line.trim();
+        if l.is_empty() {
+            continue;
+        }
+        if l.len() != 16 {
+            return Err(format!("line {}: expected 16 binary digits, got '{}'", i + 1, l));
+        }
+        match u16::from_str_radix(l, 2) {
+            Ok(word) => code.push(word),
+            Err(_) => return Err(format!("line {}: expected 16 binary digits, got '{}'", i + 1, l)),
+        }
+    }
+    if code.len() > 32768 {
+        return Err(format!("program has {} instructions but the ROM holds 32768", code.len()));
+    }
+    Ok(code)
+}
+
+pub fn is_shift(word: u16) -> bool {
+    word >> 13 == 0b101
+}
+
+fn predefined_symbols() -> HashMap<String, u16> {
+    let mut symbols = HashMap::new();
+    for (name, address) in [("SP", 0), ("LCL", 1), ("ARG", 2), ("THIS", 3), ("THAT", 4),
+                            ("SCREEN", 16384), ("KBD", 24576)] {
+        symbols.insert(name.to_string(), address);
+    }
+    for i in 0..16 {
+        symbols.insert(format!("R{}", i), i);
+    }
+    symbols
+}
+
+fn encode_c_instruction(instruction: &str) -> Option<u16> {
+    let (dest, rest) = match instruction.find('=') {
+        Some(n) => (&instruction[..n], &instruction[n + 1..]),
+        None => ("", instruction),
+    };
+    let (comp, jump) = match rest.find(';') {
+        Some(n) => (&rest[..n], &rest[n + 1..]),
+        None => (rest, ""),
+    };
+    let (prefix, comp_bits) = match encode_shift(comp) {
+        Some(bits) => (0b101, bits),
+        None => (0b111, encode_comp(comp)?),
+    };
+    let dest_bits = encode_dest(dest)?;
+    let jump_bits = match jump {
+        "" => 0,
+        "JGT" => 1,
+        "JEQ" => 2,
+        "JGE" => 3,
+        "JLT" => 4,
+        "JNE" => 5,
+        "JLE" => 6,
+        "JMP" => 7,
+        _ => return None,
+    };
+    Some(prefix << 13 | comp_bits << 6 | dest_bits << 3 | jump_bits)
+}
+
+// dest letters may come in any order (AM, MA, ...) but each at most once
+fn encode_dest(dest: &str) -> Option<u16> {
+    let mut bits = 0;
+    for c in dest.chars() {
+        let bit = match c {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+    Some(bits)
+}
+
+// returns the a-bit followed by c1..c6
+fn encode_comp(comp: &str) -> Option<u16> {
+    let bits = match comp {
+        "0" => 0b0101010,
+        "1" => 0b0111111,
+        "-1" => 0b0111010,
+        "D" => 0b0001100,
+        "A" => 0b0110000,
+        "M" => 0b1110000,
+        "!D" => 0b0001101,
+        "!A" => 0b0110001,
+        "!M" => 0b1110001,
+        "-D" => 0b0001111,
+        "-A" => 0b0110011,
+        "-M" => 0b1110011,
+        "D+1" | "1+D" => 0b0011111,
+        "A+1" | "1+A" => 0b0110111,
+        "M+1" | "1+M" => 0b1110111,
+        "D-1" => 0b0001110,
+        "A-1" => 0b0110010,
+        "M-1" => 0b1110010,
+        "D+A" | "A+D" => 0b0000010,
+        "D+M" | "M+D" => 0b1000010,
+        "D-A" => 0b0010011,
+        "D-M" => 0b1010011,
+        "A-D" => 0b0000111,
+        "M-D" => 0b1000111,
+        "D&A" | "A&D" => 0b0000000,
+        "D&M" | "M&D" => 0b1000000,
+        "D|A" | "A|D" => 0b0010101,
+        "D|M" | "M|D" => 0b1010101,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+// the a-bit and c1..c6 of the extended CPU's shift computations
+fn encode_shift(comp: &str) -> Option<u16> {
+    let bits = match comp {
+        "A<<" => 0b0100000,
+        "D<<" => 0b0110000,
+        "M<<" => 0b1100000,
+        "A>>" => 0b0000000,
+        "D>>" => 0b0010000,
+        "M>>" => 0b1000000,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_are_encoded_with_prefix_101() {
+        let forms = [
+            ("A<<", 0b0100000), ("D<<", 0b0110000), ("M<<", 0b1100000),
+            ("A>>", 0b0000000), ("D>>", 0b0010000), ("M>>", 0b1000000),
+        ];
+        for (comp, bits) in forms {
+            let code = assemble(&format!("D={}", comp), Cpu::Extended).unwrap();
+            assert_eq!(code, vec![0b101 << 13 | bits << 6 | 0b010 << 3], "{}", comp);
+            assert!(is_shift(code[0]));
+        }
+        // every dest and jump combines with a shift
+        let code = assemble("AM=M<<;JLT", Cpu::Extended).unwrap();
+        assert_eq!(code, vec![0b101 << 13 | 0b1100000 << 6 | 0b101 << 3 | 0b100]);
+    }
+
+    #[test]
+    fn shifts_need_the_extended_cpu() {
+        for comp in ["A<<", "D<<", "M<<", "A>>", "D>>", "M>>"] {
+            let e = assemble(&format!("@1\nD={}", comp), Cpu::Standard).unwrap_err();
+            assert_eq!(e, format!("line 2: 'D={}' needs the extended CPU", comp));
+        }
+        assert!(!is_shift(assemble("D=D+M", Cpu::Standard).unwrap()[0]));
+    }
+
+    #[test]
+    fn standard_instructions_are_unchanged_on_the_extended_cpu() {
+        let source = "@17\nD=D+M\nM=D|M;JNE\n0;JMP";
+        assert_eq!(assemble(source, Cpu::Extended), assemble(source, Cpu::Standard));
+    }
+
+    #[test]
+    fn hack_text_round_trips() {
+        let code = assemble("@5\nD=A<<\n@SP\nM=D", Cpu::Extended).unwrap();
+        let text = to_hack_text(&code);
+        assert_eq!(text.lines().next(), Some("0000000000000101"));
+        assert_eq!(parse_hack_text(&text), Ok(code));
+        assert_eq!(parse_hack_text("\n0000000000000001\n\n"), Ok(vec![1]));
+    }
+
+    #[test]
+    fn hack_text_rejects_malformed_lines() {
+        assert_eq!(parse_hack_text("0000000000000001\n101"),
+            Err(String::from("line 2: expected 16 binary digits, got '101'")));
+        assert!(parse_hack_text("000000000000000x").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::code_writer::Bootstrap;
+use crate::parser::{Command, CommandType, VmFile};
+
+// A code generator for VM programs. translate() drives a backend through a whole
+// program: begin_program, then for every file begin_file, one call per command and
+// end_file, and finally end_program, which must flush everything to the output.
+// With tail_calls, a call directly followed by return goes to write_tail_call
+// instead, and the return is not translated on its own.
+//
+// CodeWriter is the Hack implementation; the native backends (x86, C, WebAssembly,
+// RISC-V) implement the same interface, so main can pick one at runtime. They
+// keep CodeWriter's RAM layout, so their RAM dumps can be compared with the CPU
+// emulator: SP, LCL, ARG, THIS and THAT in RAM[0..5] with the same call frames,
+// statics from RAM[16] in order of first use, as the Hack assembler allocates
+// them, and eq, gt and lt on the sign of the wrapped 16-bit difference, as the
+// Hack ALU computes it. Names holds what they share for this.
+pub trait Backend {
+    // emits the bootstrap code, if any, before the first file
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>);
+
+    // name is the file name without the .vm extension, used to scope statics
+    fn begin_file(&mut self, name: &str);
+
+    fn end_file(&mut self) {}
+
+    // called before each command is translated, e.g. for annotations
+    fn begin_command(&mut self, _command: &Command) {}
+
+    fn write_arithmetic(&mut self, command: String);
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16);
+    fn write_label(&mut self, label: String);
+    fn write_goto(&mut self, label: String);
+    fn write_if(&mut self, label: String);
+    fn write_function(&mut self, function_name: String, num_locals: i16);
+    fn write_call(&mut self, function_name: String, num_args: i16);
+    fn write_return(&mut self);
+
+    // `call function_name num_args` directly followed by `return`; backends that
+    // can reuse the caller's frame override this
+    fn write_tail_call(&mut self, function_name: String, num_args: i16) {
+        self.write_call(function_name, num_args);
+        self.write_return();
+    }
+
+    fn end_program(&mut self) -> io::Result<()>;
+}
+
+pub fn translate<B: Backend + ?Sized>(backend: &mut B, files: &[VmFile], bootstrap: Option<&Bootstrap>, tail_calls: bool) -> io::Result<()> {
+    backend.begin_program(bootstrap);
+    for file in files.iter() {
+        backend.begin_file(&file.name);
+        let mut commands = file.commands.iter().peekable();
+        while let Some(cmd) = commands.next() {
+            let is_tail_call = tail_calls
+                && cmd.command_type == CommandType::Call
+                && commands.peek().is_some_and(|next| next.command_type == CommandType::Return);
+            if is_tail_call {
+                backend.begin_command(cmd);
+                backend.write_tail_call(cmd.arg1.clone(), cmd.arg2);
+                commands.next();
+            } else {
+                write_command(backend, cmd);
+            }
+        }
+        backend.end_file();
+    }
+    backend.end_program()
+}
+
+fn write_command<B: Backend + ?Sized>(backend: &mut B, cmd: &Command) {
+    backend.begin_command(cmd);
+    match cmd.command_type {
+        CommandType::Arithmetic => {
+            backend.write_arithmetic(cmd.arg1.clone());
+        },
+        CommandType::Push => {
+            backend.write_push_pop(String::from("push"), cmd.arg1.clone(), cmd.arg2);
+        },
+        CommandType::Pop => {
+            backend.write_push_pop(String::from("pop"), cmd.arg1.clone(), cmd.arg2);
+        },
+        CommandType::Label => {
+            backend.write_label(cmd.arg1.clone());
+        },
+        CommandType::Goto => {
+            backend.write_goto(cmd.arg1.clone());
+        },
+        CommandType::If => {
+            backend.write_if(cmd.arg1.clone());
+        },
+        CommandType::Function => {
+            backend.write_function(cmd.arg1.clone(), cmd.arg2);
+        },
+        CommandType::Return => {
+            backend.write_return();
+        },
+        CommandType::Call => {
+            backend.write_call(cmd.arg1.clone(), cmd.arg2);
+        },
+    }
+}
+
+// where a native backend finds segment[index]
+pub enum Location {
+    Based(usize, i16), // the RAM address of LCL, ARG, THIS or THAT, and the index
+    Fixed(usize),      // the RAM address of a pointer, temp or static entry
+}
+
+// the names a native backend resolves while translating: statics, labels, which
+// are scoped to their function as function$label, and the label defined just
+// before the current command, since `label L; goto L` is how VM programs stop
+#[derive(Default)]
+pub struct Names {
+    pub function_name: String,
+    pub call_count: usize, // return points so far
+    filename: String,
+    statics: HashMap<String, usize>,
+    last_label: Option<String>,
+}
+
+impl Names {
+    pub fn begin_file(&mut self, name: &str) {
+        self.filename = name.to_string();
+    }
+
+    pub fn begin_function(&mut self, name: String) {
+        self.command();
+        self.function_name = name;
+    }
+
+    // a command other than label or goto
+    pub fn command(&mut self) {
+        self.last_label = None;
+    }
+
+    pub fn scoped(&self, label: &str) -> String {
+        if !self.function_name.is_empty() {
+            format!("{}${}", self.function_name, label)
+        } else {
+            label.to_string()
+        }
+    }
+
+    // the scoped name of a label being defined
+    pub fn label(&mut self, label: String) -> String {
+        let name = self.scoped(&label);
+        self.last_label = Some(label);
+        name
+    }
+
+    // the scoped target of `goto label`, None when it halts
+    pub fn goto(&mut self, label: &str) -> Option<String> {
+        let halts = self.last_label.as_deref() == Some(label);
+        self.command();
+        if halts { None } else { Some(self.scoped(label)) }
+    }
+
+    pub fn locate(&mut self, segment: &str, index: i16) -> Location {
+        match segment {
+            "local" => Location::Based(1, index),
+            "argument" => Location::Based(2, index),
+            "this" => Location::Based(3, index),
+            "that" => Location::Based(4, index),
+            "pointer" => Location::Fixed(3 + index as usize),
+            "temp" => Location::Fixed(5 + index as usize),
+            "static" => {
+                let name = format!("{}.{}", self.filename, index);
+                let next = 16 + self.statics.len();
+                Location::Fixed(*self.statics.entry(name).or_insert(next))
+            },
+            // the parser rejects unknown segments
+            _ => unreachable!("not a segment"),
+        }
+    }
+}
+
+// VM names may contain '.', '$' and ':', which assemblers and C compilers would
+// misread; the result is a valid identifier in both
+pub fn mangle(name: &str) -> String {
+    let mut s = String::from("vm_");
+    for c in name.chars() {
+        match c {
+            '_' => s.push_str("__"),
+            c if c.is_ascii_alphanumeric() => s.push(c),
+            c => s.push_str(&format!("_{:02x}", c as u32)),
+        }
+    }
+    s
+}
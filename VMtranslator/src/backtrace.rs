@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::emulator::RAM_SIZE;
+use crate::source_map::{self, Entry};
+
+// Symbolic backtraces from the RAM of a translated program.
+//
+// write_call leaves below each frame's locals the return address and the caller's
+// LCL, ARG, THIS and THAT, so starting from LCL the frames form a chain. A return
+// address that ends a translated `call f n` names the function f and its argument
+// count, and the call itself tells in which function the caller was; the chain
+// ends at the call made by the bootstrap code, or at the first return address
+// that is not a call site, e.g. because the frame was overwritten.
+//
+// Without the PC, the innermost function is taken from the call that created its
+// frame, which is wrong after a tail call (`--tail-calls`) replaced the frame.
+
+// frames walked at most, in case the links in RAM form a cycle
+const MAX_FRAMES: usize = 10_000;
+
+pub struct Frame {
+    pub function: String,
+    pub address: Option<usize>, // ROM address executing in this frame, if known
+    pub lcl: usize,
+    pub arg: usize,
+    pub args: Vec<i16>,
+}
+
+struct CallSite {
+    start: usize,
+    caller: String, // function containing the call, empty outside functions
+    callee: String,
+    num_args: usize,
+}
+
+// the call sites of a translated program, indexed by return address
+pub struct Unwinder {
+    map: Vec<Entry>,
+    return_sites: HashMap<usize, CallSite>,
+    bootstrap_end: Option<usize>,
+}
+
+impl Unwinder {
+    pub fn new(map: &[Entry]) -> Self {
+        let mut return_sites = HashMap::new();
+        let mut bootstrap_end = None;
+        for entry in map.iter() {
+            if let Some((callee, num_args)) = call_target(entry) {
+                return_sites.insert(entry.end, CallSite {
+                    start: entry.start,
+                    caller: entry.function.clone(),
+                    callee: callee.to_string(),
+                    num_args,
+                });
+            } else if entry.command == "bootstrap" {
+                bootstrap_end = Some(entry.end);
+            }
+        }
+        Unwinder {
+            map: map.to_vec(),
+            return_sites,
+            bootstrap_end,
+        }
+    }
+
+    // innermost frame first; empty when pc is in code outside any function
+    pub fn backtrace(&self, ram: &[i16], pc: Option<usize>) -> Vec<Frame> {
+        let register = |address: usize| ram[address] as u16 as usize;
+        let mut frames = vec![];
+        let mut address = pc;
+        let mut function = match pc {
+            Some(pc) => match source_map::find(&self.map, pc) {
+                Some(entry) if !entry.function.is_empty() => Some(entry.function.clone()),
+                _ => return frames,
+            },
+            None => None,
+        };
+        let mut lcl = register(1);
+        let mut arg = register(2);
+        while frames.len() < MAX_FRAMES && (5..RAM_SIZE).contains(&lcl) {
+            let return_address = register(lcl - 5);
+            let call = match self.return_sites.get(&return_address) {
+                Some(call) => Some(call),
+                None if Some(return_address) == self.bootstrap_end => None,
+                None => break,
+            };
+            if function.is_none() {
+                function = call.map(|call| call.callee.clone());
+            }
+            let num_args = call.map_or(0, |call| call.num_args);
+            let args = (arg..arg + num_args).filter(|&a| a < RAM_SIZE).map(|a| ram[a]).collect();
+            frames.push(Frame {
+                function: function.take().unwrap_or_else(|| String::from("(entry function)")),
+                address,
+                lcl,
+                arg,
+                args,
+            });
+            let call = match call {
+                Some(call) => call,
+                None => break,
+            };
+            address = Some(call.start);
+            function = Some(call.caller.clone()).filter(|f| !f.is_empty());
+            arg = register(lcl - 3);
+            lcl = register(lcl - 4);
+        }
+        // a function that was jumped to without a call, as in the course's tests
+        if frames.is_empty() {
+            if let Some(function) = function {
+                frames.push(Frame { function, address, lcl, arg, args: vec![] });
+            }
+        }
+        frames
+    }
+}
+
+// "1, 2, 3"
+pub fn format_args(args: &[i16]) -> String {
+    args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+// (function, number of arguments) of a `call` entry
+fn call_target(entry: &Entry) -> Option<(&str, usize)> {
+    let mut words = entry.command.strip_prefix("call ")?.split_whitespace();
+    let function = words.next()?;
+    let num_args = words.next()?.parse().ok()?;
+    Some((function, num_args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Options, Program};
+
+    // FibonacciElement stopped at the first command of fibonacci(0), called
+    // from fibonacci(2), called from fibonacci(4)
+    #[test]
+    fn frames_of_the_recursion() {
+        let options = Options { os: false, ..Options::default() };
+        let path = format!("{}/vm_code/FunctionCalls/FibonacciElement", env!("CARGO_MANIFEST_DIR"));
+        let program = Program::translate(&path, &options).unwrap();
+        let unwinder = Unwinder::new(&program.map);
+        let mut emulator = program.emulator(&options);
+        let at_function = |pc: usize| source_map::find(&program.map, pc).is_some_and(|e| e.command.starts_with("function"));
+        while unwinder.backtrace(&emulator.ram, Some(emulator.pc as usize)).len() < 4 || !at_function(emulator.pc as usize) {
+            emulator.step().unwrap();
+        }
+        let describe = |frames: Vec<Frame>| -> Vec<String> {
+            frames.iter().map(|frame| {
+                let at = frame.address.and_then(|address| source_map::find(&program.map, address));
+                let at = at.map_or(String::from("?"), |e| format!("{}:{}: {}", e.file, e.line, e.command));
+                format!("{}({}) at {} [LCL {}, ARG {}]", frame.function, format_args(&frame.args), at, frame.lcl, frame.arg)
+            }).collect()
+        };
+        let callers = [
+            "Main.fibonacci(2) at Main.vm:24: call Main.fibonacci 1 [LCL 273, ARG 267]",
+            "Main.fibonacci(4) at Main.vm:24: call Main.fibonacci 1 [LCL 267, ARG 261]",
+            "Sys.init() at Sys.vm:13: call Main.fibonacci 1 [LCL 261, ARG 256]",
+        ];
+        let frames = describe(unwinder.backtrace(&emulator.ram, Some(emulator.pc as usize)));
+        assert_eq!(frames[0], "Main.fibonacci(0) at Main.vm:11: function Main.fibonacci 0 [LCL 279, ARG 273]");
+        assert_eq!(frames[1..], callers);
+        // without the pc, from the call that made the innermost frame
+        let frames = describe(unwinder.backtrace(&emulator.ram, None));
+        assert_eq!(frames[0], "Main.fibonacci(0) at ? [LCL 279, ARG 273]");
+        assert_eq!(frames[1..], callers);
+
+        // a return address that is not a call site ends the chain: the one
+        // fibonacci(4)'s frame holds
+        emulator.ram[267 - 5] = 0;
+        let frames = describe(unwinder.backtrace(&emulator.ram, Some(emulator.pc as usize)));
+        assert_eq!(frames[1..], callers[..1]);
+    }
+}
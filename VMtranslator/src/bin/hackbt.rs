@@ -0,0 +1,194 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use vmtranslator::assembler::{self, Cpu};
+use vmtranslator::backtrace::{self, Unwinder};
+use vmtranslator::emulator::{Emulator, RAM_SIZE};
+use vmtranslator::source_map;
+
+const USAGE: &str = "\
+usage: hackbt [options] --ram <dump>
+       hackbt [options] <filename>.hack | <filename>.asm
+
+Prints the VM-level backtrace of a translated program from its RAM: the
+functions being called, innermost first, with their arguments. The RAM comes
+from a dump, or from running the program in the emulator until it halts or
+reaches the cycle limit. The source map written by 'VMtranslator --source-map'
+names the functions and VM commands.
+
+options:
+  --map <path>       the source map; defaults to the program's .map file
+  --ram <path>       RAM dump: one value per line from RAM[0], or lines
+                     'RAM[<addr>] = <value>' as printed by hackrun --dump
+  --pc <n>           ROM address the program was at when the RAM was dumped;
+                     without it, the innermost function is the one whose call
+                     created the innermost frame
+  --cpu <kind>       standard (the default) or extended
+  --cycles <n>       run at most <n> instructions (default 100000000)
+  -h, --help         print this help and exit
+";
+
+const DEFAULT_CYCLES: u64 = 100_000_000;
+
+struct Options {
+    program: Option<String>,
+    map: Option<String>,
+    ram: Option<String>,
+    pc: Option<usize>,
+    cpu: Cpu,
+    cycles: u64,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hackbt: {}", e);
+            eprintln!("try 'hackbt --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hackbt: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let map_path = match (&options.map, &options.program) {
+        (Some(map), _) => map.clone(),
+        (None, Some(program)) => Path::new(program).with_extension("map").to_string_lossy().to_string(),
+        (None, None) => return Err(String::from("a RAM dump needs --map")),
+    };
+    let map_text = fs::read_to_string(&map_path)
+        .map_err(|e| format!("cannot read '{}': {}", map_path, e))?;
+    let map = source_map::from_json(&map_text).map_err(|e| format!("{}: {}", map_path, e))?;
+
+    let (ram, pc) = match &options.program {
+        Some(program) => {
+            let text = fs::read_to_string(program)
+                .map_err(|e| format!("cannot read '{}': {}", program, e))?;
+            let rom = if Path::new(program).extension().is_some_and(|ext| ext == "asm") {
+                assembler::assemble(&text, options.cpu)?
+            } else {
+                assembler::parse_hack_text(&text)?
+            };
+            if map.last().map(|e| e.end) != Some(rom.len()) {
+                return Err(format!("'{}' does not belong to '{}'", map_path, program));
+            }
+            let mut emulator = Emulator::new(rom, options.cpu);
+            let halted = emulator.run(options.cycles)?;
+            if halted {
+                println!("halted after {} cycles", emulator.cycles);
+            } else {
+                println!("stopped after {} cycles", emulator.cycles);
+            }
+            (emulator.ram, Some(emulator.pc as usize))
+        },
+        None => {
+            let path = options.ram.as_ref().unwrap();
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read '{}': {}", path, e))?;
+            (read_dump(&text).map_err(|e| format!("{}: {}", path, e))?, options.pc)
+        },
+    };
+
+    let frames = Unwinder::new(&map).backtrace(&ram, pc);
+    if frames.is_empty() {
+        let location = pc.and_then(|pc| source_map::find(&map, pc));
+        match location {
+            Some(e) if !e.file.is_empty() => println!("#0   (top level) at {}:{}", e.file, e.line),
+            _ => println!("no frames"),
+        }
+    }
+    for (depth, frame) in frames.iter().enumerate() {
+        let location = match frame.address.and_then(|address| source_map::find(&map, address)) {
+            Some(e) if !e.file.is_empty() => format!("{}:{}: {}", e.file, e.line, e.command),
+            Some(e) => e.command.clone(),
+            None => String::from("?"),
+        };
+        println!("#{:<3} {}({}) at {}  [LCL {}, ARG {}]",
+            depth, frame.function, backtrace::format_args(&frame.args), location, frame.lcl, frame.arg);
+    }
+    Ok(())
+}
+
+fn read_dump(text: &str) -> Result<Vec<i16>, String> {
+    let mut ram = vec![0; RAM_SIZE];
+    let mut next = 0;
+    for (i, line) in text.lines().enumerate() {
+        let l = line.trim();
+        if l.is_empty() {
+            continue;
+        }
+        let (address, value) = match l.strip_prefix("RAM[").and_then(|rest| rest.split_once("] =")) {
+            Some((address, value)) => (address.parse::<usize>().ok(), value.trim()),
+            None => (Some(next), l),
+        };
+        let address = match address {
+            Some(address) if address < RAM_SIZE => address,
+            _ => return Err(format!("line {}: no RAM address in '{}'", i + 1, l)),
+        };
+        ram[address] = value.parse::<i16>()
+            .map_err(|_| format!("line {}: '{}' is not a value in -32768..32767", i + 1, value))?;
+        next = address + 1;
+    }
+    Ok(ram)
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut program = None;
+    let mut map = None;
+    let mut ram = None;
+    let mut pc = None;
+    let mut cpu = Cpu::Standard;
+    let mut cycles = DEFAULT_CYCLES;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--map" => map = Some(iter.next().ok_or("option '--map' requires a path")?.clone()),
+            "--ram" => ram = Some(iter.next().ok_or("option '--ram' requires a path")?.clone()),
+            "--pc" => {
+                pc = match iter.next().map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    Some(Err(_)) => return Err(String::from("option '--pc' expects a ROM address")),
+                    None => return Err(String::from("option '--pc' requires a ROM address")),
+                };
+            },
+            "--cpu" => match iter.next() {
+                Some(name) => cpu = Cpu::from_name(name)?,
+                None => return Err(String::from("option '--cpu' requires a kind")),
+            },
+            "--cycles" => {
+                cycles = match iter.next().map(|v| v.parse::<u64>()) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Err(String::from("option '--cycles' expects a number")),
+                    None => return Err(String::from("option '--cycles' requires a number")),
+                };
+            },
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if program.is_some() {
+                    return Err(format!("unexpected argument '{}': only one program can be given", arg));
+                }
+                program = Some(arg.clone());
+            },
+        }
+    }
+
+    match (&program, &ram) {
+        (Some(_), Some(_)) => Err(String::from("give either a program or --ram, not both")),
+        (None, None) => Err(String::from("no program or RAM dump given")),
+        _ => Ok(Some(Options { program, map, ram, pc, cpu, cycles })),
+    }
+}
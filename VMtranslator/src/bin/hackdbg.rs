@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::env;
+use std::process;
+
+use vmtranslator::backtrace::{self, Frame, Unwinder};
+use vmtranslator::emulator::{Emulator, RAM_SIZE};
+use vmtranslator::program::{self, Program};
+use vmtranslator::repl::{self, Breakpoints};
+use vmtranslator::source_map::Entry;
+
+const USAGE: &str = "\
+usage: hackdbg [options] <filename>.vm | <filename>.jack | <dirname>
+
+Translates a VM program to Hack, runs the machine code in an emulator and
+debugs it in terms of the VM commands: breakpoints, stepping and inspection
+work on VM commands, functions and segments, read from the emulated RAM, and
+stepi and disassemble show the Hack instructions of each command. Commands are
+read from standard input, or from a script given with -x, one per line.
+
+options:
+";
+
+// after the translation options
+const OPTIONS: &str = "  -x <path>          read debugger commands from <path>
+  -h, --help         print this help and exit
+";
+
+const COMMANDS: &str = "\
+break <File.function> | <file>:<line>
+                 stop before the function or the first command on the line (b)
+delete [<n>]     remove breakpoint <n>, or all breakpoints
+info breakpoints list the breakpoints (info b)
+continue         run until a breakpoint or the end of the program (c, run, r)
+step [<n>]       run to the start of the next VM command, <n> times (s)
+next [<n>]       like step, but run each call to its return (n)
+finish           run until the current function returns
+stepi [<n>]      execute <n> Hack instructions, default 1 (si)
+disassemble      print the Hack instructions of the current command (di)
+registers        print PC, A and D (regs)
+stack            print the working stack of the current function
+print <segment> [<index>]
+                 print a segment of the current function, or one entry (p)
+backtrace        print the functions being called, from the frames in RAM (bt)
+list             print the VM commands around the current one (l)
+ram <addr> [<count>]
+                 print RAM from <addr> (x)
+set <addr> <value>
+                 write <value> to RAM[<addr>]
+help             print this list (h)
+quit             leave the debugger (q)
+An empty line repeats the previous command.
+";
+
+// a continue or next runs at most this many instructions before giving control back
+const MAX_CYCLES: u64 = 1_000_000_000;
+
+struct Options {
+    input: String,
+    translation: program::Options,
+    script: Option<String>,
+}
+
+struct Debugger {
+    emulator: Emulator,
+    map: Vec<Entry>,
+    unwinder: Unwinder,
+    entry_at: Vec<usize>,                 // map entry of each ROM address
+    instructions: Vec<String>,            // assembly text of each ROM address
+    symbols: HashMap<String, u16>,
+    num_locals: HashMap<String, i16>,
+    breakpoints: Breakpoints,             // at ROM addresses
+    bottom: usize,                        // of the stack outside any function, the bootstrap SP
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}{}{}", USAGE, program::USAGE, OPTIONS);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hackdbg: {}", e);
+            eprintln!("try 'hackdbg --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hackdbg: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let program = Program::translate(&options.input, &options.translation)?;
+    let mut debugger = Debugger {
+        emulator: program.emulator(&options.translation),
+        unwinder: Unwinder::new(&program.map),
+        entry_at: program.entry_at(),
+        num_locals: program.num_locals(),
+        map: program.map,
+        instructions: program.instructions,
+        symbols: program.symbols,
+        breakpoints: Breakpoints::default(),
+        bottom: options.translation.bootstrap.sp as usize,
+    };
+    debugger.show_current();
+    repl::run("(hackdbg)", options.script.as_deref(), |words| debugger.execute(words))
+}
+
+impl Debugger {
+    // runs one debugger command; returns false to quit
+    fn execute(&mut self, words: &[&str]) -> Result<bool, String> {
+        let args = &words[1..];
+        match words[0] {
+            "break" | "b" => self.add_breakpoint(args)?,
+            "delete" | "d" => self.breakpoints.delete(args)?,
+            "info" if args.first().is_some_and(|a| "breakpoints".starts_with(a)) => {
+                self.breakpoints.list(|address| self.location(address));
+            },
+            "continue" | "c" | "run" | "r" => {
+                self.resume(|_| false)?;
+            },
+            "step" | "s" => {
+                for _ in 0..repl::count(args)? {
+                    if !self.resume(|_| true)? {
+                        break;
+                    }
+                }
+            },
+            "next" | "n" => {
+                for _ in 0..repl::count(args)? {
+                    let depth = self.frames().len();
+                    if !self.resume(|d| d.frames().len() <= depth)? {
+                        break;
+                    }
+                }
+            },
+            "finish" => {
+                let depth = self.frames().len();
+                if depth == 0 {
+                    return Err(String::from("not inside a function"));
+                }
+                self.resume(|d| d.frames().len() < depth)?;
+            },
+            "stepi" | "si" => {
+                for _ in 0..repl::count(args)? {
+                    if self.emulator.is_halted() {
+                        return Err(String::from("the program has halted"));
+                    }
+                    self.emulator.step()?;
+                }
+                self.show_current();
+            },
+            "disassemble" | "di" => self.disassemble(),
+            "registers" | "regs" => {
+                println!("PC = {}  A = {}  D = {}", self.emulator.pc, self.emulator.a, self.emulator.d);
+            },
+            "stack" => self.print_stack()?,
+            "print" | "p" => self.print_segment(args)?,
+            "backtrace" | "bt" | "where" => self.backtrace(),
+            "list" | "l" => self.list(),
+            "help" | "h" => print!("{}", COMMANDS),
+            "quit" | "q" => return Ok(false),
+            _ if repl::memory_command(&mut self.emulator.ram, words)? => {},
+            command => return Err(format!("unknown command '{}'; try 'help'", command)),
+        }
+        Ok(true)
+    }
+
+    // the map entry of the instruction at address, if it is in the ROM
+    fn entry(&self, address: usize) -> Option<&Entry> {
+        self.entry_at.get(address).map(|&i| &self.map[i])
+    }
+
+    // true at the first instruction of a translated VM command
+    fn at_command(&self, address: usize) -> bool {
+        self.entry(address).is_some_and(|e| e.start == address && !e.file.is_empty())
+    }
+
+    fn location(&self, address: usize) -> String {
+        match self.entry(address) {
+            Some(e) if !e.file.is_empty() => format!("{}:{}", e.file, e.line),
+            Some(e) => e.command.clone(),
+            None => String::from("end of program"),
+        }
+    }
+
+    // executes instructions until the start of a VM command for which done returns
+    // true, a breakpoint or the halt loop; the first instruction always runs, so a
+    // resume leaves a breakpoint it stopped at. Returns whether the program can
+    // go on.
+    fn resume(&mut self, done: impl Fn(&Debugger) -> bool) -> Result<bool, String> {
+        if self.emulator.is_halted() {
+            return Err(String::from("the program has halted"));
+        }
+        let start = self.emulator.cycles;
+        loop {
+            self.emulator.step()?;
+            if self.emulator.is_halted() {
+                break;
+            }
+            let pc = self.emulator.pc as usize;
+            if let Some(n) = self.breakpoints.at(pc) {
+                println!("breakpoint {}, {}", n, self.function_name());
+                break;
+            }
+            if self.at_command(pc) && done(self) {
+                break;
+            }
+            if self.emulator.cycles - start == MAX_CYCLES {
+                println!("paused after {} instructions", MAX_CYCLES);
+                break;
+            }
+        }
+        self.show_current();
+        Ok(!self.emulator.is_halted())
+    }
+
+    fn show_current(&self) {
+        if self.emulator.is_halted() {
+            println!("program halted after {} instructions", self.emulator.cycles);
+            return;
+        }
+        let pc = self.emulator.pc as usize;
+        let entry = self.entry(pc).unwrap();
+        if entry.file.is_empty() {
+            println!("{} (ROM[{}]: {})", entry.command, pc, self.instructions[pc]);
+        } else if pc == entry.start {
+            println!("{}: {}", self.location(pc), entry.command);
+        } else {
+            println!("{}: {} (ROM[{}], instruction {} of {}: {})", self.location(pc), entry.command,
+                pc, pc - entry.start + 1, entry.end - entry.start, self.instructions[pc]);
+        }
+    }
+
+    fn function_name(&self) -> &str {
+        match self.entry(self.emulator.pc as usize) {
+            Some(e) if !e.function.is_empty() => &e.function,
+            _ => "(top level)",
+        }
+    }
+
+    // the functions being called, innermost first, from the frames in RAM
+    fn frames(&self) -> Vec<Frame> {
+        self.unwinder.backtrace(&self.emulator.ram, Some(self.emulator.pc as usize))
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let spec = match args {
+            [spec] => *spec,
+            _ => return Err(String::from("usage: break <File.function> | <file>:<line>")),
+        };
+        let commands = self.map.iter().filter(|e| !e.file.is_empty() && e.start < e.end);
+        let address = match repl::parse_file_line(spec) {
+            Some((file, line)) => {
+                let file = format!("{}.vm", file);
+                if !self.map.iter().any(|e| e.file == file) {
+                    return Err(format!("no file '{}' in the program", file));
+                }
+                // the first command on the line or after it that has instructions
+                commands.filter(|e| e.file == file && e.line >= line)
+                    .min_by_key(|e| e.line)
+                    .map(|e| e.start)
+                    .ok_or(format!("{} has no command on line {} or after it", file, line))?
+            },
+            None => {
+                let text = format!("function {} ", spec);
+                match commands.into_iter().find(|e| e.command.starts_with(&text)) {
+                    Some(e) => e.start,
+                    None => return Err(format!("function '{}' is not defined", spec)),
+                }
+            },
+        };
+        let n = self.breakpoints.add(spec, address);
+        println!("breakpoint {} at {} (ROM[{}])", n, self.location(address), address);
+        Ok(())
+    }
+
+    fn disassemble(&self) {
+        let pc = self.emulator.pc as usize;
+        let entry = match self.entry(pc) {
+            Some(entry) => entry,
+            None => return,
+        };
+        println!("{}", entry.command);
+        for address in entry.start..entry.end {
+            let marker = if address == pc { "=>" } else { "  " };
+            println!("{} {:>5}  {}", marker, address, self.instructions[address]);
+        }
+    }
+
+    fn print_stack(&self) -> Result<(), String> {
+        let lcl = self.emulator.ram[1] as u16 as usize;
+        let bottom = match self.frames().first() {
+            Some(frame) => lcl + self.num_locals[&frame.function] as usize,
+            None => self.bottom,
+        };
+        let sp = self.emulator.ram[0] as u16 as usize;
+        if sp < bottom || sp > RAM_SIZE {
+            return Err(format!("SP is {}, below the stack of {} at {}", sp, self.function_name(), bottom));
+        }
+        if sp == bottom {
+            println!("stack is empty");
+        }
+        // top first
+        for address in (bottom..sp).rev() {
+            println!("RAM[{}] = {}", address, self.emulator.ram[address]);
+        }
+        Ok(())
+    }
+
+    fn print_segment(&self, args: &[&str]) -> Result<(), String> {
+        let (segment, index) = match args {
+            [segment] => (*segment, None),
+            [segment, index] => match index.parse::<i16>() {
+                Ok(i) if i >= 0 => (*segment, Some(i)),
+                _ => return Err(format!("'{}' is not a segment index", index)),
+            },
+            _ => return Err(String::from("usage: print <segment> [<index>]")),
+        };
+        let ram = &self.emulator.ram;
+        let function = self.frames().first().map(|frame| frame.function.clone());
+        let file = self.entry(self.emulator.pc as usize).map_or("", |e| e.file.trim_end_matches(".vm"));
+        let indices: Vec<i16> = match (segment, index) {
+            (_, Some(i)) => vec![i],
+            ("local", None) if function.is_some() => (0..self.num_locals[function.as_ref().unwrap()]).collect(),
+            // the arguments lie between ARG and the saved frame below LCL
+            ("argument", None) if function.is_some() => (0..ram[1].wrapping_sub(5).wrapping_sub(ram[2]).max(0)).collect(),
+            ("pointer", None) => (0..2).collect(),
+            ("temp", None) => (0..8).collect(),
+            ("static", None) => {
+                let prefix = format!("{}.", file);
+                let mut indices: Vec<i16> = self.symbols.keys()
+                    .filter_map(|symbol| symbol.strip_prefix(&prefix)?.parse::<i16>().ok())
+                    .collect();
+                indices.sort();
+                indices
+            },
+            _ => return Err(format!("give an index to print from segment '{}'", segment)),
+        };
+        if indices.is_empty() {
+            println!("{} is empty", segment);
+        }
+        for i in indices {
+            let base = |register: usize| ram[register] as u16 as usize;
+            let address = match segment {
+                "local" => base(1) + i as usize,
+                "argument" => base(2) + i as usize,
+                "this" => base(3) + i as usize,
+                "that" => base(4) + i as usize,
+                "pointer" if i < 2 => 3 + i as usize,
+                "temp" if i < 8 => 5 + i as usize,
+                "static" => match self.symbols.get(&format!("{}.{}", file, i)) {
+                    Some(&address) => address as usize,
+                    None => return Err(format!("static {} is not used by {}.vm", i, file)),
+                },
+                "pointer" | "temp" => return Err(format!("index {} is out of range for segment '{}'", i, segment)),
+                _ => return Err(format!("segment '{}' has no address", segment)),
+            };
+            if address >= RAM_SIZE {
+                return Err(format!("{} {} is at address {}, outside the RAM", segment, i, address));
+            }
+            println!("{} {} = {} (RAM[{}])", segment, i, ram[address], address);
+        }
+        Ok(())
+    }
+
+    fn backtrace(&self) {
+        let frames = self.frames();
+        if frames.is_empty() {
+            println!("#0   (top level) at {}", self.location(self.emulator.pc as usize));
+        }
+        for (depth, frame) in frames.iter().enumerate() {
+            let location = frame.address.map_or(String::from("?"), |address| self.location(address));
+            println!("#{:<3} {}({}) at {}", depth, frame.function, backtrace::format_args(&frame.args), location);
+        }
+    }
+
+    fn list(&self) {
+        let commands: Vec<&Entry> = self.map.iter().filter(|e| !e.file.is_empty()).collect();
+        let pc = self.emulator.pc as usize;
+        let current = commands.iter().position(|e| e.start <= pc && pc < e.end)
+            .or_else(|| commands.iter().position(|e| e.start >= pc))
+            .unwrap_or(commands.len());
+        let first = current.saturating_sub(5);
+        let last = (current + 6).min(commands.len());
+        for (i, e) in commands.iter().enumerate().take(last).skip(first) {
+            let marker = if i == current && !self.emulator.is_halted() { "=>" } else { "  " };
+            let stop = if e.start < e.end && self.breakpoints.at(e.start).is_some() { "*" } else { " " };
+            let indent = if e.command.starts_with("function ") { "" } else { "    " };
+            println!("{}{} {:>5}  {:<16} {}{}", marker, stop, e.start, format!("{}:{}", e.file, e.line), indent, e.command);
+        }
+    }
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut translation = program::Options::default();
+    let mut script = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-x" => {
+                match iter.next() {
+                    Some(path) => script = Some(path.clone()),
+                    None => return Err(String::from("option '-x' requires a path")),
+                }
+            },
+            s if translation.parse(s, &mut iter)? => {},
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one input can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    match input {
+        Some(input) => Ok(Some(Options { input, translation, script })),
+        None => Err(String::from("no input given")),
+    }
+}
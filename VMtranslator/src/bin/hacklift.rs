@@ -0,0 +1,170 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use vmtranslator::lifter::{self, Lifted, Region};
+
+const USAGE: &str = "\
+usage: hacklift [options] <filename>.asm
+
+Lifts Hack assembly written by VMtranslator back to VM code: the instructions
+are matched against the templates of the VM commands, and the commands are
+written in the format of '--emit ir', a comment naming each file before its
+commands. The bootstrap code, the halt loop and the extended arithmetic
+routines are noted in comments, and so is code that matches no template, with
+its ROM addresses and instructions. Functions are put in the file their
+statics belong to, or else the file named by their class.
+
+options:
+  -o <path>          write the VM code to <path> instead of standard output
+  --dir <dirname>    write one .vm file per file of the program into
+                     <dirname>, which can be translated again; unrecognized
+                     code is left out
+  --addresses        follow each command with the ROM addresses it came from
+  -h, --help         print this help and exit
+";
+
+struct Options {
+    input: String,
+    output: Option<String>,
+    dir: Option<String>,
+    addresses: bool,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hacklift: {}", e);
+            eprintln!("try 'hacklift --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hacklift: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let source = fs::read_to_string(&options.input)
+        .map_err(|e| format!("cannot read '{}': {}", options.input, e))?;
+    let name = Path::new(&options.input).file_stem().unwrap().to_string_lossy().to_string();
+    let regions = lifter::lift(&source, &name);
+
+    match &options.dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| format!("cannot create directory '{}': {}", dir, e))?;
+            for file in lifter::files(&regions) {
+                let path = Path::new(dir).join(format!("{}.vm", file.name));
+                let text: String = file.commands.iter().map(|cmd| format!("{}\n", cmd)).collect();
+                fs::write(&path, text).map_err(|e| format!("cannot write '{}': {}", path.display(), e))?;
+            }
+        },
+        None => {
+            let text = write_vm(&regions, options.addresses);
+            match &options.output {
+                Some(path) => fs::write(path, text).map_err(|e| format!("cannot write '{}': {}", path, e))?,
+                None => print!("{}", text),
+            }
+        },
+    }
+
+    let unrecognized: Vec<&Region> = regions.iter()
+        .filter(|region| matches!(region.lifted, Lifted::Unrecognized(_)))
+        .collect();
+    if !unrecognized.is_empty() {
+        let instructions: usize = unrecognized.iter().map(|region| region.end - region.start).sum();
+        eprintln!("{} unrecognized region(s), {} instruction(s) in all", unrecognized.len(), instructions);
+    }
+    Ok(())
+}
+
+fn write_vm(regions: &[Region], addresses: bool) -> String {
+    let mut s = String::new();
+    let mut file = "";
+    for region in regions.iter() {
+        match &region.lifted {
+            Lifted::Commands(commands) => {
+                if region.file != file {
+                    file = &region.file;
+                    s.push_str(&format!("// {}.vm\n", file));
+                }
+                for (i, cmd) in commands.iter().enumerate() {
+                    if addresses && i == 0 && region.end > region.start {
+                        s.push_str(&format!("{:<23} // ROM[{}..{}]\n", cmd.to_string(), region.start, region.end - 1));
+                    } else {
+                        s.push_str(&format!("{}\n", cmd));
+                    }
+                }
+            },
+            Lifted::Bootstrap(registers, entry) => {
+                let mut parts: Vec<String> = registers.iter()
+                    .map(|(register, value)| format!("{}={}", register, value))
+                    .collect();
+                if let Some(entry) = entry {
+                    parts.push(format!("call {}", entry));
+                }
+                s.push_str(&format!("// bootstrap: {}\n", parts.join(", ")));
+            },
+            Lifted::Halt => s.push_str("// end of program\n"),
+            Lifted::Routine(routine) => s.push_str(&format!("// runtime {}\n", routine)),
+            Lifted::Unrecognized(instructions) => {
+                s.push_str(&format!("// unrecognized: ROM[{}..{}]\n", region.start, region.end - 1));
+                for instruction in instructions.iter() {
+                    s.push_str(&format!("//   {}\n", instruction));
+                }
+            },
+        }
+    }
+    s
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut dir = None;
+    let mut addresses = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => {
+                match iter.next() {
+                    Some(path) => output = Some(path.clone()),
+                    None => return Err(format!("option '{}' requires a path", arg)),
+                }
+            },
+            "--dir" => {
+                match iter.next() {
+                    Some(path) => dir = Some(path.clone()),
+                    None => return Err(String::from("option '--dir' requires a directory")),
+                }
+            },
+            "--addresses" => addresses = true,
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one program can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    if output.is_some() && dir.is_some() {
+        return Err(String::from("options '-o' and '--dir' cannot be combined"));
+    }
+    match input {
+        Some(input) => Ok(Some(Options { input, output, dir, addresses })),
+        None => Err(String::from("no program given")),
+    }
+}
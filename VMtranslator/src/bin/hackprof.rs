@@ -0,0 +1,158 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use vmtranslator::profiler::Profile;
+use vmtranslator::program::{self, Program};
+
+const USAGE: &str = "\
+usage: hackprof [options] <filename>.vm | <filename>.jack | <dirname>
+
+Translates a VM program to Hack, runs the machine code in an emulator until it
+halts or reaches the cycle limit, and reports where the instructions were
+executed: per function, following the calls through the frames written by
+'call', per VM command and per source line.
+
+options:
+  --report <kind>    flat (per function, the default), tree (the call tree),
+                     lines (per source line), commands (per kind of VM
+                     command) or folded (call stacks for flamegraph.pl,
+                     alone); may be repeated
+  --cycles <n>       stop after <n> instructions (default 100000000)
+  -o <path>          write the reports to <path> instead of standard output
+";
+
+// after the translation options
+const OPTIONS: &str = "  -h, --help         print this help and exit
+";
+
+const DEFAULT_CYCLES: u64 = 100_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Report {
+    Flat,
+    Tree,
+    Lines,
+    Commands,
+    Folded,
+}
+
+struct Options {
+    input: String,
+    translation: program::Options,
+    reports: Vec<Report>,
+    cycles: u64,
+    output: Option<String>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}{}{}", USAGE, program::USAGE, OPTIONS);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hackprof: {}", e);
+            eprintln!("try 'hackprof --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hackprof: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let program = Program::translate(&options.input, &options.translation)?;
+    let mut profile = Profile::new(&program.map, program.rom.len());
+    let mut emulator = program.emulator(&options.translation);
+    while !emulator.is_halted() && emulator.cycles < options.cycles {
+        let pc = emulator.pc as usize;
+        emulator.step()?;
+        profile.record(pc, emulator.pc as usize);
+    }
+    if !emulator.is_halted() {
+        eprintln!("stopped after {} cycles without halting", emulator.cycles);
+    }
+
+    let output = options.reports.iter()
+        .map(|report| match report {
+            Report::Flat => profile.flat(),
+            Report::Tree => profile.tree(),
+            Report::Lines => profile.lines(),
+            Report::Commands => profile.commands(),
+            Report::Folded => profile.folded(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    match &options.output {
+        Some(path) => fs::write(path, &output).map_err(|e| format!("cannot write '{}': {}", path, e)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        },
+    }
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut translation = program::Options::default();
+    let mut reports = vec![];
+    let mut cycles = DEFAULT_CYCLES;
+    let mut output = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--report" => {
+                reports.push(match iter.next().map(|s| s.as_str()) {
+                    Some("flat") => Report::Flat,
+                    Some("tree") => Report::Tree,
+                    Some("lines") => Report::Lines,
+                    Some("commands") => Report::Commands,
+                    Some("folded") => Report::Folded,
+                    Some(kind) => return Err(format!(
+                        "unknown report '{}' (expected flat, tree, lines, commands or folded)", kind)),
+                    None => return Err(String::from("option '--report' requires a kind")),
+                });
+            },
+            "--cycles" => {
+                cycles = match iter.next().map(|v| v.parse::<u64>()) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Err(String::from("option '--cycles' expects a number")),
+                    None => return Err(String::from("option '--cycles' requires a number")),
+                };
+            },
+            "-o" | "--output" => {
+                match iter.next() {
+                    Some(path) => output = Some(path.clone()),
+                    None => return Err(format!("option '{}' requires a path", arg)),
+                }
+            },
+            s if translation.parse(s, &mut iter)? => {},
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one input can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    if reports.is_empty() {
+        reports.push(Report::Flat);
+    }
+    if reports.contains(&Report::Folded) && reports.len() > 1 {
+        return Err(String::from("the folded report cannot be combined with others"));
+    }
+    match input {
+        Some(input) => Ok(Some(Options { input, translation, reports, cycles, output })),
+        None => Err(String::from("no input given")),
+    }
+}
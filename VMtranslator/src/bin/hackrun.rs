@@ -0,0 +1,229 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use vmtranslator::assembler::{self, Cpu};
+use vmtranslator::devices;
+use vmtranslator::emulator::{Emulator, RAM_SIZE};
+
+const USAGE: &str = "\
+usage: hackrun [options] <filename>.hack | <filename>.asm
+
+Runs a Hack program until it reaches the halt loop '(L) @L 0;JMP' or the
+cycle limit, then prints the number of instructions executed to standard error
+and the RAM asked for with --dump to standard output. A keyboard script and
+screen images let graphical programs run and be checked without a display.
+
+options:
+  --cpu <kind>       standard (the default) or extended, which adds the shift
+                     instructions
+  --cycles <n>       stop after <n> instructions (default 100000000)
+  --set <addr>=<v>   set RAM[addr] to v before running; may be repeated
+  --dump <a>..<b>    print RAM[a] through RAM[b] after running; may be repeated
+  --keys <path>      press keys as the keyboard script <path> says: lines
+                     '<cycle> <key>' hold <key> down from <cycle> on, where
+                     <key> is a character, space, newline, backspace, left,
+                     up, right, down, home, end, pageup, pagedown, insert,
+                     delete, esc, f1 to f12, a key code, or none
+  --screen <path>    save the screen after running as a PNG or PBM image,
+                     by the extension of <path>
+  --screen-at <n>=<path>
+                     save the screen after <n> cycles, or when the program
+                     halts before; may be repeated
+  -h, --help         print this help and exit
+";
+
+const DEFAULT_CYCLES: u64 = 100_000_000;
+
+struct Options {
+    input: String,
+    cpu: Cpu,
+    cycles: u64,
+    sets: Vec<(usize, i16)>,
+    dumps: Vec<(usize, usize)>,
+    keys: Option<String>,
+    screens: Vec<(u64, String)>, // the final screen at u64::MAX
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hackrun: {}", e);
+            eprintln!("try 'hackrun --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hackrun: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let text = fs::read_to_string(&options.input)
+        .map_err(|e| format!("cannot read '{}': {}", options.input, e))?;
+    let rom = if Path::new(&options.input).extension().is_some_and(|ext| ext == "asm") {
+        assembler::assemble(&text, options.cpu)?
+    } else {
+        assembler::parse_hack_text(&text)?
+    };
+    if options.cpu == Cpu::Standard && rom.iter().any(|&word| assembler::is_shift(word)) {
+        return Err(String::from("the program uses shift instructions; run it with --cpu extended"));
+    }
+
+    let mut emulator = Emulator::new(rom, options.cpu);
+    for &(address, value) in options.sets.iter() {
+        emulator.ram[address] = value;
+    }
+    let keys = match &options.keys {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?;
+            devices::parse_keys(&text).map_err(|e| format!("{}: {}", path, e))?
+        },
+        None => vec![],
+    };
+    let mut keys = keys.iter().peekable();
+    let mut screens = options.screens.iter().peekable();
+
+    // run from one key press or screen to the next
+    let halted = loop {
+        while let Some((_, key)) = keys.next_if(|(cycle, _)| *cycle <= emulator.cycles) {
+            emulator.set_key(*key);
+        }
+        while let Some((_, path)) = screens.next_if(|(cycle, _)| *cycle <= emulator.cycles) {
+            save_screen(&emulator, path)?;
+        }
+        let next = [keys.peek().map(|k| k.0), screens.peek().map(|s| s.0), Some(options.cycles)]
+            .into_iter().flatten().min().unwrap();
+        if emulator.run(next - emulator.cycles)? {
+            break true;
+        }
+        if emulator.cycles >= options.cycles {
+            break false;
+        }
+    };
+    for (cycle, path) in screens {
+        if halted || *cycle == u64::MAX {
+            save_screen(&emulator, path)?;
+        } else {
+            eprintln!("not saving '{}': the program did not reach cycle {}", path, cycle);
+        }
+    }
+    if halted {
+        eprintln!("halted after {} cycles", emulator.cycles);
+    } else {
+        eprintln!("stopped after {} cycles without halting", emulator.cycles);
+    }
+    for &(first, last) in options.dumps.iter() {
+        for address in first..=last {
+            println!("RAM[{}] = {}", address, emulator.ram[address]);
+        }
+    }
+    Ok(())
+}
+
+fn save_screen(emulator: &Emulator, path: &str) -> Result<(), String> {
+    let image = if path.ends_with(".png") {
+        devices::to_png(&emulator.ram)
+    } else {
+        devices::to_pbm(&emulator.ram)
+    };
+    fs::write(path, image).map_err(|e| format!("cannot write '{}': {}", path, e))
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut cpu = Cpu::Standard;
+    let mut cycles = DEFAULT_CYCLES;
+    let mut sets = vec![];
+    let mut dumps = vec![];
+    let mut keys = None;
+    let mut screens = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--cpu" => match iter.next() {
+                Some(name) => cpu = Cpu::from_name(name)?,
+                None => return Err(String::from("option '--cpu' requires a kind")),
+            },
+            "--cycles" => {
+                cycles = match iter.next().map(|v| v.parse::<u64>()) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Err(String::from("option '--cycles' expects a number")),
+                    None => return Err(String::from("option '--cycles' requires a number")),
+                };
+            },
+            "--set" => {
+                let value = iter.next().ok_or("option '--set' requires <addr>=<value>")?;
+                let (address, v) = value.split_once('=')
+                    .ok_or(format!("option '--set' expects <addr>=<value>, got '{}'", value))?;
+                let v = v.parse::<i16>()
+                    .map_err(|_| format!("option '--set' expects a value in -32768..32767, got '{}'", v))?;
+                sets.push((parse_address(address)?, v));
+            },
+            "--dump" => {
+                let range = iter.next().ok_or("option '--dump' requires <first>..<last>")?;
+                let (first, last) = range.split_once("..")
+                    .ok_or(format!("option '--dump' expects <first>..<last>, got '{}'", range))?;
+                let (first, last) = (parse_address(first)?, parse_address(last)?);
+                if first > last {
+                    return Err(format!("option '--dump' got an empty range '{}'", range));
+                }
+                dumps.push((first, last));
+            },
+            "--keys" => keys = Some(iter.next().ok_or("option '--keys' requires a path")?.clone()),
+            "--screen" => {
+                let path = iter.next().ok_or("option '--screen' requires a path")?;
+                screens.push((u64::MAX, image_path(path)?));
+            },
+            "--screen-at" => {
+                let value = iter.next().ok_or("option '--screen-at' requires <cycle>=<path>")?;
+                let (cycle, path) = value.split_once('=')
+                    .ok_or(format!("option '--screen-at' expects <cycle>=<path>, got '{}'", value))?;
+                let cycle = cycle.parse::<u64>()
+                    .map_err(|_| format!("option '--screen-at' expects a cycle number, got '{}'", cycle))?;
+                screens.push((cycle, image_path(path)?));
+            },
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one program can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    match input {
+        Some(input) => {
+            screens.sort_by_key(|&(cycle, _)| cycle);
+            Ok(Some(Options { input, cpu, cycles, sets, dumps, keys, screens }))
+        },
+        None => Err(String::from("no program given")),
+    }
+}
+
+fn image_path(path: &str) -> Result<String, String> {
+    if path.ends_with(".png") || path.ends_with(".pbm") {
+        Ok(path.to_string())
+    } else {
+        Err(format!("'{}' is neither a .png nor a .pbm path", path))
+    }
+}
+
+fn parse_address(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(n) if n < RAM_SIZE => Ok(n),
+        _ => Err(format!("expected an address in 0..{}, got '{}'", RAM_SIZE - 1, text)),
+    }
+}
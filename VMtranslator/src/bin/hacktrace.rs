@@ -0,0 +1,178 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use vmtranslator::program::{self, Program};
+use vmtranslator::trace::{Format, Level, Tracer};
+
+const USAGE: &str = "\
+usage: hacktrace [options] <filename>.vm | <filename>.jack | <dirname>
+
+Translates a VM program to Hack, runs the machine code in an emulator until it
+halts or reaches the cycle limit, and writes a trace of the execution: one
+record per VM command once it has run, with SP and the values on top of the
+stack, or one record per Hack instruction with PC, A and D.
+
+options:
+  --level <level>    vm (the default) or hack
+  --format <format>  jsonl (a JSON object per line, the default) or csv
+  --function <name>  only trace the commands of this function, or of all the
+                     functions of a class given by its name; may be repeated
+  --stack <n>        stack values per VM record, top first (default 4)
+  --cycles <n>       stop after <n> instructions (default 100000000)
+  -o <path>          write the trace to <path> instead of standard output
+";
+
+// after the translation options
+const OPTIONS: &str = "  -h, --help         print this help and exit
+";
+
+const DEFAULT_CYCLES: u64 = 100_000_000;
+
+struct Options {
+    input: String,
+    translation: program::Options,
+    level: Level,
+    format: Format,
+    functions: Vec<String>,
+    depth: usize,
+    cycles: u64,
+    output: Option<String>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}{}{}", USAGE, program::USAGE, OPTIONS);
+            return;
+        },
+        Err(e) => {
+            eprintln!("hacktrace: {}", e);
+            eprintln!("try 'hacktrace --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("hacktrace: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let program = Program::translate(&options.input, &options.translation)?;
+    let (out, name): (Box<dyn Write>, &str) = match &options.output {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("cannot write '{}': {}", path, e))?;
+            (Box::new(file), path)
+        },
+        None => (Box::new(io::stdout().lock()), "standard output"),
+    };
+
+    let mut tracer = Tracer::new(BufWriter::new(out), options.level, options.format, &program.map, &program.instructions);
+    tracer.set_functions(&options.functions);
+    tracer.set_depth(options.depth);
+    let mut emulator = program.emulator(&options.translation);
+    if let Err(e) = tracer.begin(&emulator) {
+        return write_failed(e, name);
+    }
+    while !emulator.is_halted() && emulator.cycles < options.cycles {
+        let pc = emulator.pc as usize;
+        emulator.step()?;
+        if let Err(e) = tracer.record(&emulator, pc) {
+            return write_failed(e, name);
+        }
+    }
+    if let Err(e) = tracer.into_inner().flush() {
+        return write_failed(e, name);
+    }
+    if !emulator.is_halted() {
+        eprintln!("stopped after {} cycles without halting", emulator.cycles);
+    }
+    Ok(())
+}
+
+// a reader that stops early, like head, ends the trace quietly
+fn write_failed(e: io::Error, name: &str) -> Result<(), String> {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        Ok(())
+    } else {
+        Err(format!("cannot write to {}: {}", name, e))
+    }
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut translation = program::Options::default();
+    let mut level = Level::Vm;
+    let mut format = Format::Jsonl;
+    let mut functions = vec![];
+    let mut depth = 4;
+    let mut cycles = DEFAULT_CYCLES;
+    let mut output = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--level" => {
+                level = match iter.next().map(|s| s.as_str()) {
+                    Some("vm") => Level::Vm,
+                    Some("hack") => Level::Hack,
+                    Some(l) => return Err(format!("unknown level '{}' (expected vm or hack)", l)),
+                    None => return Err(String::from("option '--level' requires a level")),
+                };
+            },
+            "--format" => {
+                format = match iter.next().map(|s| s.as_str()) {
+                    Some("jsonl") => Format::Jsonl,
+                    Some("csv") => Format::Csv,
+                    Some(f) => return Err(format!("unknown format '{}' (expected jsonl or csv)", f)),
+                    None => return Err(String::from("option '--format' requires a format")),
+                };
+            },
+            "--function" => {
+                match iter.next() {
+                    Some(name) => functions.push(name.clone()),
+                    None => return Err(String::from("option '--function' requires a name")),
+                }
+            },
+            "--stack" => {
+                depth = match iter.next().map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Err(String::from("option '--stack' expects a number")),
+                    None => return Err(String::from("option '--stack' requires a number")),
+                };
+            },
+            "--cycles" => {
+                cycles = match iter.next().map(|v| v.parse::<u64>()) {
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return Err(String::from("option '--cycles' expects a number")),
+                    None => return Err(String::from("option '--cycles' requires a number")),
+                };
+            },
+            "-o" | "--output" => {
+                match iter.next() {
+                    Some(path) => output = Some(path.clone()),
+                    None => return Err(format!("option '{}' requires a path", arg)),
+                }
+            },
+            s if translation.parse(s, &mut iter)? => {},
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one input can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    match input {
+        Some(input) => Ok(Some(Options { input, translation, level, format, functions, depth, cycles, output })),
+        None => Err(String::from("no input given")),
+    }
+}
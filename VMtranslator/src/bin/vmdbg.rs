@@ -0,0 +1,368 @@
+use std::env;
+use std::process;
+
+use vmtranslator::emulator::RAM_SIZE;
+use vmtranslator::interpreter::{self, Interpreter};
+use vmtranslator::os;
+use vmtranslator::parser::{self, CommandType};
+use vmtranslator::repl::{self, Breakpoints};
+
+const USAGE: &str = "\
+usage: vmdbg [options] <filename>.vm | <filename>.jack | <dirname>
+
+Runs a VM program in an interpreter under a debugger. Commands are read from
+standard input, or from a script given with -x, one per line.
+
+options:
+  --extensions       accept the extended arithmetic commands mul, div, mod,
+                     shl and shr
+  --entry <function> function called at startup (default Sys.init)
+  --no-bootstrap     start at the first command with SP = 256 instead of
+                     calling the entry function
+  --no-os            do not link the bundled Jack OS classes the program needs
+  --os-class <path>  link <path> instead of the bundled OS class of the same
+                     name; may be repeated
+  --builtins         run the functions of the bundled OS natively instead of
+                     interpreting their code, except Keyboard's and Sys's
+  -x <path>          read debugger commands from <path>
+  -h, --help         print this help and exit
+";
+
+const COMMANDS: &str = "\
+break <File.function> | <file>:<line>
+                 stop before the function or the first command on the line (b)
+delete [<n>]     remove breakpoint <n>, or all breakpoints
+info breakpoints list the breakpoints (info b)
+continue         run until a breakpoint or the end of the program (c, run, r)
+step [<n>]       execute <n> commands, default 1 (s)
+next [<n>]       like step, but run each call to its return (n)
+finish           run until the current function returns
+stack            print the working stack of the current function
+print <segment> [<index>]
+                 print a segment of the current function, or one entry (p)
+backtrace        print the functions being called (bt)
+list             print the commands around the current one (l)
+ram <addr> [<count>]
+                 print RAM from <addr> (x)
+set <addr> <value>
+                 write <value> to RAM[<addr>]
+help             print this list (h)
+quit             leave the debugger (q)
+An empty line repeats the previous command.
+";
+
+// a continue or next runs at most this many commands before giving control back
+const MAX_STEPS: u64 = 100_000_000;
+
+struct Options {
+    input: String,
+    extensions: bool,
+    entry: Option<String>,
+    os: bool,
+    os_classes: Vec<String>,
+    builtins: bool,
+    script: Option<String>,
+}
+
+struct Debugger {
+    vm: Interpreter,
+    breakpoints: Breakpoints, // at command indices
+    bottom: usize, // start of the stack outside any function
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("vmdbg: {}", e);
+            eprintln!("try 'vmdbg --help' for more information");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("vmdbg: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut files = parser::read_program(&options.input, options.extensions)?;
+    let bundled = if options.os {
+        os::link(&mut files, &options.os_classes, options.extensions)?
+    } else {
+        vec![]
+    };
+    let mut vm = Interpreter::new(&files)?;
+    if options.builtins {
+        vm.set_builtins(&bundled);
+    }
+    let entry = options.entry.as_deref().filter(|entry| vm.function(entry).is_some());
+    if let Some(name) = &options.entry {
+        if entry.is_none() && name != "Sys.init" {
+            return Err(format!("function '{}' is not defined", name));
+        }
+    }
+    vm.bootstrap(interpreter::DEFAULT_SP, entry)?;
+    let mut debugger = Debugger {
+        vm,
+        breakpoints: Breakpoints::default(),
+        bottom: interpreter::DEFAULT_SP as usize,
+    };
+
+    debugger.show_current();
+    repl::run("(vmdbg)", options.script.as_deref(), |words| debugger.execute(words))
+}
+
+impl Debugger {
+    // runs one debugger command; returns false to quit
+    fn execute(&mut self, words: &[&str]) -> Result<bool, String> {
+        let args = &words[1..];
+        match words[0] {
+            "break" | "b" => self.add_breakpoint(args)?,
+            "delete" | "d" => self.breakpoints.delete(args)?,
+            "info" if args.first().is_some_and(|a| "breakpoints".starts_with(a)) => {
+                self.breakpoints.list(|pc| self.vm.location(pc));
+            },
+            "continue" | "c" | "run" | "r" => {
+                self.resume(|_| false)?;
+            },
+            "step" | "s" => {
+                for _ in 0..repl::count(args)? {
+                    if !self.resume(|_| true)? {
+                        break;
+                    }
+                }
+            },
+            "next" | "n" => {
+                for _ in 0..repl::count(args)? {
+                    let depth = self.vm.frames.len();
+                    if !self.resume(|vm| vm.frames.len() <= depth)? {
+                        break;
+                    }
+                }
+            },
+            "finish" => {
+                let depth = self.vm.frames.len();
+                if depth == 0 {
+                    return Err(String::from("not inside a function"));
+                }
+                self.resume(|vm| vm.frames.len() < depth)?;
+            },
+            "stack" => self.print_stack()?,
+            "print" | "p" => self.print_segment(args)?,
+            "backtrace" | "bt" | "where" => self.backtrace(),
+            "list" | "l" => self.list(),
+            "help" | "h" => print!("{}", COMMANDS),
+            "quit" | "q" => return Ok(false),
+            _ if repl::memory_command(&mut self.vm.ram, words)? => {},
+            command => return Err(format!("unknown command '{}'; try 'help'", command)),
+        }
+        Ok(true)
+    }
+
+    // executes commands until done returns true, a breakpoint is reached or the
+    // program halts; the first command always runs, so a resume leaves a
+    // breakpoint it stopped at. Returns whether the program can go on.
+    fn resume(&mut self, done: impl Fn(&Interpreter) -> bool) -> Result<bool, String> {
+        if self.vm.is_halted() {
+            return Err(String::from("the program has halted"));
+        }
+        let mut steps = 0;
+        loop {
+            self.vm.step()?;
+            steps += 1;
+            if self.vm.is_halted() {
+                break;
+            }
+            if let Some(n) = self.breakpoints.at(self.vm.pc) {
+                println!("breakpoint {}, {}", n, self.function_name());
+                break;
+            }
+            if done(&self.vm) {
+                break;
+            }
+            if steps == MAX_STEPS {
+                println!("paused after {} commands", steps);
+                break;
+            }
+        }
+        self.show_current();
+        Ok(!self.vm.is_halted())
+    }
+
+    fn show_current(&self) {
+        match self.vm.current() {
+            Some(instruction) => println!("{}: {}", self.vm.location(self.vm.pc), instruction.command),
+            None => println!("program halted after {} commands", self.vm.steps),
+        }
+    }
+
+    fn function_name(&self) -> &str {
+        self.vm.frames.last().map_or("(top level)", |frame| &frame.function)
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let spec = match args {
+            [spec] => *spec,
+            _ => return Err(String::from("usage: break <File.function> | <file>:<line>")),
+        };
+        let pc = match repl::parse_file_line(spec) {
+            Some((file, line)) => {
+                let index = match self.vm.files.iter().position(|f| f == file) {
+                    Some(index) => index,
+                    None => return Err(format!("no file '{}.vm' in the program", file)),
+                };
+                // the first command on the line or after it
+                self.vm.program.iter().enumerate()
+                    .filter(|(_, i)| i.file == index && i.command.line >= line)
+                    .min_by_key(|(_, i)| i.command.line)
+                    .map(|(pc, _)| pc)
+                    .ok_or(format!("{}.vm has no command on line {} or after it", file, line))?
+            },
+            None => match self.vm.function(spec) {
+                Some(pc) => pc,
+                None => return Err(format!("function '{}' is not defined", spec)),
+            },
+        };
+        let n = self.breakpoints.add(spec, pc);
+        println!("breakpoint {} at {}", n, self.vm.location(pc));
+        Ok(())
+    }
+
+    fn print_stack(&self) -> Result<(), String> {
+        let bottom = match self.vm.frames.last() {
+            Some(frame) => self.vm.address("local", 0)? + frame.num_locals as usize,
+            None => self.bottom,
+        };
+        let sp = self.vm.ram[0] as u16 as usize;
+        if sp < bottom || sp > RAM_SIZE {
+            return Err(format!("SP is {}, below the stack of {} at {}", sp, self.function_name(), bottom));
+        }
+        if sp == bottom {
+            println!("stack is empty");
+        }
+        // top first
+        for address in (bottom..sp).rev() {
+            println!("RAM[{}] = {}", address, self.vm.ram[address]);
+        }
+        Ok(())
+    }
+
+    fn print_segment(&self, args: &[&str]) -> Result<(), String> {
+        let (segment, index) = match args {
+            [segment] => (*segment, None),
+            [segment, index] => match index.parse::<i16>() {
+                Ok(i) if i >= 0 => (*segment, Some(i)),
+                _ => return Err(format!("'{}' is not a segment index", index)),
+            },
+            _ => return Err(String::from("usage: print <segment> [<index>]")),
+        };
+        let frame = self.vm.frames.last();
+        let indices: Vec<i16> = match (segment, index) {
+            (_, Some(i)) => vec![i],
+            ("local", None) if frame.is_some() => (0..frame.unwrap().num_locals).collect(),
+            ("argument", None) if frame.is_some() => (0..frame.unwrap().num_args).collect(),
+            ("pointer", None) => (0..2).collect(),
+            ("temp", None) => (0..8).collect(),
+            ("static", None) => {
+                let file = self.vm.program.get(self.vm.pc).map_or(0, |i| i.file);
+                self.vm.statics(file).into_iter().map(|(index, _)| index).collect()
+            },
+            _ => return Err(format!("give an index to print from segment '{}'", segment)),
+        };
+        if indices.is_empty() {
+            println!("{} is empty", segment);
+        }
+        for i in indices {
+            let address = self.vm.address(segment, i)?;
+            println!("{} {} = {} (RAM[{}])", segment, i, self.vm.ram[address], address);
+        }
+        Ok(())
+    }
+
+    fn backtrace(&self) {
+        let mut pc = self.vm.pc;
+        for (depth, frame) in self.vm.frames.iter().rev().enumerate() {
+            println!("#{:<3} {} at {}", depth, frame.function, self.vm.location(pc));
+            match frame.call_site {
+                Some(call) => pc = call,
+                None => return,
+            }
+        }
+        if self.vm.frames.is_empty() {
+            println!("#0   (top level) at {}", self.vm.location(pc));
+        }
+    }
+
+    fn list(&self) {
+        let first = self.vm.pc.saturating_sub(5);
+        let last = (self.vm.pc + 6).min(self.vm.program.len());
+        for pc in first..last {
+            let instruction = &self.vm.program[pc];
+            let marker = if pc == self.vm.pc && !self.vm.is_halted() { "=>" } else { "  " };
+            let stop = if self.breakpoints.at(pc).is_some() { "*" } else { " " };
+            let indent = match instruction.command.command_type {
+                CommandType::Function => "",
+                _ => "    ",
+            };
+            println!("{}{} {:<16} {}{}", marker, stop, self.vm.location(pc), indent, instruction.command);
+        }
+    }
+}
+
+// args excludes the program name; None asks for the help text
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut input = None;
+    let mut extensions = false;
+    let mut entry = Some(String::from("Sys.init"));
+    let mut os = true;
+    let mut os_classes = vec![];
+    let mut builtins = false;
+    let mut script = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--extensions" => extensions = true,
+            "--entry" => {
+                match iter.next() {
+                    Some(name) => entry = Some(name.clone()),
+                    None => return Err(String::from("option '--entry' requires a function name")),
+                }
+            },
+            "--no-bootstrap" => entry = None,
+            "--no-os" => os = false,
+            "--os-class" => {
+                match iter.next() {
+                    Some(path) => os_classes.push(path.clone()),
+                    None => return Err(String::from("option '--os-class' requires a path")),
+                }
+            },
+            "--builtins" => builtins = true,
+            "-x" => {
+                match iter.next() {
+                    Some(path) => script = Some(path.clone()),
+                    None => return Err(String::from("option '-x' requires a path")),
+                }
+            },
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one input can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    match input {
+        Some(input) => Ok(Some(Options { input, extensions, entry, os, os_classes, builtins, script })),
+        None => Err(String::from("no input given")),
+    }
+}
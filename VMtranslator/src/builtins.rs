@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+
+use crate::emulator::{KBD, RAM_SIZE};
+
+// Native implementations of functions of the bundled Jack OS, which the
+// interpreter and the emulator run in place of the VM code when asked to
+// (--builtins), like the built-in OS of the book's VM emulator.
+//
+// A built-in has the RAM effects of the function it replaces: it walks the same
+// heap, draws the same pixels and updates the same statics of its class, so the
+// functions that are not built in, such as Keyboard's, which wait for keys, work
+// alongside. Only classes linked from the bundle get built-ins, as a class of
+// the program's own may lay out its data differently.
+//
+// A built-in gives up where the OS function would fail, on bad arguments or an
+// exhausted heap, and also on an address outside the RAM; RAM is then left as
+// it was and the VM code runs instead, to report the error as the OS does.
+
+// a built-in's code, which gets the arguments and returns the value
+type Run = fn(&mut Machine, &[i16]) -> Option<i16>;
+
+#[derive(Clone, Copy)]
+pub struct Native {
+    pub arity: usize,
+    run: Run,
+}
+
+// the statics the built-ins work on, as (class, index)
+const STATICS: [(&str, i16); 8] = [
+    ("Memory", 1), // freeList
+    ("Output", 0), // screen
+    ("Output", 1), // maps
+    ("Output", 2), // row
+    ("Output", 3), // column
+    ("Output", 4), // digits
+    ("Screen", 0), // screen
+    ("Screen", 2), // color
+];
+const FREE_LIST: usize = 0;
+const OUTPUT_SCREEN: usize = 1;
+const MAPS: usize = 2;
+const ROW: usize = 3;
+const COLUMN: usize = 4;
+const DIGITS: usize = 5;
+const SCREEN: usize = 6;
+const COLOR: usize = 7;
+
+// each built-in with its number of arguments and the classes whose data it
+// works on, which must all be bundled; max and min compare through gt and lt,
+// which look at the sign of the wrapped difference
+const FUNCTIONS: [(&str, usize, &[&str], Run); 37] = [
+    ("Math.abs", 1, &[], |_, a| Some(a[0].wrapping_abs())),
+    ("Math.multiply", 2, &[], |_, a| Some(a[0].wrapping_mul(a[1]))),
+    ("Math.divide", 2, &[], |_, a| divide(a[0], a[1])),
+    ("Math.sqrt", 1, &[], |_, a| sqrt(a[0])),
+    ("Math.max", 2, &[], |_, a| Some(if a[0].wrapping_sub(a[1]) > 0 { a[0] } else { a[1] })),
+    ("Math.min", 2, &[], |_, a| Some(if a[0].wrapping_sub(a[1]) < 0 { a[0] } else { a[1] })),
+    ("Memory.peek", 1, &[], |m, a| m.read(a[0])),
+    ("Memory.poke", 2, &[], |m, a| m.write(a[0], a[1]).map(|_| 0)),
+    ("Memory.alloc", 1, &["Memory"], |m, a| m.alloc(a[0])),
+    ("Memory.deAlloc", 1, &["Memory"], |m, a| m.dealloc(a[0]).map(|_| 0)),
+    ("Array.new", 1, &["Memory"], |m, a| if a[0] > 0 { m.alloc(a[0]) } else { None }),
+    ("Array.dispose", 1, &["Memory"], |m, a| m.dealloc(a[0]).map(|_| 0)),
+    ("String.new", 1, &["Memory"], |m, a| m.new_string(a[0])),
+    ("String.dispose", 1, &["Memory"], |m, a| m.dispose_string(a[0]).map(|_| 0)),
+    ("String.length", 1, &[], |m, a| m.read(a[0].wrapping_add(1))),
+    ("String.charAt", 2, &[], |m, a| m.char_at(a[0], a[1])),
+    ("String.setCharAt", 3, &[], |m, a| m.set_char_at(a[0], a[1], a[2]).map(|_| 0)),
+    ("String.appendChar", 2, &[], |m, a| m.append_char(a[0], a[1]).map(|_| a[0])),
+    ("String.eraseLastChar", 1, &[], |m, a| m.erase_last_char(a[0]).map(|_| 0)),
+    ("String.intValue", 1, &[], |m, a| m.int_value(a[0])),
+    ("String.setInt", 2, &[], |m, a| m.set_int(a[0], a[1]).map(|_| 0)),
+    ("String.newLine", 0, &[], |_, _| Some(128)),
+    ("String.backSpace", 0, &[], |_, _| Some(129)),
+    ("String.doubleQuote", 0, &[], |_, _| Some(34)),
+    ("Output.moveCursor", 2, &["Output"], |m, a| m.move_cursor(a[0], a[1]).map(|_| 0)),
+    ("Output.printChar", 1, &["Output"], |m, a| m.print_char(a[0]).map(|_| 0)),
+    ("Output.printString", 1, &["Output", "String"], |m, a| m.print_string(a[0]).map(|_| 0)),
+    ("Output.printInt", 1, &["Output", "String"], |m, a| m.print_int(a[0]).map(|_| 0)),
+    ("Output.println", 0, &["Output"], |m, _| m.println().map(|_| 0)),
+    ("Output.backSpace", 0, &["Output"], |m, _| m.back_space().map(|_| 0)),
+    ("Screen.clearScreen", 0, &["Screen"], |m, _| m.clear_screen().map(|_| 0)),
+    ("Screen.setColor", 1, &["Screen"], |m, a| m.set(COLOR, a[0]).map(|_| 0)),
+    ("Screen.drawPixel", 2, &["Screen"], |m, a| m.draw_pixel(a[0], a[1]).map(|_| 0)),
+    ("Screen.drawLine", 4, &["Screen"], |m, a| m.draw_line(a[0], a[1], a[2], a[3]).map(|_| 0)),
+    ("Screen.drawRectangle", 4, &["Screen"], |m, a| m.draw_rectangle(a[0], a[1], a[2], a[3]).map(|_| 0)),
+    ("Screen.drawCircle", 3, &["Screen"], |m, a| m.draw_circle(a[0], a[1], a[2]).map(|_| 0)),
+    ("Screen.drawHorizontal", 3, &["Screen"], |m, a| m.draw_horizontal(a[0], a[1], a[2]).map(|_| 0)),
+];
+
+pub struct Builtins {
+    natives: HashMap<&'static str, Native>,
+    statics: [usize; 8], // address of each of STATICS
+}
+
+impl Builtins {
+    // the built-ins of the bundled classes, given the address of static index
+    // of a class; a class whose statics are not all found gets none
+    pub fn new(bundled: &[String], statics: impl Fn(&str, i16) -> Option<usize>) -> Self {
+        let mut addresses = [0; 8];
+        let mut classes: Vec<&str> = bundled.iter().map(|class| class.as_str()).collect();
+        for (i, &(class, index)) in STATICS.iter().enumerate() {
+            match statics(class, index) {
+                Some(address) => addresses[i] = address,
+                None => classes.retain(|c| *c != class),
+            }
+        }
+        let natives = FUNCTIONS.iter()
+            .filter(|(name, _, uses, _)| {
+                let class = name.split('.').next().unwrap_or("");
+                classes.contains(&class) && uses.iter().all(|c| classes.contains(c))
+            })
+            .map(|&(name, arity, _, run)| (name, Native { arity, run }))
+            .collect();
+        Builtins { natives, statics: addresses }
+    }
+
+    pub fn get(&self, function: &str) -> Option<Native> {
+        self.natives.get(function).copied()
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.natives.keys().copied()
+    }
+
+    // runs native on args and returns its value, or None with RAM unchanged
+    // when the VM code has to run instead
+    pub fn call(&self, native: Native, ram: &mut [i16], args: &[i16]) -> Option<i16> {
+        let mut machine = Machine { ram, statics: &self.statics, log: vec![] };
+        let value = (native.run)(&mut machine, args);
+        if value.is_none() {
+            for &(address, old) in machine.log.iter().rev() {
+                machine.ram[address] = old;
+            }
+        }
+        value
+    }
+}
+
+// x / y as Math.divide computes it, which is truncated division except for
+// y = -32768, where it only tells whether x is -32768 as well
+fn divide(x: i16, y: i16) -> Option<i16> {
+    if y == 0 {
+        return None;
+    }
+    if y == i16::MIN {
+        return Some((x == y) as i16);
+    }
+    let q = (x.unsigned_abs() / y.unsigned_abs()) as i16;
+    Some(if (x < 0) == (y < 0) { q } else { q.wrapping_neg() })
+}
+
+// Math.sqrt's search for the bits of the root, squares wrapping as they do there
+fn sqrt(x: i16) -> Option<i16> {
+    if x < 0 {
+        return None;
+    }
+    let mut y: i16 = 0;
+    for j in (0..8).rev() {
+        let t = y + (1 << j);
+        let square = t.wrapping_mul(t);
+        if square <= x && square > 0 {
+            y = t;
+        }
+    }
+    Some(y)
+}
+
+// the RAM as the built-ins see it, logging writes so that they can be undone
+struct Machine<'a> {
+    ram: &'a mut [i16],
+    statics: &'a [usize; 8],
+    log: Vec<(usize, i16)>, // address and old value of each write
+}
+
+impl Machine<'_> {
+    fn read(&self, address: i16) -> Option<i16> {
+        self.ram.get(address as u16 as usize).copied()
+    }
+
+    // the keyboard is read-only, which the hosts enforce differently
+    fn write(&mut self, address: i16, value: i16) -> Option<()> {
+        let address = address as u16 as usize;
+        if address >= RAM_SIZE || address == KBD {
+            return None;
+        }
+        self.log.push((address, self.ram[address]));
+        self.ram[address] = value;
+        Some(())
+    }
+
+    fn get(&self, s: usize) -> i16 {
+        self.ram[self.statics[s]]
+    }
+
+    fn set(&mut self, s: usize, value: i16) -> Option<()> {
+        self.write(self.statics[s] as i16, value)
+    }
+
+    // Memory: blocks of the free list have their size in the first word and the
+    // next block in the second; allocated blocks keep their size in the word
+    // before the object
+
+    fn alloc(&mut self, size: i16) -> Option<i16> {
+        if size < 1 {
+            return None;
+        }
+        let needed = size.wrapping_add(1);
+        let mut previous: i16 = 0;
+        let mut block = self.get(FREE_LIST);
+        // a free list that loops runs forever in the VM code
+        for _ in 0..RAM_SIZE {
+            if block == 0 {
+                return None;
+            }
+            let length = self.read(block)?;
+            if length > needed.wrapping_add(1) {
+                self.write(block, length.wrapping_sub(needed))?;
+                let block = block.wrapping_add(length.wrapping_sub(needed));
+                self.write(block, needed)?;
+                return Some(block.wrapping_add(1));
+            }
+            let next = self.read(block.wrapping_add(1))?;
+            if length >= needed {
+                if previous == 0 {
+                    self.set(FREE_LIST, next)?;
+                } else {
+                    self.write(previous.wrapping_add(1), next)?;
+                }
+                return Some(block.wrapping_add(1));
+            }
+            previous = block;
+            block = next;
+        }
+        None
+    }
+
+    fn dealloc(&mut self, object: i16) -> Option<()> {
+        let block = object.wrapping_sub(1);
+        let mut previous: i16 = 0;
+        let mut next = self.get(FREE_LIST);
+        let mut steps = 0;
+        while next != 0 && next < block {
+            steps += 1;
+            if steps > RAM_SIZE {
+                return None;
+            }
+            previous = next;
+            next = self.read(next.wrapping_add(1))?;
+        }
+        let length = self.read(block)?;
+        if block.wrapping_add(length) == next {
+            let merged = length.wrapping_add(self.read(next)?);
+            self.write(block, merged)?;
+            self.write(block.wrapping_add(1), self.read(next.wrapping_add(1))?)?;
+        } else {
+            self.write(block.wrapping_add(1), next)?;
+        }
+        if previous == 0 {
+            return self.set(FREE_LIST, block);
+        }
+        let length = self.read(previous)?;
+        if previous.wrapping_add(length) == block {
+            self.write(previous, length.wrapping_add(self.read(block)?))?;
+            self.write(previous.wrapping_add(1), self.read(block.wrapping_add(1))?)
+        } else {
+            self.write(previous.wrapping_add(1), block)
+        }
+    }
+
+    // String: an object of three fields, the array of characters, the length and
+    // the capacity
+
+    fn new_string(&mut self, capacity: i16) -> Option<i16> {
+        if capacity < 0 {
+            return None;
+        }
+        let this = self.alloc(3)?;
+        if capacity > 0 {
+            let chars = self.alloc(capacity)?;
+            self.write(this, chars)?;
+        }
+        self.write(this.wrapping_add(2), capacity)?;
+        self.write(this.wrapping_add(1), 0)?;
+        Some(this)
+    }
+
+    fn dispose_string(&mut self, this: i16) -> Option<()> {
+        if self.read(this.wrapping_add(2))? > 0 {
+            self.dealloc(self.read(this)?)?;
+        }
+        self.dealloc(this)
+    }
+
+    // the address of character j, which must be within the length
+    fn char_address(&self, this: i16, j: i16) -> Option<i16> {
+        let length = self.read(this.wrapping_add(1))?;
+        if j < 0 || j >= length {
+            return None;
+        }
+        Some(self.read(this)?.wrapping_add(j))
+    }
+
+    fn char_at(&self, this: i16, j: i16) -> Option<i16> {
+        self.read(self.char_address(this, j)?)
+    }
+
+    fn set_char_at(&mut self, this: i16, j: i16, c: i16) -> Option<()> {
+        self.write(self.char_address(this, j)?, c)
+    }
+
+    fn append_char(&mut self, this: i16, c: i16) -> Option<()> {
+        let length = self.read(this.wrapping_add(1))?;
+        if length == self.read(this.wrapping_add(2))? {
+            return None;
+        }
+        self.write(self.read(this)?.wrapping_add(length), c)?;
+        self.write(this.wrapping_add(1), length.wrapping_add(1))
+    }
+
+    fn erase_last_char(&mut self, this: i16) -> Option<()> {
+        let length = self.read(this.wrapping_add(1))?;
+        if length == 0 {
+            return None;
+        }
+        self.write(this.wrapping_add(1), length - 1)
+    }
+
+    // the digits up to the first other character, after an optional '-'
+    fn int_value(&self, this: i16) -> Option<i16> {
+        let length = self.read(this.wrapping_add(1))?;
+        let chars = self.read(this)?;
+        let mut i = 0;
+        let negative = length > 0 && self.read(chars)? == b'-' as i16;
+        if negative {
+            i = 1;
+        }
+        let mut value: i16 = 0;
+        while i < length {
+            let digit = self.read(chars.wrapping_add(i))?.wrapping_sub(b'0' as i16);
+            if !(0..=9).contains(&digit) {
+                break;
+            }
+            value = value.wrapping_mul(10).wrapping_add(digit);
+            i += 1;
+        }
+        Some(if negative { value.wrapping_neg() } else { value })
+    }
+
+    fn set_int(&mut self, this: i16, number: i16) -> Option<()> {
+        self.write(this.wrapping_add(1), 0)?;
+        for c in number.to_string().bytes() {
+            self.append_char(this, c as i16)?;
+        }
+        Some(())
+    }
+
+    // Output: 23 rows of 64 characters in cells of 8 x 11 pixels, two to a
+    // screen word; maps holds the 7 rows of pixels of each character's glyph,
+    // drawn from the third row of the cell
+
+    fn move_cursor(&mut self, i: i16, j: i16) -> Option<()> {
+        if !(0..=22).contains(&i) || !(0..=63).contains(&j) {
+            return None;
+        }
+        self.set(ROW, i)?;
+        self.set(COLUMN, j)
+    }
+
+    fn draw_char(&mut self, c: i16) -> Option<()> {
+        let c = if (32..=126).contains(&c) { c } else { 0 };
+        let map = self.read(self.get(MAPS).wrapping_add(c))?;
+        let (row, column) = (self.get(ROW), self.get(COLUMN));
+        let mut address = self.get(OUTPUT_SCREEN)
+            .wrapping_add(row.wrapping_mul(352))
+            .wrapping_add(column / 2);
+        // the other character of the word stays
+        let mask: i16 = if column & 1 == 0 { -256 } else { 255 };
+        for i in 0..11 {
+            let mut bits = 0;
+            if (2..9).contains(&i) {
+                bits = self.read(map.wrapping_add(i - 2))?;
+                if mask == 255 {
+                    bits = bits.wrapping_mul(256);
+                }
+            }
+            self.write(address, (self.read(address)? & mask) | bits)?;
+            address = address.wrapping_add(32);
+        }
+        Some(())
+    }
+
+    fn print_char(&mut self, c: i16) -> Option<()> {
+        match c {
+            128 => self.println(),
+            129 => self.back_space(),
+            _ => {
+                self.draw_char(c)?;
+                let column = self.get(COLUMN).wrapping_add(1);
+                self.set(COLUMN, column)?;
+                if column == 64 {
+                    self.println()?;
+                }
+                Some(())
+            },
+        }
+    }
+
+    fn print_string(&mut self, s: i16) -> Option<()> {
+        let length = self.read(s.wrapping_add(1))?;
+        for i in 0..length.max(0) {
+            self.print_char(self.char_at(s, i)?)?;
+        }
+        Some(())
+    }
+
+    // through digits, which is left holding the number as the OS leaves it
+    fn print_int(&mut self, number: i16) -> Option<()> {
+        let digits = self.get(DIGITS);
+        self.set_int(digits, number)?;
+        self.print_string(digits)
+    }
+
+    fn println(&mut self) -> Option<()> {
+        self.set(COLUMN, 0)?;
+        let row = self.get(ROW).wrapping_add(1);
+        self.set(ROW, if row == 23 { 0 } else { row })
+    }
+
+    fn back_space(&mut self) -> Option<()> {
+        let (row, column) = (self.get(ROW), self.get(COLUMN));
+        if column == 0 {
+            if row > 0 {
+                self.set(ROW, row - 1)?;
+                self.set(COLUMN, 63)?;
+            }
+        } else {
+            self.set(COLUMN, column.wrapping_sub(1))?;
+        }
+        self.draw_char(b' ' as i16)
+    }
+
+    // Screen: pixels are set in the current color, black unless color is false
+
+    fn clear_screen(&mut self) -> Option<()> {
+        let screen = self.get(SCREEN);
+        for i in 0..8192 {
+            self.write(screen.wrapping_add(i), 0)?;
+        }
+        Some(())
+    }
+
+    // sets or clears the bits of mask in the word at offset from the screen
+    fn paint(&mut self, offset: i16, mask: i16) -> Option<()> {
+        let address = self.get(SCREEN).wrapping_add(offset);
+        let word = self.read(address)?;
+        if self.get(COLOR) != 0 {
+            self.write(address, word | mask)
+        } else {
+            self.write(address, word & !mask)
+        }
+    }
+
+    fn draw_pixel(&mut self, x: i16, y: i16) -> Option<()> {
+        check(x, y)?;
+        self.paint(y * 32 + x / 16, 1i16.wrapping_shl((x & 15) as u32))
+    }
+
+    fn draw_line(&mut self, x1: i16, y1: i16, x2: i16, y2: i16) -> Option<()> {
+        check(x1, y1)?;
+        check(x2, y2)?;
+        if y1 == y2 {
+            return self.draw_horizontal(x1.min(x2), x1.max(x2), y1);
+        }
+        let (dx, dy) = ((x2 - x1).abs(), (y2 - y1).abs());
+        let step_x = if x2 < x1 { -1 } else { 1 };
+        let step_y = if y2 < y1 { -1 } else { 1 };
+        let (mut x, mut y) = (x1, y1);
+        // a and b count the steps taken along x and y; diff is a * dy - b * dx
+        let (mut a, mut b, mut diff) = (0, 0, 0i16);
+        while a <= dx && b <= dy {
+            self.draw_pixel(x, y)?;
+            if diff < 0 {
+                a += 1;
+                x += step_x;
+                diff = diff.wrapping_add(dy);
+            } else {
+                b += 1;
+                y += step_y;
+                diff = diff.wrapping_sub(dx);
+            }
+        }
+        Some(())
+    }
+
+    // pixels x1 to x2 of row y, a word at a time
+    fn draw_horizontal(&mut self, x1: i16, x2: i16, y: i16) -> Option<()> {
+        check(x1, y)?;
+        check(x2, y)?;
+        let mut offset = y * 32 + x1 / 16;
+        let last = y * 32 + x2 / 16;
+        let left = !(1i16.wrapping_shl((x1 & 15) as u32).wrapping_sub(1));
+        let right = 1i16.wrapping_shl((x2 & 15) as u32).wrapping_mul(2).wrapping_sub(1);
+        if offset == last {
+            return self.paint(offset, left & right);
+        }
+        self.paint(offset, left)?;
+        offset += 1;
+        while offset < last {
+            self.paint(offset, -1)?;
+            offset += 1;
+        }
+        self.paint(last, right)
+    }
+
+    fn draw_rectangle(&mut self, x1: i16, y1: i16, x2: i16, y2: i16) -> Option<()> {
+        check(x1, y1)?;
+        check(x2, y2)?;
+        if x1 > x2 || y1 > y2 {
+            return None;
+        }
+        for y in y1..=y2 {
+            self.draw_horizontal(x1, x2, y)?;
+        }
+        Some(())
+    }
+
+    fn draw_circle(&mut self, x: i16, y: i16, r: i16) -> Option<()> {
+        check(x, y)?;
+        if !(0..=181).contains(&r) {
+            return None;
+        }
+        for dy in -r..=r {
+            let half = sqrt(r * r - dy * dy)?;
+            if (0..=255).contains(&(y + dy)) {
+                self.draw_horizontal((x - half).max(0), (x + half).min(511), y + dy)?;
+            }
+        }
+        Some(())
+    }
+}
+
+// whether (x, y) is on the screen
+fn check(x: i16, y: i16) -> Option<()> {
+    ((0..=511).contains(&x) && (0..=255).contains(&y)).then_some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Interpreter, DEFAULT_SP};
+    use crate::os;
+    use crate::parser;
+
+    const VALUES: [i16; 12] = [0, 1, -1, 2, -3, 181, 255, -256, 20000, -20000, i16::MAX, i16::MIN];
+    const RESULTS: usize = 16384; // one word per call, in the screen
+
+    // push x: constants are non-negative, so negative ones are 0 - |x|
+    fn push(x: i16) -> String {
+        match x {
+            i16::MIN => String::from("push constant 0\npush constant 32767\nsub\npush constant 1\nsub\n"),
+            x if x < 0 => format!("push constant 0\npush constant {}\nsub\n", -x),
+            x => format!("push constant {}\n", x),
+        }
+    }
+
+    // runs Main.main with the bundled OS, natively or not, and returns the
+    // RAM from RESULTS on
+    fn run(main: &str, builtins: bool) -> Vec<i16> {
+        let mut files = vec![parser::parse_source("Main", main, false).unwrap()];
+        let bundled = os::link(&mut files, &[], false).unwrap();
+        let mut vm = Interpreter::new(&files).unwrap();
+        if builtins {
+            vm.set_builtins(&bundled);
+        }
+        vm.bootstrap(DEFAULT_SP, Some("Sys.init")).unwrap();
+        while !vm.is_halted() {
+            vm.step().unwrap();
+        }
+        vm.ram[RESULTS..].to_vec()
+    }
+
+    // the built-ins of Math give what its VM code gives for every pair of
+    // edge values the VM code does not reject
+    #[test]
+    fn math_matches_the_vm_code() {
+        let mut main = String::from("function Main.main 0\n");
+        let mut address = RESULTS;
+        let mut call = |function: &str, args: &[i16]| {
+            main.push_str(&format!("push constant {}\npop pointer 1\n", address));
+            for &arg in args.iter() {
+                main.push_str(&push(arg));
+            }
+            main.push_str(&format!("call {} {}\npop that 0\n", function, args.len()));
+            address += 1;
+        };
+        for x in VALUES {
+            call("Math.abs", &[x]);
+            if x >= 0 {
+                call("Math.sqrt", &[x]);
+            }
+            for y in VALUES {
+                call("Math.max", &[x, y]);
+                call("Math.min", &[x, y]);
+                call("Math.multiply", &[x, y]);
+                if y != 0 {
+                    call("Math.divide", &[x, y]);
+                }
+            }
+        }
+        main.push_str("push constant 0\nreturn\n");
+
+        let emulated = run(&main, false);
+        let native = run(&main, true);
+        for i in 0..address - RESULTS {
+            assert_eq!(native[i], emulated[i], "call {}", i);
+        }
+    }
+}
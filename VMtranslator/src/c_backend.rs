@@ -0,0 +1,206 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::backend::{mangle, Backend, Location, Names};
+use crate::code_writer::Bootstrap;
+
+// C backend: translates a whole VM program into a single C file (`--emit c`).
+//
+// The Hack RAM is `int16_t ram[32768]` with SP, LCL, ARG, THIS, THAT in ram[0..4],
+// and every VM function is a labeled block of main(). call and return build and
+// tear down exactly the frame of CodeWriter::write_call/write_return; the saved
+// return address is an index resolved by the rt_return switch instead of a ROM
+// address, and one the switch does not know is an error.
+//
+// The program halts at `label L; goto L` or by falling off the end, and then
+// prints RAM[first..first+count] (arguments, default 0 16).
+pub struct CWriter<W: Write> {
+    writer: BufWriter<W>,
+    names: Names,
+}
+
+const PRELUDE: &str = "\
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#pragma GCC diagnostic ignored \"-Wunused-label\"
+
+static int16_t ram[32768];
+
+#define SP   ram[0]
+#define LCL  ram[1]
+#define ARG  ram[2]
+#define THIS ram[3]
+#define THAT ram[4]
+#define AT(a) ram[(uint16_t)(a) & 0x7fff]
+#define PUSH(v) (AT(SP) = (int16_t)(v), SP++)
+#define POP() (SP--, AT(SP))
+#define TOP AT(SP - 1)
+
+int main(int argc, char **argv) {
+    int16_t x, y;
+    uint16_t frame, ret = 0;
+    (void)x; (void)y; (void)frame;
+";
+
+impl<W: Write> CWriter<W> {
+    pub fn new(out: W) -> Self {
+        let mut w = CWriter {
+            writer: BufWriter::new(out),
+            names: Names::default(),
+        };
+        write!(w.writer, "{}", PRELUDE).unwrap();
+        w
+    }
+
+    fn write_init(&mut self, bootstrap: &Bootstrap) {
+        let registers = [
+            ("SP", Some(bootstrap.sp)),
+            ("LCL", bootstrap.lcl),
+            ("ARG", bootstrap.arg),
+            ("THIS", bootstrap.this),
+            ("THAT", bootstrap.that),
+        ];
+        for (register, value) in registers.iter() {
+            if let Some(value) = value {
+                writeln!(self.writer, "    {} = {};", register, value).unwrap();
+            }
+        }
+        if let Some(entry) = &bootstrap.entry {
+            self.write_call(entry.clone(), 0);
+        }
+    }
+
+    // C expression for the RAM address of segment[index]
+    fn segment_address(&mut self, segment: &str, index: i16) -> String {
+        match self.names.locate(segment, index) {
+            Location::Based(base, index) => format!("{} + {}", ["SP", "LCL", "ARG", "THIS", "THAT"][base], index),
+            Location::Fixed(address) => address.to_string(),
+        }
+    }
+}
+
+impl<W: Write> Backend for CWriter<W> {
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>) {
+        if let Some(bootstrap) = bootstrap {
+            self.write_init(bootstrap);
+        }
+    }
+
+    fn begin_file(&mut self, name: &str) {
+        self.names.begin_file(name);
+    }
+
+    fn write_arithmetic(&mut self, command: String) {
+        self.names.command();
+        let statement = match command.as_str() {
+            "add" => "y = POP(); TOP = (int16_t)(TOP + y);",
+            "sub" => "y = POP(); TOP = (int16_t)(TOP - y);",
+            "and" => "y = POP(); TOP = TOP & y;",
+            "or" => "y = POP(); TOP = TOP | y;",
+            "neg" => "TOP = (int16_t)-TOP;",
+            "not" => "TOP = ~TOP;",
+            "eq" => "y = POP(); x = (int16_t)(TOP - y); TOP = x == 0 ? -1 : 0;",
+            "gt" => "y = POP(); x = (int16_t)(TOP - y); TOP = x > 0 ? -1 : 0;",
+            "lt" => "y = POP(); x = (int16_t)(TOP - y); TOP = x < 0 ? -1 : 0;",
+            _ => panic!("invalid arithmetic command"),
+        };
+        writeln!(self.writer, "    {}", statement).unwrap();
+    }
+
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
+        self.names.command();
+        match command.as_str() {
+            "push" => {
+                let value = if segment == "constant" {
+                    index.to_string()
+                } else {
+                    format!("AT({})", self.segment_address(&segment, index))
+                };
+                writeln!(self.writer, "    PUSH({});", value).unwrap();
+            },
+            "pop" => {
+                let address = self.segment_address(&segment, index);
+                // the address is computed before SP changes
+                writeln!(self.writer, "    x = (int16_t)({}); AT(x) = POP();", address).unwrap();
+            },
+            _ => {
+                panic!("invalid command");
+            },
+        }
+    }
+
+    fn write_label(&mut self, label: String) {
+        let name = self.names.label(label);
+        writeln!(self.writer, "{}:;", mangle(&name)).unwrap();
+    }
+
+    fn write_goto(&mut self, label: String) {
+        match self.names.goto(&label) {
+            Some(name) => writeln!(self.writer, "    goto {};", mangle(&name)).unwrap(),
+            None => writeln!(self.writer, "    goto rt_halt;").unwrap(),
+        }
+    }
+
+    fn write_if(&mut self, label: String) {
+        self.names.command();
+        let name = self.names.scoped(&label);
+        writeln!(self.writer, "    if (POP() != 0) goto {};", mangle(&name)).unwrap();
+    }
+
+    fn write_call(&mut self, function_name: String, num_args: i16) {
+        self.names.command();
+        writeln!(self.writer, "    PUSH({});", self.names.call_count).unwrap(); // return-address
+        writeln!(self.writer, "    PUSH(LCL); PUSH(ARG); PUSH(THIS); PUSH(THAT);").unwrap();
+        writeln!(self.writer, "    ARG = (int16_t)(SP - {} - 5);", num_args).unwrap();
+        writeln!(self.writer, "    LCL = SP;").unwrap();
+        writeln!(self.writer, "    goto {};", mangle(&function_name)).unwrap();
+        writeln!(self.writer, "rt_ret_{}:;", self.names.call_count).unwrap();
+        self.names.call_count += 1;
+    }
+
+    fn write_return(&mut self) {
+        self.names.command();
+        writeln!(self.writer, "    frame = LCL;").unwrap();
+        writeln!(self.writer, "    ret = AT(frame - 5);").unwrap();
+        writeln!(self.writer, "    AT(ARG) = POP();").unwrap();
+        writeln!(self.writer, "    SP = (int16_t)(ARG + 1);").unwrap();
+        writeln!(self.writer, "    THAT = AT(frame - 1);").unwrap();
+        writeln!(self.writer, "    THIS = AT(frame - 2);").unwrap();
+        writeln!(self.writer, "    ARG = AT(frame - 3);").unwrap();
+        writeln!(self.writer, "    LCL = AT(frame - 4);").unwrap();
+        writeln!(self.writer, "    goto rt_return;").unwrap();
+    }
+
+    fn write_function(&mut self, function_name: String, num_locals: i16) {
+        writeln!(self.writer, "\n    /* function {} */", function_name).unwrap();
+        writeln!(self.writer, "{}:;", mangle(&function_name)).unwrap();
+        self.names.begin_function(function_name);
+        if num_locals > 0 {
+            writeln!(self.writer, "    for (x = 0; x < {}; x++) PUSH(0);", num_locals).unwrap();
+        }
+    }
+
+    fn end_program(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "    goto rt_halt;")?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "rt_return:")?;
+        writeln!(self.writer, "    switch (ret) {{")?;
+        for i in 0..self.names.call_count {
+            writeln!(self.writer, "    case {}: goto rt_ret_{};", i, i)?;
+        }
+        writeln!(self.writer, "    default: fprintf(stderr, \"invalid return address %u\\n\", ret); return 1;")?;
+        writeln!(self.writer, "    }}")?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "rt_halt:;")?;
+        writeln!(self.writer, "    int first = argc > 1 ? atoi(argv[1]) : 0;")?;
+        writeln!(self.writer, "    int count = argc > 2 ? atoi(argv[2]) : 16;")?;
+        writeln!(self.writer, "    for (int i = 0; i < count; i++) {{")?;
+        writeln!(self.writer, "        int a = (first + i) & 0x7fff;")?;
+        writeln!(self.writer, "        printf(\"RAM[%d] = %d\\n\", a, ram[a]);")?;
+        writeln!(self.writer, "    }}")?;
+        writeln!(self.writer, "    return 0;")?;
+        writeln!(self.writer, "}}")?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,221 @@
+use vmtranslator::assembler::Cpu;
+use vmtranslator::code_writer::{Bootstrap, BootstrapMode};
+use vmtranslator::inliner;
+
+pub const USAGE: &str = "\
+usage: VMtranslator [options] <filename>.vm | <filename>.jack | <dirname> | -
+
+Translates VM code to Hack assembly. A directory is translated as one program
+made of all its .vm and .jack files, the Jack classes compiled on the way, and
+a class's .jack file taken over its .vm file; '-' reads VM code from standard
+input.
+
+options:
+  -o <path>          write the output to <path> ('-' for standard output);
+                     defaults to <dir>/<dir>.<ext> or <file>.<ext>, or to
+                     standard output when reading standard input
+  --emit <kind>      output kind: asm (Hack assembly, the default), hack
+                     (Hack machine code), x86 (x86-64 GNU assembly, build
+                     with 'gcc out.s'), c (a single C file), wat
+                     (WebAssembly text), riscv (RV32I assembly for Linux),
+                     ir (normalized VM code) or json
+  --bootstrap        always emit the bootstrap code
+  --no-bootstrap     never emit the bootstrap code; by default it is emitted
+                     only when the program defines the entry function
+  --entry <function> function called by the bootstrap code (default Sys.init)
+  --sp <n>           initial SP set by the bootstrap code (default 256)
+  --lcl <n>, --arg <n>, --this <n>, --that <n>
+                     also initialize LCL, ARG, THIS or THAT
+  --extensions       accept the extended arithmetic commands mul, div, mod,
+                     shl and shr (asm, hack, ir and json output only)
+  --cpu <kind>       target CPU for asm and hack output: standard (the
+                     default) or extended, whose shift instructions A<<, D<<,
+                     M<<, A>>, D>> and M>> speed up mul and shifts
+  --no-os            do not link the bundled Jack OS; by default its classes
+                     are added when the program calls them without defining
+                     them, and Sys when it defines Main.main but not Sys.init
+  --os-class <path>  link the .vm file <path> instead of the bundled OS class
+                     of the same name; may be repeated, and is an error
+                     when the program does not need that class
+  --optimize         apply VM-level peephole optimizations
+  --inline           replace calls to small leaf functions with their body
+  --inline-threshold <n>
+                     inline functions of at most <n> VM commands (default
+                     10); implies --inline
+  --tail-calls       translate 'call f n' directly followed by 'return' into
+                     a jump that reuses the caller's frame
+  --annotate         precede each translated command with a comment naming
+                     its file, line and VM command
+  --annotate-instructions
+                     like --annotate, and also explain every instruction
+  --source-map       also write <output>.map, a JSON file mapping ROM address
+                     ranges to VM file, function, line and command
+  --stats            print instruction counts per function, file and VM
+                     command kind to standard error
+  -h, --help         print this help and exit
+  -V, --version      print the version and exit
+";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Emit {
+    Asm,
+    Hack,
+    X86,
+    C,
+    Wat,
+    Riscv,
+    Ir,
+    Json,
+}
+
+impl Emit {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Emit::Asm => "asm",
+            Emit::Hack => "hack",
+            Emit::X86 => "s",
+            Emit::C => "c",
+            Emit::Wat => "wat",
+            Emit::Riscv => "rv32.s",
+            Emit::Ir => "ir",
+            Emit::Json => "json",
+        }
+    }
+}
+
+pub struct Options {
+    pub input: String,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub bootstrap_mode: BootstrapMode,
+    pub bootstrap: Bootstrap,
+    pub extensions: bool,
+    pub cpu: Cpu,
+    pub os: bool,
+    pub os_classes: Vec<String>, // replacements for bundled OS classes
+    pub optimize: bool,
+    pub inline: Option<usize>, // size threshold, when inlining
+    pub tail_calls: bool,
+    pub annotate: bool,
+    pub annotate_instructions: bool,
+    pub source_map: bool,
+    pub stats: bool,
+}
+
+pub enum Action {
+    Translate(Options),
+    Help,
+    Version,
+}
+
+// args excludes the program name
+pub fn parse_args(args: &[String]) -> Result<Action, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut emit = Emit::Asm;
+    let mut bootstrap_mode = BootstrapMode::Auto;
+    let mut bootstrap = Bootstrap::default();
+    let mut extensions = false;
+    let mut cpu = Cpu::Standard;
+    let mut os = true;
+    let mut os_classes = vec![];
+    let mut optimize = false;
+    let mut inline = None;
+    let mut tail_calls = false;
+    let mut annotate = false;
+    let mut annotate_instructions = false;
+    let mut source_map = false;
+    let mut stats = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "-o" | "--output" => {
+                match iter.next() {
+                    Some(path) => output = Some(path.clone()),
+                    None => return Err(format!("option '{}' requires a path", arg)),
+                }
+            },
+            "--emit" => {
+                emit = match iter.next().map(|s| s.as_str()) {
+                    Some("asm") => Emit::Asm,
+                    Some("hack") => Emit::Hack,
+                    Some("x86") => Emit::X86,
+                    Some("c") => Emit::C,
+                    Some("wat") => Emit::Wat,
+                    Some("riscv") => Emit::Riscv,
+                    Some("ir") => Emit::Ir,
+                    Some("json") => Emit::Json,
+                    Some(kind) => return Err(format!("unknown emit kind '{}' (expected asm, hack, x86, c, wat, riscv, ir or json)", kind)),
+                    None => return Err(String::from("option '--emit' requires a kind")),
+                };
+            },
+            "--bootstrap" => bootstrap_mode = BootstrapMode::Always,
+            "--no-bootstrap" => bootstrap_mode = BootstrapMode::Never,
+            "--extensions" => extensions = true,
+            "--cpu" => match iter.next() {
+                Some(name) => cpu = Cpu::from_name(name)?,
+                None => return Err(String::from("option '--cpu' requires a kind")),
+            },
+            "--no-os" => os = false,
+            "--os-class" => {
+                match iter.next() {
+                    Some(path) => os_classes.push(path.clone()),
+                    None => return Err(String::from("option '--os-class' requires a path")),
+                }
+            },
+            "--optimize" => optimize = true,
+            "--inline" => inline = inline.or(Some(inliner::DEFAULT_THRESHOLD)),
+            "--inline-threshold" => {
+                match iter.next().map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) => inline = Some(n),
+                    Some(Err(_)) => return Err(String::from("option '--inline-threshold' expects a number of commands")),
+                    None => return Err(String::from("option '--inline-threshold' requires a number")),
+                }
+            },
+            "--tail-calls" => tail_calls = true,
+            "--annotate" => annotate = true,
+            "--source-map" => source_map = true,
+            "--stats" => stats = true,
+            "--annotate-instructions" => {
+                annotate = true;
+                annotate_instructions = true;
+            },
+            s if bootstrap.parse(s, &mut iter)? => {},
+            s if s.starts_with('-') && s != "-" => {
+                return Err(format!("unknown option '{}'", s));
+            },
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument '{}': only one input can be given", arg));
+                }
+                input = Some(arg.clone());
+            },
+        }
+    }
+
+    match input {
+        Some(input) => Ok(Action::Translate(Options {
+            input,
+            output,
+            emit,
+            bootstrap_mode,
+            bootstrap,
+            extensions,
+            cpu,
+            os,
+            os_classes,
+            optimize,
+            inline,
+            tail_calls,
+            annotate,
+            annotate_instructions,
+            source_map,
+            stats,
+        })),
+        None => Err(String::from("no input given")),
+    }
+}
+
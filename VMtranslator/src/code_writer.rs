@@ -1,39 +1,362 @@
-use std::io::{BufWriter, Write};
-use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
-pub struct CodeWriter {
-    writer: BufWriter<File>,
+use crate::assembler::Cpu;
+use crate::backend::Backend;
+use crate::extensions;
+use crate::parser::{Command, CommandType, VmFile};
+use crate::source_map;
+
+// initial register values and entry point of the bootstrap code
+#[derive(Clone)]
+pub struct Bootstrap {
+    pub sp: i16,
+    pub lcl: Option<i16>,
+    pub arg: Option<i16>,
+    pub this: Option<i16>,
+    pub that: Option<i16>,
+    pub entry: Option<String>, // function to call, if any
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Bootstrap {
+            sp: 256,
+            lcl: None,
+            arg: None,
+            this: None,
+            that: None,
+            entry: Some(String::from("Sys.init")),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BootstrapMode {
+    Auto, // when the program defines the entry function
+    Always,
+    Never,
+}
+
+impl Bootstrap {
+    // takes arg, and its value from iter, if it is one of --entry, --sp, --lcl,
+    // --arg, --this and --that; returns whether it was
+    pub fn parse<'a>(&mut self, arg: &str, iter: &mut impl Iterator<Item = &'a String>) -> Result<bool, String> {
+        match arg {
+            "--entry" => match iter.next() {
+                Some(name) => self.entry = Some(name.clone()),
+                None => return Err(String::from("option '--entry' requires a function name")),
+            },
+            "--sp" => self.sp = parse_address(arg, iter.next())?,
+            "--lcl" => self.lcl = Some(parse_address(arg, iter.next())?),
+            "--arg" => self.arg = Some(parse_address(arg, iter.next())?),
+            "--this" => self.this = Some(parse_address(arg, iter.next())?),
+            "--that" => self.that = Some(parse_address(arg, iter.next())?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // the bootstrap code to emit for files, if any
+    pub fn for_program(&self, files: &[VmFile], mode: BootstrapMode) -> Option<Bootstrap> {
+        let defines_entry = match &self.entry {
+            Some(entry) => files.iter().any(|file| file.commands.iter().any(|cmd| {
+                cmd.command_type == CommandType::Function && &cmd.arg1 == entry
+            })),
+            None => false,
+        };
+        match mode {
+            BootstrapMode::Auto if defines_entry => Some(self.clone()),
+            BootstrapMode::Always => {
+                // without the entry function only the registers are initialized
+                // and execution falls through to the first translated command
+                let mut bootstrap = self.clone();
+                if !defines_entry {
+                    bootstrap.entry = None;
+                }
+                Some(bootstrap)
+            },
+            _ => None,
+        }
+    }
+}
+
+// RAM addresses given on the command line must fit an A-instruction
+fn parse_address(option: &str, value: Option<&String>) -> Result<i16, String> {
+    match value {
+        Some(v) => match v.parse::<i16>() {
+            Ok(n) if n >= 0 => Ok(n),
+            _ => Err(format!("option '{}' expects an address in 0..32767, got '{}'", option, v)),
+        },
+        None => Err(format!("option '{}' requires an address", option)),
+    }
+}
+
+pub struct CodeWriter<W: Write> {
+    writer: BufWriter<W>,
     filename: String,
     function_name: String,
     line_count: usize,
     call_count: usize,
+    annotate: bool,
+    source_map: Vec<source_map::Entry>,
+    routines: Vec<&'static str>, // extended arithmetic routines used so far
+    cpu: Cpu,
+    pending_constant: Option<i16>, // `push constant` held back, Cpu::Extended only
 }
 
-impl CodeWriter {
-    pub fn new(f: File) -> Self {
+impl<W: Write> CodeWriter<W> {
+    pub fn new(out: W) -> Self {
         CodeWriter {
-            writer: BufWriter::<File>::new(f),
+            writer: BufWriter::new(out),
             filename: String::new(),
             function_name: String::new(),
             line_count: 0,
             call_count: 0,
+            annotate: false,
+            source_map: vec![],
+            routines: vec![],
+            cpu: Cpu::Standard,
+            pending_constant: None,
         }
     }
 
-    pub fn set_filename(&mut self, filename: String) {
-        self.filename = filename;
+    pub fn set_annotate(&mut self, annotate: bool) {
+        self.annotate = annotate;
+    }
+
+    // target profile: Cpu::Extended uses the shift instructions for mul, shl and
+    // shr by a constant, and in the extended arithmetic routines
+    pub fn set_cpu(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
     }
 
-    pub fn write_init(&mut self) {
-        writeln!(self.writer, "@256").unwrap();
+    pub fn write_comment(&mut self, comment: &str) {
+        // comments are not instructions, so line_count is left untouched
+        writeln!(self.writer, "// {}", comment).unwrap();
+    }
+
+    // the ROM address ranges of everything translated so far
+    pub fn source_map(&self) -> &[source_map::Entry] {
+        &self.source_map
+    }
+
+    fn begin_entry(&mut self, file: String, function: String, line: usize, command: String) {
+        if let Some(last) = self.source_map.last_mut() {
+            last.end = self.line_count;
+        }
+        self.source_map.push(source_map::Entry {
+            start: self.line_count,
+            end: self.line_count,
+            file,
+            function,
+            line,
+            command,
+        });
+    }
+
+    fn write_init(&mut self, bootstrap: &Bootstrap) {
+        if self.annotate {
+            self.write_comment("bootstrap");
+        }
+        self.begin_entry(String::new(), String::new(), 0, String::from("bootstrap"));
+        let registers = [
+            ("SP", Some(bootstrap.sp)),
+            ("LCL", bootstrap.lcl),
+            ("ARG", bootstrap.arg),
+            ("THIS", bootstrap.this),
+            ("THAT", bootstrap.that),
+        ];
+        for (register, value) in registers.iter() {
+            if let Some(value) = value {
+                writeln!(self.writer, "@{}", value).unwrap();
+                writeln!(self.writer, "D=A").unwrap();
+                writeln!(self.writer, "@{}", register).unwrap();
+                writeln!(self.writer, "M=D").unwrap();
+                self.line_count += 4;
+            }
+        }
+        if let Some(entry) = &bootstrap.entry {
+            self.write_call(entry.clone(), 0);
+        }
+    }
+
+    // copies count words from RAM[R13] to RAM[R14], advancing both pointers;
+    // going up word by word, overlapping blocks are safe when the destination is lower
+    fn copy_words(&mut self, count: usize) {
+        for _ in 0..count {
+            writeln!(self.writer, "@R13").unwrap();
+            writeln!(self.writer, "M=M+1").unwrap();
+            writeln!(self.writer, "A=M-1").unwrap();
+            writeln!(self.writer, "D=M").unwrap();
+            writeln!(self.writer, "@R14").unwrap();
+            writeln!(self.writer, "M=M+1").unwrap();
+            writeln!(self.writer, "A=M-1").unwrap();
+            writeln!(self.writer, "M=D").unwrap();
+            self.line_count += 8;
+        }
+    }
+
+    // A = m[base] + index without touching D: A=M, A=M+1, then one A=A+1 per step
+    fn write_offset_address(&mut self, base: &str, index: i16) {
+        writeln!(self.writer, "@{}", base).unwrap();
+        if index == 0 {
+            writeln!(self.writer, "A=M").unwrap();
+        } else {
+            writeln!(self.writer, "A=M+1").unwrap();
+        }
+        self.line_count += 2;
+        for _ in 1..index {
+            writeln!(self.writer, "A=A+1").unwrap();
+            self.line_count += 1;
+        }
+    }
+
+    fn write_push_constant(&mut self, value: i16) {
+        writeln!(self.writer, "@{}", value).unwrap();
         writeln!(self.writer, "D=A").unwrap();
         writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "A=M").unwrap();
         writeln!(self.writer, "M=D").unwrap();
-        self.line_count += 4;
-        self.write_call(String::from("Sys.init"), 0);
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "M=M+1").unwrap();
+        self.line_count += 7;
+    }
+
+    fn flush_constant(&mut self) {
+        if let Some(value) = self.pending_constant.take() {
+            self.write_push_constant(value);
+        }
     }
 
-    pub fn write_arithmetic(&mut self, command: String) {
+    // `push constant value` followed by mul, shl or shr: the top of the stack is
+    // shifted in place, and multiplied by adding its shifts for the set bits
+    fn write_constant_operation(&mut self, command: &str, value: i16) {
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "A=M-1").unwrap();
+        self.line_count += 2;
+        let shifts = match command {
+            "shl" if value > 15 => {
+                writeln!(self.writer, "M=0").unwrap();
+                self.line_count += 1;
+                return;
+            },
+            "shl" => value,
+            // 15 arithmetic shifts leave only copies of the sign bit
+            "shr" => value.min(15),
+            "mul" if value == 0 => {
+                writeln!(self.writer, "M=0").unwrap();
+                self.line_count += 1;
+                return;
+            },
+            "mul" if value & value.wrapping_sub(1) == 0 => value.trailing_zeros() as i16,
+            "mul" => {
+                // D = x, then per lower bit of value from the top: D = 2D (+ x)
+                writeln!(self.writer, "D=M").unwrap();
+                self.line_count += 1;
+                let top = 15 - value.leading_zeros();
+                for bit in (0..top).rev() {
+                    writeln!(self.writer, "D=D<<").unwrap();
+                    self.line_count += 1;
+                    if value & (1 << bit) != 0 {
+                        writeln!(self.writer, "D=D+M").unwrap();
+                        self.line_count += 1;
+                    }
+                }
+                writeln!(self.writer, "M=D").unwrap();
+                self.line_count += 1;
+                return;
+            },
+            _ => panic!("no constant form for '{}'", command),
+        };
+        let direction = if command == "shr" { ">>" } else { "<<" };
+        for _ in 0..shifts {
+            writeln!(self.writer, "M=M{}", direction).unwrap();
+        }
+        self.line_count += shifts as usize;
+    }
+
+    // x op y through the shared routine, which end_program emits
+    fn write_routine_call(&mut self, command: &str) {
+        let routine = extensions::routine(command);
+        if !self.routines.contains(&routine) {
+            self.routines.push(routine);
+        }
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "AM=M-1").unwrap();
+        writeln!(self.writer, "D=M").unwrap();
+        writeln!(self.writer, "@R14").unwrap();
+        writeln!(self.writer, "M=D").unwrap(); // m[14] = y
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "A=M-1").unwrap();
+        writeln!(self.writer, "D=M").unwrap();
+        writeln!(self.writer, "@R13").unwrap();
+        writeln!(self.writer, "M=D").unwrap(); // m[13] = x
+        self.line_count += 10;
+        writeln!(self.writer, "@{}", self.line_count + 6).unwrap();
+        writeln!(self.writer, "D=A").unwrap();
+        writeln!(self.writer, "@R15").unwrap();
+        writeln!(self.writer, "M=D").unwrap(); // m[15] = return address
+        writeln!(self.writer, "@{}", routine).unwrap();
+        writeln!(self.writer, "0;JMP").unwrap();
+        self.line_count += 6;
+        if command == "mod" {
+            writeln!(self.writer, "@R13").unwrap();
+            writeln!(self.writer, "D=M").unwrap(); // the remainder
+            self.line_count += 2;
+        }
+        // replace x with the result
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "A=M-1").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        self.line_count += 3;
+    }
+
+    // hands back the underlying output once the program is closed
+    pub fn into_inner(self) -> W {
+        match self.writer.into_inner() {
+            Ok(out) => out,
+            Err(_) => panic!("end_program must be called before into_inner"),
+        }
+    }
+}
+
+impl<W: Write> Backend for CodeWriter<W> {
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>) {
+        if let Some(bootstrap) = bootstrap {
+            self.write_init(bootstrap);
+        }
+    }
+
+    fn begin_file(&mut self, name: &str) {
+        self.filename = name.to_string();
+    }
+
+    // called before each VM command is translated
+    fn begin_command(&mut self, command: &Command) {
+        let takes_constant = command.command_type == CommandType::Arithmetic
+            && matches!(command.arg1.as_str(), "mul" | "shl" | "shr");
+        if !takes_constant {
+            self.flush_constant();
+        }
+        let text = command.to_string();
+        if self.annotate {
+            let comment = format!("{}.vm:{}: {}", self.filename, command.line, text);
+            self.write_comment(&comment);
+        }
+        let function = if command.command_type == CommandType::Function {
+            command.arg1.clone()
+        } else {
+            self.function_name.clone()
+        };
+        let file = format!("{}.vm", self.filename);
+        self.begin_entry(file, function, command.line, text);
+    }
+
+    fn write_arithmetic(&mut self, command: String) {
+        if let Some(value) = self.pending_constant.take() {
+            self.write_constant_operation(&command, value);
+            return;
+        }
         // translate arithmetic command to .asm
         match command.as_str() {
             "add" => {
@@ -60,12 +383,9 @@ impl CodeWriter {
             },
             "neg" => {
                 writeln!(self.writer, "@SP").unwrap();
-                writeln!(self.writer, "AM=M-1").unwrap();
-                writeln!(self.writer, "D=!M").unwrap(); // two's complement
-                writeln!(self.writer, "M=D+1").unwrap();
-                writeln!(self.writer, "@SP").unwrap();
-                writeln!(self.writer, "M=M+1").unwrap();
-                self.line_count += 6;
+                writeln!(self.writer, "A=M-1").unwrap(); // in place, SP is unchanged
+                writeln!(self.writer, "M=-M").unwrap();
+                self.line_count += 3;
             },
             "eq" => {
                 writeln!(self.writer, "@SP").unwrap();
@@ -175,78 +495,48 @@ impl CodeWriter {
                 writeln!(self.writer, "M=M+1").unwrap();
                 self.line_count += 5;
             },
+            "mul" | "div" | "mod" | "shl" | "shr" => {
+                self.write_routine_call(&command);
+            },
             _ => {
                 panic!("invalid arithmetic command");
             },
         }
     }
 
-    pub fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
         // Push or Pop command to .asm
         match command.as_str() {
             "push" => {
                 match segment.as_str() {
                     "constant" => {
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "D=A").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "M=M+1").unwrap();
-                        self.line_count += 7;
+                        if self.cpu == Cpu::Extended {
+                            // held back until the next command shows whether
+                            // it can use the constant directly
+                            self.pending_constant = Some(index);
+                        } else {
+                            self.write_push_constant(index);
+                        }
                     },
-                    "local" => {
-                        writeln!(self.writer, "@LCL").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "A=D+A").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "M=M+1").unwrap();
-                        self.line_count += 10;
-                    },
-                    "argument" => {
-                        writeln!(self.writer, "@ARG").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "A=D+A").unwrap();
+                    "local" | "argument" | "this" | "that" => {
+                        let base = segment_base(&segment);
+                        if index <= 2 {
+                            // @base / A=M / A=M+1, A=A+1: at most as long as adding the index
+                            self.write_offset_address(base, index);
+                        } else {
+                            writeln!(self.writer, "@{}", base).unwrap();
+                            writeln!(self.writer, "D=M").unwrap();
+                            writeln!(self.writer, "@{}", index).unwrap();
+                            writeln!(self.writer, "A=D+A").unwrap();
+                            self.line_count += 4;
+                        }
                         writeln!(self.writer, "D=M").unwrap();
                         writeln!(self.writer, "@SP").unwrap();
                         writeln!(self.writer, "A=M").unwrap();
                         writeln!(self.writer, "M=D").unwrap();
                         writeln!(self.writer, "@SP").unwrap();
                         writeln!(self.writer, "M=M+1").unwrap();
-                        self.line_count += 10;
-                    },
-                    "this" => {
-                        writeln!(self.writer, "@THIS").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "A=D+A").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "M=M+1").unwrap();
-                        self.line_count += 10;
-                    },
-                    "that" => {
-                        writeln!(self.writer, "@THAT").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "A=D+A").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "M=M+1").unwrap();
-                        self.line_count += 10;
+                        self.line_count += 6;
                     },
                     "pointer" => {
                         writeln!(self.writer, "@{}", 3 + index).unwrap();
@@ -285,65 +575,33 @@ impl CodeWriter {
             },
             "pop" => {
                 match segment.as_str() {
-                    "local" => {
-                        writeln!(self.writer, "@LCL").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "D=D+A").unwrap(); // a = m[LCL] + index
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "M=D").unwrap(); // m[13] = m[LCL] + index
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "AM=M-1").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap(); // m[LCL + index] = D
-                        self.line_count += 12;
-                    },
-                    "argument" => {
-                        writeln!(self.writer, "@ARG").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "D=D+A").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "AM=M-1").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        self.line_count += 12;
-                    },
-                    "this" => {
-                        writeln!(self.writer, "@THIS").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "D=D+A").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "AM=M-1").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        self.line_count += 12;
-                    },
-                    "that" => {
-                        writeln!(self.writer, "@THAT").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@{}", index).unwrap();
-                        writeln!(self.writer, "D=D+A").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        writeln!(self.writer, "@SP").unwrap();
-                        writeln!(self.writer, "AM=M-1").unwrap();
-                        writeln!(self.writer, "D=M").unwrap();
-                        writeln!(self.writer, "@R13").unwrap();
-                        writeln!(self.writer, "A=M").unwrap();
-                        writeln!(self.writer, "M=D").unwrap();
-                        self.line_count += 12;
+                    "local" | "argument" | "this" | "that" => {
+                        let base = segment_base(&segment);
+                        if index <= 6 {
+                            // store in place: the address is reached by increments
+                            // once D holds the value, with no need for R13
+                            writeln!(self.writer, "@SP").unwrap();
+                            writeln!(self.writer, "AM=M-1").unwrap();
+                            writeln!(self.writer, "D=M").unwrap();
+                            self.line_count += 3;
+                            self.write_offset_address(base, index);
+                            writeln!(self.writer, "M=D").unwrap();
+                            self.line_count += 1;
+                        } else {
+                            writeln!(self.writer, "@{}", base).unwrap();
+                            writeln!(self.writer, "D=M").unwrap();
+                            writeln!(self.writer, "@{}", index).unwrap();
+                            writeln!(self.writer, "D=D+A").unwrap(); // a = m[base] + index
+                            writeln!(self.writer, "@R13").unwrap();
+                            writeln!(self.writer, "M=D").unwrap(); // m[13] = m[base] + index
+                            writeln!(self.writer, "@SP").unwrap();
+                            writeln!(self.writer, "AM=M-1").unwrap();
+                            writeln!(self.writer, "D=M").unwrap();
+                            writeln!(self.writer, "@R13").unwrap();
+                            writeln!(self.writer, "A=M").unwrap();
+                            writeln!(self.writer, "M=D").unwrap(); // m[base + index] = D
+                            self.line_count += 12;
+                        }
                     },
                     "pointer" => {
                         writeln!(self.writer, "@SP").unwrap();
@@ -380,16 +638,16 @@ impl CodeWriter {
         }
     }
  
-    pub fn write_label(&mut self, label: String) {
-        if self.function_name != "" {
+    fn write_label(&mut self, label: String) {
+        if !self.function_name.is_empty() {
             writeln!(self.writer, "({}${})", self.function_name, label).unwrap();
         } else {
             writeln!(self.writer, "({})", label).unwrap();
         }
     }
 
-    pub fn write_goto(&mut self, label: String) {
-        if self.function_name != "" {
+    fn write_goto(&mut self, label: String) {
+        if !self.function_name.is_empty() {
             writeln!(self.writer, "@{}${}", self.function_name, label).unwrap();
         } else {
             writeln!(self.writer, "@{}", label).unwrap()
@@ -398,11 +656,11 @@ impl CodeWriter {
         self.line_count += 2;
     }
 
-    pub fn write_if(&mut self, label: String) {
+    fn write_if(&mut self, label: String) {
         writeln!(self.writer, "@SP").unwrap();
         writeln!(self.writer, "AM=M-1").unwrap();
         writeln!(self.writer, "D=M").unwrap();
-        if self.function_name != "" {
+        if !self.function_name.is_empty() {
             writeln!(self.writer, "@{}${}", self.function_name, label).unwrap();
         } else {
             writeln!(self.writer, "@{}", label).unwrap()
@@ -411,7 +669,7 @@ impl CodeWriter {
         self.line_count += 5;
     }
 
-    pub fn write_call(&mut self, function_name: String, num_args: i16) {
+    fn write_call(&mut self, function_name: String, num_args: i16) {
         // push return-address
         writeln!(self.writer, "@Return.{}", self.call_count).unwrap();
         writeln!(self.writer, "D=A").unwrap();
@@ -488,7 +746,7 @@ impl CodeWriter {
         self.call_count += 1;
     }
 
-    pub fn write_return(&mut self) {
+    fn write_return(&mut self) {
         // FRAME = LCL
         writeln!(self.writer, "@LCL").unwrap();
         writeln!(self.writer, "D=M").unwrap();
@@ -569,7 +827,60 @@ impl CodeWriter {
         self.line_count += 3;
     }
 
-    pub fn write_function(&mut self, function_name: String, num_locals: i16) {
+    fn write_tail_call(&mut self, function_name: String, num_args: i16) {
+        // the callee takes over the current frame: its arguments replace ours at
+        // ARG, followed by our saved frame, so it returns straight to our caller
+        if self.annotate {
+            self.write_comment("tail call: the following return is folded into the jump");
+        }
+
+        // copy the saved frame (LCL-5..LCL-1) above the arguments, since the new
+        // arguments may be more than ours and overwrite it
+        writeln!(self.writer, "@5").unwrap();
+        writeln!(self.writer, "D=A").unwrap();
+        writeln!(self.writer, "@LCL").unwrap();
+        writeln!(self.writer, "D=M-D").unwrap();
+        writeln!(self.writer, "@R13").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "D=M").unwrap();
+        writeln!(self.writer, "@R14").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        self.line_count += 10;
+        self.copy_words(5);
+
+        // move the arguments and the frame copy down to ARG
+        writeln!(self.writer, "@{}", num_args).unwrap();
+        writeln!(self.writer, "D=A").unwrap();
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "D=M-D").unwrap();
+        writeln!(self.writer, "@R13").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        writeln!(self.writer, "@ARG").unwrap();
+        writeln!(self.writer, "D=M").unwrap();
+        writeln!(self.writer, "@R14").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        self.line_count += 10;
+        self.copy_words(num_args as usize + 5);
+
+        // LCL = SP = ARG + n + 5
+        writeln!(self.writer, "@ARG").unwrap();
+        writeln!(self.writer, "D=M").unwrap();
+        writeln!(self.writer, "@{}", num_args as i32 + 5).unwrap();
+        writeln!(self.writer, "D=D+A").unwrap();
+        writeln!(self.writer, "@LCL").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        writeln!(self.writer, "@SP").unwrap();
+        writeln!(self.writer, "M=D").unwrap();
+        self.line_count += 8;
+
+        // goto f
+        writeln!(self.writer, "@{}", function_name).unwrap();
+        writeln!(self.writer, "0;JMP").unwrap();
+        self.line_count += 2;
+    }
+
+    fn write_function(&mut self, function_name: String, num_locals: i16) {
         self.function_name = function_name;
         // function_name label
         writeln!(self.writer, "({})", self.function_name).unwrap();
@@ -592,9 +903,98 @@ impl CodeWriter {
         self.line_count += 2;
     }
 
-    pub fn close(&mut self) {
-        writeln!(self.writer, "@{}", self.line_count).unwrap();
-        writeln!(self.writer, "0;JMP").unwrap();
-        self.writer.flush().unwrap();
+    fn end_program(&mut self) -> io::Result<()> {
+        self.flush_constant();
+        if self.annotate {
+            self.write_comment("end of program");
+        }
+        self.begin_entry(String::new(), String::new(), 0, String::from("halt"));
+        writeln!(self.writer, "@{}", self.line_count)?;
+        writeln!(self.writer, "0;JMP")?;
+        self.line_count += 2;
+        for routine in std::mem::take(&mut self.routines) {
+            if self.annotate {
+                self.write_comment(&format!("runtime {}", routine));
+            }
+            self.begin_entry(String::new(), String::new(), 0, format!("runtime {}", routine));
+            let code = extensions::code(routine, self.cpu);
+            write!(self.writer, "{}", code)?;
+            self.line_count += extensions::size(code);
+        }
+        self.source_map.last_mut().unwrap().end = self.line_count;
+        self.writer.flush()
+    }
+}
+
+// the register holding the base address of a pointer-based segment
+fn segment_base(segment: &str) -> &'static str {
+    match segment {
+        "local" => "LCL",
+        "argument" => "ARG",
+        "this" => "THIS",
+        "that" => "THAT",
+        _ => unreachable!("not a pointer-based segment"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::backend;
+    use crate::emulator::Emulator;
+    use crate::parser;
+
+    fn translate(source: &str, cpu: Cpu) -> String {
+        let file = parser::parse_source("Test", source, true).unwrap();
+        let mut w = CodeWriter::new(vec![]);
+        w.set_cpu(cpu);
+        backend::translate(&mut w, &[file], None, false).unwrap();
+        String::from_utf8(w.into_inner()).unwrap()
+    }
+
+    // translates and runs the commands, returning the top of the stack
+    fn run(source: &str, cpu: Cpu) -> i16 {
+        let asm = translate(source, cpu);
+        let mut emulator = Emulator::new(assembler::assemble(&asm, cpu).unwrap(), cpu);
+        emulator.ram[0] = 256;
+        assert!(emulator.run(100_000).unwrap());
+        assert_eq!(emulator.ram[0], 257);
+        emulator.ram[256]
+    }
+
+    // `push constant c, mul` on the extended CPU expands to shifts and adds
+    // in place; it has to agree with the $mul routine of the standard CPU
+    #[test]
+    fn mul_by_constant_matches_the_routine() {
+        let xs = [0, 1, -1, 3, -7, 100, -100, 181, 12345, i16::MAX, i16::MIN];
+        let cs = [0, 1, 2, 3, 5, 7, 10, 64, 255, 1000, 0x5555, 0x4000, i16::MAX];
+        for x in xs {
+            // push x: constants are non-negative, so negative ones are 0 - |x|
+            let push_x = match x {
+                i16::MIN => String::from("push constant 0\npush constant 32767\nsub\npush constant 1\nsub"),
+                x if x < 0 => format!("push constant 0\npush constant {}\nsub", -x),
+                x => format!("push constant {}", x),
+            };
+            for c in cs {
+                let source = format!("{}\npush constant {}\nmul\n", push_x, c);
+                let expanded = run(&source, Cpu::Extended);
+                assert!(!translate(&source, Cpu::Extended).contains("($mul)"), "{} * {} calls $mul", x, c);
+                assert!(translate(&source, Cpu::Standard).contains("($mul)"));
+                assert_eq!(expanded, run(&source, Cpu::Standard), "{} * {}", x, c);
+                assert_eq!(expanded, x.wrapping_mul(c), "{} * {}", x, c);
+            }
+        }
+    }
+
+    // -M is a standard comp, so neg is the same three instructions on both CPUs
+    #[test]
+    fn neg_is_in_place_on_both_cpus() {
+        for cpu in [Cpu::Standard, Cpu::Extended] {
+            assert!(translate("push constant 1\nneg\n", cpu).contains("A=M-1\nM=-M\n"));
+            assert_eq!(run("push constant 12345\nneg\n", cpu), -12345);
+            assert_eq!(run("push constant 0\nneg\n", cpu), 0);
+            assert_eq!(run("push constant 0\npush constant 32767\nsub\npush constant 1\nsub\nneg\n", cpu), i16::MIN);
+        }
     }
 }
@@ -0,0 +1,261 @@
+use crate::emulator::SCREEN;
+
+// The screen and keyboard of the emulated Hack computer: snapshots of the screen
+// as PBM or PNG images, and keyboard scripts that press keys at given cycles
+// (hackrun --screen and --keys).
+//
+// Word SCREEN + 32 * row + col / 16 holds 16 pixels of a row, the leftmost in bit
+// 0; a set bit is black.
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+// each row of the screen as bytes of 8 pixels, the leftmost in the high bit, 1
+// for black, as PBM stores them
+fn rows(ram: &[i16]) -> Vec<Vec<u8>> {
+    (0..HEIGHT)
+        .map(|row| {
+            ram[SCREEN + row * WIDTH / 16..SCREEN + (row + 1) * WIDTH / 16]
+                .iter()
+                .flat_map(|&word| {
+                    let word = (word as u16).reverse_bits();
+                    [(word >> 8) as u8, word as u8]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// binary PBM (P4)
+pub fn to_pbm(ram: &[i16]) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for row in rows(ram) {
+        out.extend(row);
+    }
+    out
+}
+
+// 1-bit grayscale PNG; the image data is stored without compression, which
+// keeps the encoder small and a screen under 17 KB
+pub fn to_png(ram: &[i16]) -> Vec<u8> {
+    let mut raw = vec![];
+    for row in rows(ram) {
+        raw.push(0); // filter type None
+        // PNG grayscale has 0 for black
+        raw.extend(row.iter().map(|byte| !byte));
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = vec![];
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    header.extend([1, 0, 0, 0, 0]); // bit depth 1, grayscale, no interlace
+
+    let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// the codes of the Hack keyboard's special keys
+const KEYS: [(&str, i16); 14] = [
+    ("space", 32),
+    ("newline", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+];
+
+// A keyboard script has a line '<cycle> <key>' per key press: from that cycle
+// on the key is held down, until the next line's cycle. A key is a single
+// character, a name from KEYS, f1 to f12, a key code of two or more digits, or
+// 'none' to release the keys. '#' starts a comment at the start of a line or
+// after the key, so '100 #' presses '#'. Returns the presses in order of cycle.
+pub fn parse_keys(text: &str) -> Result<Vec<(u64, i16)>, String> {
+    let mut keys = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", i + 1, message);
+        let mut fields = line.split_whitespace();
+        let (cycle, key) = match (fields.next(), fields.next()) {
+            (Some(cycle), Some(key)) => (cycle, key),
+            _ => return Err(error(format!("expected '<cycle> <key>', got '{}'", line))),
+        };
+        if fields.next().is_some_and(|rest| !rest.starts_with('#')) {
+            return Err(error(format!("expected '<cycle> <key>', got '{}'", line)));
+        }
+        let cycle = cycle.parse::<u64>()
+            .map_err(|_| error(format!("'{}' is not a cycle number", cycle)))?;
+        let key = key_code(key).ok_or_else(|| error(format!("unknown key '{}'", key)))?;
+        keys.push((cycle, key));
+    }
+    keys.sort_by_key(|&(cycle, _)| cycle);
+    Ok(keys)
+}
+
+fn key_code(key: &str) -> Option<i16> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return (' '..='~').contains(&c).then_some(c as i16);
+    }
+    if key == "none" {
+        return Some(0);
+    }
+    if let Some(&(_, code)) = KEYS.iter().find(|(name, _)| *name == key) {
+        return Some(code);
+    }
+    if let Some(n) = key.strip_prefix('f').and_then(|n| n.parse::<i16>().ok()) {
+        return (1..=12).contains(&n).then_some(140 + n);
+    }
+    key.parse::<i16>().ok().filter(|&code| code > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::RAM_SIZE;
+
+    // a screen with the pixels (0, 0), (17, 1) and (511, 255) black
+    fn screen() -> Vec<i16> {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN] = 1;
+        ram[SCREEN + 32 + 1] = 2;
+        ram[SCREEN + 32 * 256 - 1] = i16::MIN;
+        ram
+    }
+
+    #[test]
+    fn pbm_rows_start_at_the_high_bit() {
+        let pbm = to_pbm(&screen());
+        let header = b"P4\n512 256\n";
+        assert_eq!(&pbm[..header.len()], header);
+        let pixels = &pbm[header.len()..];
+        assert_eq!(pixels.len(), WIDTH * HEIGHT / 8);
+        assert_eq!(pixels[0], 0x80);
+        assert_eq!(pixels[64 + 2], 0x40);
+        assert_eq!(pixels[pixels.len() - 1], 0x01);
+        assert_eq!(pixels.iter().map(|byte| byte.count_ones()).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    // reads the chunks back, checking their CRCs, and undoes the stored blocks
+    #[test]
+    fn png_holds_the_rows_uncompressed() {
+        let png = to_png(&screen());
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = vec![];
+        let mut at = 8;
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let (kind, data) = (&png[at + 4..at + 8], &png[at + 8..at + 8 + len]);
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[at + 4..at + 8 + len]));
+            chunks.push((kind.to_vec(), data.to_vec()));
+            at += 12 + len;
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 2, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0]);
+
+        let zlib = &chunks[1].1;
+        assert_eq!(&zlib[..2], [0x78, 0x01]);
+        let mut raw = vec![];
+        let mut at = 2;
+        loop {
+            let last = zlib[at] == 1;
+            let len = u16::from_le_bytes([zlib[at + 1], zlib[at + 2]]) as usize;
+            assert_eq!(u16::from_le_bytes([zlib[at + 3], zlib[at + 4]]), !(len as u16));
+            raw.extend(&zlib[at + 5..at + 5 + len]);
+            at += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(zlib[at..], adler32(&raw).to_be_bytes());
+
+        // a filter byte and 64 bytes per row, with 0 for black
+        assert_eq!(raw.len(), HEIGHT * 65);
+        let rows = rows(&screen());
+        for (row, line) in raw.chunks(65).enumerate() {
+            assert_eq!(line[0], 0);
+            assert!(line[1..].iter().zip(rows[row].iter()).all(|(&png, &pbm)| png == !pbm));
+        }
+        assert_eq!(raw[1], 0x7f);
+    }
+
+    #[test]
+    fn keys_parse() {
+        let script = "# a comment\n\n200 none\n100 a   # press a\n150 space\n160 f12\n170 #\n180 128\n190 ~\n";
+        assert_eq!(parse_keys(script).unwrap(), [(100, 97), (150, 32), (160, 152), (170, 35), (180, 128), (190, 126), (200, 0)]);
+    }
+
+    #[test]
+    fn bad_key_lines_name_the_line() {
+        for (script, error) in [
+            ("100", "line 1: expected '<cycle> <key>', got '100'"),
+            ("# ok\n100 a b", "line 2: expected '<cycle> <key>', got '100 a b'"),
+            ("x a", "line 1: 'x' is not a cycle number"),
+            ("100 f13", "line 1: unknown key 'f13'"),
+            ("100 \u{e9}", "line 1: unknown key '\u{e9}'"),
+        ] {
+            assert_eq!(parse_keys(script).unwrap_err(), error);
+        }
+    }
+}
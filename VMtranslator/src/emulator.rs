@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use crate::assembler::Cpu;
+use crate::builtins::{Builtins, Native};
+
+pub const RAM_SIZE: usize = 32768;
+
+// the memory maps of the 512 x 256 screen, one bit per pixel and 32 words per
+// row, and of the keyboard, which holds the code of the key pressed or 0
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+
+// The `0;JMP` instruction, used to recognize the halt loop `(L) @L 0;JMP`.
+const JMP: u16 = 0b1110101010000111;
+
+// Hack CPU emulator. The ALU is modeled through its six control bits, so every
+// comp encoding behaves as on the hardware, and with Cpu::Extended the shift
+// instructions (prefix 101) run as well.
+//
+// Like the hardware, a C-instruction writes M and jumps using the value A had
+// before the instruction, and addresses wrap to 15 bits. The keyboard register
+// is read-only for the program; set_key presses a key.
+//
+// With built-ins, reaching the first instruction of a built-in function runs
+// it natively and returns to the caller through the frame 'call' pushed, as a
+// single cycle.
+pub struct Emulator {
+    pub rom: Vec<u16>,
+    pub ram: Vec<i16>,
+    pub pc: u16,
+    pub a: i16,
+    pub d: i16,
+    pub cycles: u64, // instructions executed so far
+    cpu: Cpu,
+    builtins: Option<Builtins>,
+    natives: Vec<Option<Native>>, // per ROM address, the built-in starting there
+}
+
+impl Emulator {
+    pub fn new(rom: Vec<u16>, cpu: Cpu) -> Self {
+        Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            a: 0,
+            d: 0,
+            cycles: 0,
+            cpu,
+            builtins: None,
+            natives: vec![],
+        }
+    }
+
+    // runs the functions of builtins natively; symbols gives the address of
+    // each function
+    pub fn set_builtins(&mut self, builtins: Builtins, symbols: &HashMap<String, u16>) {
+        self.natives = vec![None; self.rom.len()];
+        for function in builtins.functions() {
+            if let Some(&address) = symbols.get(function) {
+                if let Some(native) = self.natives.get_mut(address as usize) {
+                    *native = builtins.get(function);
+                }
+            }
+        }
+        self.builtins = Some(builtins);
+    }
+
+    // true when the program sits in the loop `(L) @L 0;JMP`, which the VM
+    // translator emits at the end, or has run past the end of the ROM
+    pub fn is_halted(&self) -> bool {
+        let pc = self.pc as usize;
+        if pc >= self.rom.len() {
+            return true;
+        }
+        self.rom[pc] == self.pc && self.rom.get(pc + 1) == Some(&JMP)
+    }
+
+    // runs until the program halts or max_cycles instructions have been executed;
+    // returns whether it halted
+    pub fn run(&mut self, max_cycles: u64) -> Result<bool, String> {
+        let limit = self.cycles.saturating_add(max_cycles);
+        while self.cycles < limit {
+            if self.is_halted() {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(self.is_halted())
+    }
+
+    // executes the instruction at pc
+    pub fn step(&mut self) -> Result<(), String> {
+        if let Some(Some(native)) = self.natives.get(self.pc as usize) {
+            if self.call_native(*native) {
+                return Ok(());
+            }
+        }
+        let instruction = match self.rom.get(self.pc as usize) {
+            Some(&word) => word,
+            None => return Err(format!("pc {} is outside the program", self.pc)),
+        };
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction as i16;
+            self.pc = self.pc.wrapping_add(1);
+            return Ok(());
+        }
+
+        let address = self.a as u16 as usize & (RAM_SIZE - 1);
+        let m = self.ram[address];
+        let comp = (instruction >> 6) & 0x7f;
+        let out = match instruction >> 13 {
+            0b111 => {
+                let y = if comp & 0x40 != 0 { m } else { self.a };
+                alu(self.d, y, comp & 0x3f)
+            },
+            0b101 if self.cpu == Cpu::Extended => match shift(self.a, self.d, m, comp) {
+                Some(out) => out,
+                None => return Err(self.illegal(instruction)),
+            },
+            _ => return Err(self.illegal(instruction)),
+        };
+
+        if instruction & 0b001000 != 0 && address != KBD {
+            self.ram[address] = out;
+        }
+        let jump_target = self.a as u16;
+        if instruction & 0b100000 != 0 {
+            self.a = out;
+        }
+        if instruction & 0b010000 != 0 {
+            self.d = out;
+        }
+        let jump = instruction & 0b111;
+        let taken = (jump & 0b100 != 0 && out < 0)
+            || (jump & 0b010 != 0 && out == 0)
+            || (jump & 0b001 != 0 && out > 0);
+        self.pc = if taken {
+            jump_target & 0x7fff
+        } else {
+            self.pc.wrapping_add(1)
+        };
+        Ok(())
+    }
+
+    // runs native on the arguments at ARG and returns as 'return' would; false,
+    // with nothing changed, when the function's code has to run instead
+    fn call_native(&mut self, native: Native) -> bool {
+        let builtins = match &self.builtins {
+            Some(builtins) => builtins,
+            None => return false,
+        };
+        let (frame, arg) = (self.ram[1] as u16 as usize, self.ram[2] as u16 as usize);
+        if !(5..=RAM_SIZE).contains(&frame) || arg >= RAM_SIZE || arg + native.arity > RAM_SIZE {
+            return false;
+        }
+        let args = self.ram[arg..arg + native.arity].to_vec();
+        let value = match builtins.call(native, &mut self.ram, &args) {
+            Some(value) => value,
+            None => return false,
+        };
+        let return_address = self.ram[frame - 5];
+        self.ram[arg] = value;
+        self.ram[0] = arg as i16 + 1;
+        self.ram[4] = self.ram[frame - 1];
+        self.ram[3] = self.ram[frame - 2];
+        self.ram[2] = self.ram[frame - 3];
+        self.ram[1] = self.ram[frame - 4];
+        self.pc = return_address as u16 & 0x7fff;
+        self.cycles += 1;
+        true
+    }
+
+    // the key held down from now on, 0 for none
+    pub fn set_key(&mut self, key: i16) {
+        self.ram[KBD] = key;
+    }
+
+    fn illegal(&self, instruction: u16) -> String {
+        format!("illegal instruction {:016b} at ROM[{}]", instruction, self.pc)
+    }
+}
+
+// zx, nx, zy, ny, f, no applied to x = D and y = A or M
+fn alu(x: i16, y: i16, control: u16) -> i16 {
+    let mut x = x;
+    let mut y = y;
+    if control & 0b100000 != 0 {
+        x = 0;
+    }
+    if control & 0b010000 != 0 {
+        x = !x;
+    }
+    if control & 0b001000 != 0 {
+        y = 0;
+    }
+    if control & 0b000100 != 0 {
+        y = !y;
+    }
+    let mut out = if control & 0b000010 != 0 { x.wrapping_add(y) } else { x & y };
+    if control & 0b000001 != 0 {
+        out = !out;
+    }
+    out
+}
+
+// the extended CPU's shifts; c1 selects left, c2 selects D and the a-bit M
+fn shift(a: i16, d: i16, m: i16, comp: u16) -> Option<i16> {
+    if comp & 0b0001111 != 0 || (comp & 0x40 != 0 && comp & 0b0010000 != 0) {
+        return None;
+    }
+    let operand = if comp & 0x40 != 0 {
+        m
+    } else if comp & 0b0010000 != 0 {
+        d
+    } else {
+        a
+    };
+    if comp & 0b0100000 != 0 {
+        Some(operand.wrapping_shl(1))
+    } else {
+        Some(operand >> 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    // runs source until it halts, with RAM[0] = x
+    fn run(source: &str, cpu: Cpu, x: i16) -> Result<Emulator, String> {
+        let rom = assembler::assemble(&format!("{}\n(END)\n@END\n0;JMP", source), cpu)?;
+        let mut emulator = Emulator::new(rom, cpu);
+        emulator.ram[0] = x;
+        assert!(emulator.run(100)?);
+        Ok(emulator)
+    }
+
+    #[test]
+    fn shifts_are_arithmetic() {
+        let cases: [(i16, i16, i16); 6] = [
+            (5, 10, 2),
+            (-5, -10, -3), // right shifts round toward minus infinity
+            (-1, -2, -1),
+            (0x4000, i16::MIN, 0x2000),
+            (i16::MIN, 0, -0x4000),
+            (i16::MAX, -2, 0x3fff),
+        ];
+        for (x, left, right) in cases {
+            // the same shift through each operand and into each register
+            let e = run("@0\nD=M\nA=D\nD=A<<\n@1\nM=D\n@0\nM=M<<\nD=M\nD=D>>\n@2\nM=D", Cpu::Extended, x).unwrap();
+            assert_eq!((e.ram[0], e.ram[1]), (left, left), "{} << 1", x);
+            assert_eq!(e.ram[2], left >> 1, "({} << 1) >> 1", x);
+            let e = run("@0\nD=M\nA=D\nD=A>>\n@1\nM=D\n@0\nM=M>>\nD=M\nD=D<<\n@2\nM=D", Cpu::Extended, x).unwrap();
+            assert_eq!((e.ram[0], e.ram[1]), (right, right), "{} >> 1", x);
+            assert_eq!(e.ram[2], right.wrapping_shl(1), "({} >> 1) << 1", x);
+        }
+    }
+
+    #[test]
+    fn shifts_set_flags_for_jumps() {
+        let e = run("@0\nD=M\n@NEG\nD=D<<;JLT\n@1\nM=1\n(NEG)", Cpu::Extended, 0x4000).unwrap();
+        assert_eq!(e.ram[1], 0);
+    }
+
+    #[test]
+    fn standard_cpu_rejects_shifts() {
+        let rom = assembler::assemble("@0\nD=M<<", Cpu::Extended).unwrap();
+        let mut emulator = Emulator::new(rom, Cpu::Standard);
+        assert_eq!(emulator.run(10), Err(String::from("illegal instruction 1011100000010000 at ROM[1]")));
+    }
+
+    #[test]
+    fn undefined_shift_encodings_are_illegal() {
+        // the a-bit with D as the operand, and c3..c6 set
+        for bits in [0b1110000u16, 0b0100001] {
+            let mut emulator = Emulator::new(vec![0b101 << 13 | bits << 6 | 0b010 << 3], Cpu::Extended);
+            assert!(emulator.step().is_err(), "{:07b}", bits);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+// Hack routines behind the extended arithmetic commands (`--extensions`):
+//
+//   mul        x * y, wrapping like add
+//   div, mod   quotient and remainder truncated toward zero, so the remainder
+//              has the sign of x; x div 0 is -1 and x mod 0 is x
+//   shl, shr   x shifted left or right (arithmetic) by y bits; counts outside
+//              0..15 shift every bit out
+//
+// CodeWriter emits each routine once, after the halt loop, if the program uses it.
+// A call site stores x in R13, y in R14 and its return address in R15 and jumps
+// to the routine, which returns with the result in D (div/mod: the quotient in D
+// and the remainder in R13). The routines keep their own state in the variables
+// $ext.*, which the assembler allocates after the program's statics.
+
+use crate::assembler::Cpu;
+
+// the routine implementing an extended command
+pub fn routine(command: &str) -> &'static str {
+    match command {
+        "mul" => "$mul",
+        "div" | "mod" => "$divmod",
+        "shl" => "$shl",
+        "shr" => "$shr",
+        _ => panic!("not an extended arithmetic command"),
+    }
+}
+
+// the routine's code for the given CPU; the extended CPU shifts in one instruction
+pub fn code(routine: &str, cpu: Cpu) -> &'static str {
+    match (routine, cpu) {
+        ("$mul", Cpu::Standard) => MUL,
+        ("$mul", Cpu::Extended) => MUL_EXTENDED,
+        ("$divmod", _) => DIVMOD,
+        ("$shl", Cpu::Standard) => SHL,
+        ("$shl", Cpu::Extended) => SHL_EXTENDED,
+        ("$shr", Cpu::Standard) => SHR,
+        ("$shr", Cpu::Extended) => SHR_EXTENDED,
+        _ => panic!("no such routine"),
+    }
+}
+
+// the result of an extended command, as the routines compute it
+pub fn evaluate(command: &str, x: i16, y: i16) -> i16 {
+    match command {
+        "mul" => x.wrapping_mul(y),
+        "div" if y == 0 => -1,
+        "mod" if y == 0 => x,
+        "div" => x.wrapping_div(y),
+        "mod" => x.wrapping_rem(y),
+        "shl" if (0..16).contains(&y) => x << y,
+        "shl" => 0,
+        "shr" if (0..16).contains(&y) => x >> y,
+        // 15 arithmetic shifts leave only copies of the sign bit
+        "shr" => x >> 15,
+        _ => panic!("not an extended arithmetic command"),
+    }
+}
+
+// number of instructions in a routine, labels and comments aside
+pub fn size(code: &str) -> usize {
+    code.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('(') && !l.starts_with("//"))
+        .count()
+}
+
+// r = sum of x << i over the bits i of y, stopping once x or the mask is shifted out
+const MUL: &str = "\
+($mul)
+@$ext.r
+M=0
+@$ext.m
+M=1
+($mul.loop)
+@$ext.m
+D=M
+@R14
+D=D&M
+@$mul.skip
+D;JEQ
+@R13
+D=M
+@$ext.r
+M=D+M
+($mul.skip)
+@R13
+D=M
+MD=D+M
+@$mul.done
+D;JEQ
+@$ext.m
+D=M
+MD=D+M
+@$mul.loop
+D;JNE
+($mul.done)
+@$ext.r
+D=M
+@R15
+A=M
+0;JMP
+";
+
+// long division of |x| by |y|, one bit of |x| per step from the top; |x| and |y|
+// may be 32768, so the comparison r >= |y| is unsigned
+const DIVMOD: &str = "\
+($divmod)
+@R13
+D=M
+@$ext.d
+M=D
+@$ext.s
+M=0
+@$divmod.xpos
+D;JGE
+@R13
+M=-M
+@$ext.s
+M=!M
+($divmod.xpos)
+@R14
+D=M
+@$divmod.ypos
+D;JGT
+@$divmod.zero
+D;JEQ
+@R14
+M=-M
+@$ext.s
+M=!M
+($divmod.ypos)
+@$ext.q
+M=0
+@$ext.r
+M=0
+@16
+D=A
+@$ext.m
+M=D
+($divmod.loop)
+@$ext.r
+D=M
+M=D+M
+@R13
+D=M
+@$divmod.shift
+D;JGE
+@$ext.r
+M=M+1
+($divmod.shift)
+@R13
+M=D+M
+@$ext.q
+D=M
+M=D+M
+@$ext.r
+D=M
+@$divmod.sub
+D;JLT
+@R14
+D=M
+@$divmod.next
+D;JLT
+@$ext.r
+D=M
+@R14
+D=D-M
+@$divmod.next
+D;JLT
+($divmod.sub)
+@R14
+D=M
+@$ext.r
+M=M-D
+@$ext.q
+M=M+1
+($divmod.next)
+@$ext.m
+MD=M-1
+@$divmod.loop
+D;JGT
+@$ext.s
+D=M
+@$divmod.qpos
+D;JEQ
+@$ext.q
+M=-M
+($divmod.qpos)
+@$ext.d
+D=M
+@$divmod.rpos
+D;JGE
+@$ext.r
+M=-M
+($divmod.rpos)
+@$ext.r
+D=M
+@R13
+M=D
+@$ext.q
+D=M
+@R15
+A=M
+0;JMP
+($divmod.zero)
+@$ext.d
+D=M
+@R13
+M=D
+D=-1
+@R15
+A=M
+0;JMP
+";
+
+// doubles x y times
+const SHL: &str = "\
+($shl)
+@R14
+D=M
+@$shl.zero
+D;JLT
+@16
+D=D-A
+@$shl.zero
+D;JGE
+($shl.loop)
+@R14
+MD=M-1
+@$shl.done
+D;JLT
+@R13
+D=M
+M=D+M
+@$shl.loop
+0;JMP
+($shl.done)
+@R13
+D=M
+@R15
+A=M
+0;JMP
+($shl.zero)
+D=0
+@R15
+A=M
+0;JMP
+";
+
+// copies bit y+i of x to bit i of r, then fills the bits above with the sign of x
+const SHR: &str = "\
+($shr)
+@$ext.r
+M=0
+@$ext.d
+M=1
+@R14
+D=M
+@$shr.sign
+D;JLT
+@16
+D=D-A
+@$shr.sign
+D;JGE
+@$ext.m
+M=1
+($shr.mask)
+@R14
+MD=M-1
+@$shr.bit
+D;JLT
+@$ext.m
+D=M
+M=D+M
+@$shr.mask
+0;JMP
+($shr.bit)
+@$ext.m
+D=M
+@$shr.sign
+D;JEQ
+@R13
+D=D&M
+@$shr.next
+D;JEQ
+@$ext.d
+D=M
+@$ext.r
+M=D|M
+($shr.next)
+@$ext.d
+D=M
+M=D+M
+@$ext.m
+D=M
+M=D+M
+@$shr.bit
+0;JMP
+($shr.sign)
+@R13
+D=M
+@$shr.done
+D;JGE
+($shr.fill)
+@$ext.d
+D=M
+@$shr.done
+D;JEQ
+@$ext.r
+M=D|M
+@$ext.d
+M=D+M
+@$shr.fill
+0;JMP
+($shr.done)
+@$ext.r
+D=M
+@R15
+A=M
+0;JMP
+";
+
+const MUL_EXTENDED: &str = "\
+($mul)
+@$ext.r
+M=0
+@$ext.m
+M=1
+($mul.loop)
+@$ext.m
+D=M
+@R14
+D=D&M
+@$mul.skip
+D;JEQ
+@R13
+D=M
+@$ext.r
+M=D+M
+($mul.skip)
+@R13
+MD=M<<
+@$mul.done
+D;JEQ
+@$ext.m
+MD=M<<
+@$mul.loop
+D;JNE
+($mul.done)
+@$ext.r
+D=M
+@R15
+A=M
+0;JMP
+";
+
+const SHL_EXTENDED: &str = "\
+($shl)
+@R14
+D=M
+@$shl.zero
+D;JLT
+@16
+D=D-A
+@$shl.zero
+D;JGE
+($shl.loop)
+@R14
+MD=M-1
+@$shl.done
+D;JLT
+@R13
+M=M<<
+@$shl.loop
+0;JMP
+($shl.done)
+@R13
+D=M
+@R15
+A=M
+0;JMP
+($shl.zero)
+D=0
+@R15
+A=M
+0;JMP
+";
+
+// counts outside 0..15 shift by 15, which leaves only copies of the sign bit
+const SHR_EXTENDED: &str = "\
+($shr)
+@R14
+D=M
+@$shr.all
+D;JLT
+@16
+D=D-A
+@$shr.loop
+D;JLT
+($shr.all)
+@15
+D=A
+@R14
+M=D
+($shr.loop)
+@R14
+MD=M-1
+@$shr.done
+D;JLT
+@R13
+M=M>>
+@$shr.loop
+0;JMP
+($shr.done)
+@R13
+D=M
+@R15
+A=M
+0;JMP
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use crate::backend;
+    use crate::code_writer::CodeWriter;
+    use crate::emulator::Emulator;
+    use crate::parser;
+
+    const VALUES: [i16; 14] = [0, 1, -1, 2, 3, -3, 7, -8, 15, 16, 100, -12345, i16::MAX, i16::MIN];
+
+    // commands pushing v; constants are non-negative
+    fn push(v: i16) -> String {
+        match v {
+            i16::MIN => String::from("push constant 0\npush constant 32767\nsub\npush constant 1\nsub\n"),
+            v if v < 0 => format!("push constant 0\npush constant {}\nsub\n", -v),
+            v => format!("push constant {}\n", v),
+        }
+    }
+
+    // translates and runs the commands, returning the top of the stack
+    fn run(source: &str, cpu: Cpu) -> i16 {
+        let file = parser::parse_source("Test", source, true).unwrap();
+        let mut w = CodeWriter::new(vec![]);
+        w.set_cpu(cpu);
+        backend::translate(&mut w, &[file], None, false).unwrap();
+        let asm = String::from_utf8(w.into_inner()).unwrap();
+        let mut emulator = Emulator::new(assembler::assemble(&asm, cpu).unwrap(), cpu);
+        emulator.ram[0] = 256;
+        assert!(emulator.run(100_000).unwrap());
+        assert_eq!(emulator.ram[0], 257);
+        emulator.ram[256]
+    }
+
+    // the cases the header documents
+    #[test]
+    fn evaluate_edge_cases() {
+        assert_eq!(evaluate("div", 7, 0), -1);
+        assert_eq!(evaluate("mod", -7, 0), -7);
+        assert_eq!(evaluate("div", -7, 2), -3);
+        assert_eq!(evaluate("mod", -7, 2), -1);
+        assert_eq!(evaluate("div", i16::MIN, -1), i16::MIN);
+        assert_eq!(evaluate("mod", i16::MIN, -1), 0);
+        assert_eq!(evaluate("shl", 1, 16), 0);
+        assert_eq!(evaluate("shl", 1, -1), 0);
+        assert_eq!(evaluate("shr", i16::MIN, 16), -1);
+        assert_eq!(evaluate("shr", 0x4000, 16), 0);
+    }
+
+    // y goes through temp 0, so that the command is not taken for one on a constant
+    #[test]
+    fn routines_match_evaluate() {
+        for command in ["div", "mod", "shl", "shr"] {
+            for cpu in [Cpu::Standard, Cpu::Extended] {
+                for x in VALUES {
+                    for y in VALUES {
+                        let source = format!("{}pop temp 0\n{}push temp 0\n{}\n", push(y), push(x), command);
+                        assert_eq!(run(&source, cpu), evaluate(command, x, y), "{} {} {} ({:?})", x, command, y, cpu);
+                    }
+                }
+            }
+        }
+    }
+
+    // shl and shr by a constant are expanded in place on the extended CPU
+    #[test]
+    fn constant_shifts_match_evaluate() {
+        for command in ["shl", "shr"] {
+            for cpu in [Cpu::Standard, Cpu::Extended] {
+                for x in VALUES {
+                    for y in [0, 1, 7, 15, 16, 17, 1000, i16::MAX] {
+                        let source = format!("{}push constant {}\n{}\n", push(x), y, command);
+                        assert_eq!(run(&source, cpu), evaluate(command, x, y), "{} {} {} ({:?})", x, command, y, cpu);
+                    }
+                }
+            }
+        }
+    }
+}
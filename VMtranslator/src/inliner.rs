@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{Command, CommandType, VmFile};
+
+// Function inlining, enabled by `--inline`.
+//
+// Calls to small leaf functions (no calls of their own, so never recursive) are
+// replaced by a copy of the function body. The arguments are popped into temp
+// slots that no code in the program touches, the locals get zeroed slots of their
+// own, returns become jumps to the end of the copy, and labels are renamed so
+// that every copy has its own. A function is not inlined when the copy could
+// behave differently from the call:
+//   - it writes pointer 0/1, which return would have restored
+//   - it uses static and lives in another file than the caller
+//   - the stack does not hold exactly the return value at every return
+//   - it reads arguments the call does not pass or locals it does not declare
+
+pub const DEFAULT_THRESHOLD: usize = 10;
+
+struct Callee {
+    file: String,
+    num_locals: i16,
+    num_args: i16, // highest argument index used, plus one
+    uses_static: bool,
+    body: Vec<Command>,
+}
+
+// threshold is the largest body, in VM commands, that gets inlined
+pub fn inline(files: &mut [VmFile], threshold: usize) {
+    let callees = find_callees(files, threshold);
+    let slots = free_temp_slots(files);
+    let mut copies = 0;
+    for file in files.iter_mut() {
+        let mut out = vec![];
+        for cmd in std::mem::take(&mut file.commands).into_iter() {
+            let callee = match cmd.command_type {
+                CommandType::Call => callees.get(&cmd.arg1),
+                _ => None,
+            };
+            let fits = callee.is_some_and(|callee| {
+                (!callee.uses_static || callee.file == file.name)
+                    && callee.num_args <= cmd.arg2
+                    && (cmd.arg2 + callee.num_locals) as usize <= slots.len()
+            });
+            if fits {
+                expand(callee.unwrap(), &cmd, &slots, copies, &mut out);
+                copies += 1;
+            } else {
+                out.push(cmd);
+            }
+        }
+        file.commands = out;
+    }
+}
+
+fn find_callees(files: &[VmFile], threshold: usize) -> HashMap<String, Callee> {
+    let mut callees = HashMap::new();
+    let mut defined = HashSet::new();
+    for file in files.iter() {
+        let functions = file.commands.iter().enumerate()
+            .filter(|(_, cmd)| cmd.command_type == CommandType::Function);
+        for (start, function) in functions {
+            let body: Vec<Command> = file.commands[start + 1..].iter()
+                .take_while(|cmd| cmd.command_type != CommandType::Function)
+                .cloned()
+                .collect();
+            // a name defined twice cannot be resolved here
+            if !defined.insert(function.arg1.clone()) {
+                callees.remove(&function.arg1);
+                continue;
+            }
+            if body.len() > threshold || !can_inline(&body, function.arg2) {
+                continue;
+            }
+            let num_args = body.iter()
+                .filter(|cmd| segment(cmd) == Some("argument"))
+                .map(|cmd| cmd.arg2 + 1)
+                .max()
+                .unwrap_or(0);
+            let uses_static = body.iter().any(|cmd| segment(cmd) == Some("static"));
+            callees.insert(function.arg1.clone(), Callee {
+                file: file.name.clone(),
+                num_locals: function.arg2,
+                num_args,
+                uses_static,
+                body,
+            });
+        }
+    }
+    callees
+}
+
+fn can_inline(body: &[Command], num_locals: i16) -> bool {
+    let safe = body.iter().all(|cmd| match cmd.command_type {
+        CommandType::Call | CommandType::Function => false,
+        CommandType::Pop if cmd.arg1 == "pointer" => false,
+        CommandType::Push | CommandType::Pop if cmd.arg1 == "local" => cmd.arg2 < num_locals,
+        _ => true,
+    });
+    safe && returns_one_value(body)
+}
+
+// follows every path through the body, checking that the stack never goes below
+// the function's own and holds exactly one value at each return
+fn returns_one_value(body: &[Command]) -> bool {
+    let labels: HashMap<&str, usize> = body.iter().enumerate()
+        .filter(|(_, cmd)| cmd.command_type == CommandType::Label)
+        .map(|(i, cmd)| (cmd.arg1.as_str(), i))
+        .collect();
+    let mut depths: Vec<Option<i32>> = vec![None; body.len() + 1];
+    depths[0] = Some(0);
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        // falling off the end would run into whatever follows the function
+        let cmd = match body.get(i) {
+            Some(cmd) => cmd,
+            None => return false,
+        };
+        let depth = depths[i].unwrap();
+        let mut next = vec![];
+        match cmd.command_type {
+            CommandType::Push => next.push((i + 1, depth + 1)),
+            CommandType::Pop => next.push((i + 1, depth - 1)),
+            CommandType::Arithmetic => match cmd.arg1.as_str() {
+                "neg" | "not" => next.push((i + 1, depth)),
+                _ => next.push((i + 1, depth - 1)),
+            },
+            CommandType::Label => next.push((i + 1, depth)),
+            CommandType::Goto | CommandType::If => {
+                let target = match labels.get(cmd.arg1.as_str()) {
+                    Some(&target) => target,
+                    None => return false,
+                };
+                if cmd.command_type == CommandType::Goto {
+                    next.push((target, depth));
+                } else {
+                    next.push((target, depth - 1));
+                    next.push((i + 1, depth - 1));
+                }
+            },
+            CommandType::Return => {
+                if depth != 1 {
+                    return false;
+                }
+            },
+            CommandType::Function | CommandType::Call => return false,
+        }
+        // an operand taken from an empty stack would belong to the caller
+        let operands = match cmd.command_type {
+            CommandType::Arithmetic if cmd.arg1 == "neg" || cmd.arg1 == "not" => 1,
+            CommandType::Arithmetic => 2,
+            CommandType::Pop | CommandType::If => 1,
+            _ => 0,
+        };
+        if depth < operands {
+            return false;
+        }
+        for (j, d) in next {
+            match depths[j] {
+                Some(known) if known != d => return false,
+                Some(_) => {},
+                None => {
+                    depths[j] = Some(d);
+                    pending.push(j);
+                },
+            }
+        }
+    }
+    true
+}
+
+// temp indices that no command of the program uses
+fn free_temp_slots(files: &[VmFile]) -> Vec<i16> {
+    let used: HashSet<i16> = files.iter()
+        .flat_map(|file| file.commands.iter())
+        .filter(|cmd| segment(cmd) == Some("temp"))
+        .map(|cmd| cmd.arg2)
+        .collect();
+    (0..8).filter(|i| !used.contains(i)).collect()
+}
+
+// appends the inlined copy number `copy` of callee for the command `call`
+fn expand(callee: &Callee, call: &Command, slots: &[i16], copy: usize, out: &mut Vec<Command>) {
+    let num_args = call.arg2;
+    let prefix = format!("{}$inline.{}", call.arg1, copy);
+    let end = format!("{}.end", prefix);
+    let command = |command_type, arg1: &str, arg2| Command {
+        command_type,
+        arg1: arg1.to_string(),
+        arg2,
+        line: call.line,
+    };
+
+    // the last argument is on top of the stack
+    for i in (0..num_args).rev() {
+        out.push(command(CommandType::Pop, "temp", slots[i as usize]));
+    }
+    for j in 0..callee.num_locals {
+        out.push(command(CommandType::Push, "constant", 0));
+        out.push(command(CommandType::Pop, "temp", slots[(num_args + j) as usize]));
+    }
+
+    let mut jumps_to_end = false;
+    for (i, cmd) in callee.body.iter().enumerate() {
+        match cmd.command_type {
+            CommandType::Push | CommandType::Pop if cmd.arg1 == "argument" => {
+                out.push(command(cmd.command_type, "temp", slots[cmd.arg2 as usize]));
+            },
+            CommandType::Push | CommandType::Pop if cmd.arg1 == "local" => {
+                out.push(command(cmd.command_type, "temp", slots[(num_args + cmd.arg2) as usize]));
+            },
+            CommandType::Label | CommandType::Goto | CommandType::If => {
+                out.push(command(cmd.command_type, &format!("{}${}", prefix, cmd.arg1), 0));
+            },
+            CommandType::Return => {
+                // the return value is already where the call would leave it
+                if i + 1 < callee.body.len() {
+                    out.push(command(CommandType::Goto, &end, 0));
+                    jumps_to_end = true;
+                }
+            },
+            _ => out.push(command(cmd.command_type, &cmd.arg1, cmd.arg2)),
+        }
+    }
+    if jumps_to_end {
+        out.push(command(CommandType::Label, &end, 0));
+    }
+}
+
+fn segment(cmd: &Command) -> Option<&str> {
+    match cmd.command_type {
+        CommandType::Push | CommandType::Pop => Some(&cmd.arg1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_writer::{Bootstrap, BootstrapMode};
+    use crate::parser;
+    use crate::program::{Options, Program};
+
+    // the functions still called after inlining the given files
+    fn calls_after_inlining(sources: &[(&str, &str)]) -> Vec<String> {
+        let mut files: Vec<VmFile> = sources.iter()
+            .map(|(name, source)| parser::parse_source(name, source, false).unwrap())
+            .collect();
+        inline(&mut files, DEFAULT_THRESHOLD);
+        files.iter()
+            .flat_map(|file| file.commands.iter())
+            .filter(|cmd| cmd.command_type == CommandType::Call)
+            .map(|cmd| cmd.arg1.clone())
+            .collect()
+    }
+
+    // Sys.init calling F.f with the arguments 1 .. num_args
+    fn caller(num_args: i16) -> String {
+        let mut source = String::from("function Sys.init 0\n");
+        for i in 1..=num_args {
+            source += &format!("push constant {}\n", i);
+        }
+        source + &format!("call F.f {}\nlabel END\ngoto END\n", num_args)
+    }
+
+    #[test]
+    fn leaf_functions_are_inlined() {
+        let callee = "function F.f 1\npush argument 0\npush argument 1\nadd\npop local 0\npush local 0\nreturn\n";
+        assert!(calls_after_inlining(&[("Sys", &caller(2)), ("F", callee)]).is_empty());
+    }
+
+    #[test]
+    fn recursive_and_calling_functions_are_not_inlined() {
+        let recursive = "function F.f 0\npush argument 0\nif-goto REC\npush constant 0\nreturn\n\
+                         label REC\npush constant 0\ncall F.f 1\nreturn\n";
+        assert_eq!(calls_after_inlining(&[("Sys", &caller(1)), ("F", recursive)]), ["F.f", "F.f"]);
+        let calling = "function F.f 0\npush argument 0\ncall F.g 1\nreturn\nfunction F.g 0\npush argument 0\nreturn\n";
+        assert_eq!(calls_after_inlining(&[("Sys", &caller(1)), ("F", calling)]), ["F.f"]);
+    }
+
+    #[test]
+    fn too_few_free_temp_slots() {
+        let callee = "function F.f 1\npush argument 0\npop local 0\npush local 0\nreturn\n";
+        // one argument and one local need two slots; temp 0..6 leave one
+        let mut busy = String::new();
+        for i in 0..7 {
+            busy += &format!("push constant 0\npop temp {}\n", i);
+        }
+        let sys = caller(1).replacen("\n", &format!("\n{}", busy), 1);
+        assert_eq!(calls_after_inlining(&[("Sys", &sys), ("F", callee)]), ["F.f"]);
+        let sys = caller(1).replacen("\n", &format!("\n{}", busy.replace("pop temp 6", "pop temp 5")), 1);
+        assert!(calls_after_inlining(&[("Sys", &sys), ("F", callee)]).is_empty());
+    }
+
+    #[test]
+    fn frame_dependent_functions_are_not_inlined() {
+        let callees = [
+            // pointer is restored by return
+            "function F.f 0\npush argument 0\npop pointer 0\npush constant 0\nreturn\n",
+            // an argument the call does not pass
+            "function F.f 0\npush argument 1\nreturn\n",
+            // a local the function does not declare
+            "function F.f 1\npush local 1\nreturn\n",
+            // two values on the stack at the return
+            "function F.f 0\npush argument 0\npush argument 0\nreturn\n",
+            // one path returns with nothing on the stack
+            "function F.f 0\npush argument 0\nif-goto EMPTY\npush constant 1\nreturn\nlabel EMPTY\nreturn\n",
+        ];
+        for callee in callees {
+            assert_eq!(calls_after_inlining(&[("Sys", &caller(1)), ("F", callee)]), ["F.f"], "{}", callee);
+        }
+        // static belongs to the file the function is in
+        let callee = "function F.f 0\npush static 0\nreturn\n";
+        assert_eq!(calls_after_inlining(&[("Sys", &caller(0)), ("F", callee)]), ["F.f"]);
+        let both = format!("{}{}", caller(0), callee);
+        assert!(calls_after_inlining(&[("Sys", &both)]).is_empty());
+    }
+
+    // the segments the .tst scripts set up and the heap; temp holds the inlined
+    // arguments and locals
+    const COMPARED: [(usize, usize); 3] = [(300, 310), (400, 410), (2048, 16384)];
+
+    #[test]
+    fn inlining_leaves_the_same_ram() {
+        let programs = [
+            "vm_code/MemoryAccess/BasicTest",
+            "vm_code/MemoryAccess/PointerTest",
+            "vm_code/StackArithmetic/StackTest",
+            "vm_code/FunctionCalls/FibonacciElement",
+            "vm_code/FunctionCalls/NestedCall",
+            "vm_code/FunctionCalls/StaticsTest",
+            "tests/programs/Segments",
+            "tests/programs/Inline",
+            "tests/programs/Jack",
+        ];
+        let mut inlined_calls = 0;
+        for dir in programs {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), dir);
+            let translate = |inline| {
+                let options = Options {
+                    inline,
+                    bootstrap_mode: BootstrapMode::Always,
+                    bootstrap: Bootstrap { lcl: Some(300), arg: Some(400), this: Some(3000), that: Some(3010), ..Bootstrap::default() },
+                    ..Options::default()
+                };
+                let program = Program::translate(&path, &options).unwrap();
+                let mut emulator = program.emulator(&options);
+                assert!(emulator.run(10_000_000).unwrap(), "{} did not halt", dir);
+                (program, emulator.ram)
+            };
+            let calls = |program: &Program| program.files.iter()
+                .flat_map(|file| file.commands.iter())
+                .filter(|cmd| cmd.command_type == CommandType::Call)
+                .count();
+            let (program, expected) = translate(false);
+            let (inlined, ram) = translate(true);
+            inlined_calls += calls(&program) - calls(&inlined);
+
+            // the assembler allocates the statics in the order it meets them,
+            // which inlining changes, so they are compared by name
+            for file in program.files.iter() {
+                for cmd in file.commands.iter().filter(|cmd| segment(cmd) == Some("static")) {
+                    let name = format!("{}.{}", file.name, cmd.arg2);
+                    let (address, expected_address) = (inlined.symbols[&name], program.symbols[&name]);
+                    assert_eq!(ram[address as usize], expected[expected_address as usize], "{}: {}", dir, name);
+                }
+            }
+            // Sys.halt is a leaf too, so a program that halts there stops one
+            // frame lower inlined; otherwise the registers and the stack end the
+            // same, as the frames left on it are from before any inlined code
+            let halts_in_sys_halt = dir.ends_with("Jack");
+            let stack = if halts_in_sys_halt { 0..0 } else { 256..expected[0] as usize };
+            let registers = if halts_in_sys_halt { 0..0 } else { 0..5 };
+            let addresses = COMPARED.iter().flat_map(|&(start, end)| start..end).chain(stack).chain(registers);
+            for address in addresses {
+                assert_eq!(ram[address], expected[address], "{}: RAM[{}]", dir, address);
+            }
+        }
+        assert!(inlined_calls > 5);
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use crate::builtins::{Builtins, Native};
+use crate::emulator::RAM_SIZE;
+use crate::extensions;
+use crate::parser::{Command, CommandType, VmFile};
+
+// VM interpreter over parser output, used by vmdbg.
+//
+// Memory is laid out as in the translated program: SP, LCL, ARG, THIS and THAT in
+// RAM[0..5], temp in RAM[5..13] and statics from RAM[16], allocated in order of
+// first use like the assembler does. Calls build the same frame as CodeWriter,
+// with the index of the command after the call as return address, so programs
+// that inspect or patch their frames behave as on the Hack platform.
+//
+// With built-ins, a call to a built-in function runs it natively as a single
+// step, without a frame.
+//
+// The program halts when it runs past its last command, returns from the entry
+// function or reaches `label L` directly followed by `goto L`.
+
+pub const DEFAULT_SP: i16 = 256;
+
+// return address of the entry function's frame
+const HALT: i16 = -1;
+
+// a command of the program with the file it comes from
+pub struct Instruction {
+    pub file: usize,
+    pub command: Command,
+}
+
+// a call in progress, kept for backtraces and stepping; the VM state itself only
+// lives in RAM
+pub struct Frame {
+    pub function: String,
+    pub call_site: Option<usize>, // None for the entry function
+    pub num_args: i16,
+    pub num_locals: i16,
+}
+
+pub struct Interpreter {
+    pub files: Vec<String>,
+    pub program: Vec<Instruction>,
+    pub ram: Vec<i16>,
+    pub pc: usize,
+    pub frames: Vec<Frame>,
+    pub steps: u64, // commands executed so far
+    functions: HashMap<String, usize>,
+    statics: HashMap<(usize, i16), usize>,
+    jumps: Vec<usize>, // target of each goto, if-goto and call
+    builtins: Option<Builtins>,
+    natives: Vec<Option<Native>>, // the built-in each call runs
+    halted: bool,
+}
+
+impl Interpreter {
+    // resolves every label, function and static up front, so that running the
+    // program cannot fail on a missing name
+    pub fn new(files: &[VmFile]) -> Result<Self, String> {
+        let mut program = vec![];
+        for (i, file) in files.iter().enumerate() {
+            for command in file.commands.iter() {
+                program.push(Instruction { file: i, command: command.clone() });
+            }
+        }
+        if program.len() > i16::MAX as usize {
+            return Err(format!("program has {} commands but return addresses hold at most {}", program.len(), i16::MAX));
+        }
+
+        let mut functions = HashMap::new();
+        let mut labels = HashMap::new(); // (enclosing function, label)
+        let mut statics = HashMap::new();
+        let mut function = String::new();
+        for (pc, instruction) in program.iter().enumerate() {
+            let command = &instruction.command;
+            match command.command_type {
+                CommandType::Function => {
+                    function = command.arg1.clone();
+                    functions.insert(function.clone(), pc);
+                },
+                CommandType::Label => {
+                    labels.insert((function.clone(), command.arg1.clone()), pc);
+                },
+                CommandType::Push | CommandType::Pop if command.arg1 == "static" => {
+                    let next = 16 + statics.len();
+                    statics.entry((instruction.file, command.arg2)).or_insert(next);
+                },
+                _ => {},
+            }
+        }
+
+        let mut jumps = vec![0; program.len()];
+        let mut function = String::new();
+        for (pc, instruction) in program.iter().enumerate() {
+            let command = &instruction.command;
+            let target = match command.command_type {
+                CommandType::Function => {
+                    function = command.arg1.clone();
+                    continue;
+                },
+                CommandType::Goto | CommandType::If => labels.get(&(function.clone(), command.arg1.clone())),
+                CommandType::Call => functions.get(&command.arg1),
+                _ => continue,
+            };
+            jumps[pc] = match target {
+                Some(&target) => target,
+                None => return Err(format!("{}.vm:{}: '{}' is not defined",
+                    files[instruction.file].name, command.line, command.arg1)),
+            };
+        }
+
+        Ok(Interpreter {
+            files: files.iter().map(|file| file.name.clone()).collect(),
+            program,
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            frames: vec![],
+            steps: 0,
+            functions,
+            statics,
+            jumps,
+            builtins: None,
+            natives: vec![],
+            halted: false,
+        })
+    }
+
+    // runs the built-in functions natively from now on; statics are resolved by
+    // the name of their file
+    pub fn set_builtins(&mut self, bundled: &[String]) {
+        let builtins = Builtins::new(bundled, |class, index| {
+            let file = self.files.iter().position(|name| name == class)?;
+            self.statics.get(&(file, index)).copied()
+        });
+        self.natives = self.program.iter()
+            .map(|instruction| match instruction.command.command_type {
+                CommandType::Call => builtins.get(&instruction.command.arg1),
+                _ => None,
+            })
+            .collect();
+        self.builtins = Some(builtins);
+    }
+
+    // sets SP and calls entry, like the bootstrap code; without entry, execution
+    // starts at the first command
+    pub fn bootstrap(&mut self, sp: i16, entry: Option<&str>) -> Result<(), String> {
+        self.ram[0] = sp;
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let target = match self.functions.get(entry) {
+            Some(&target) => target,
+            None => return Err(format!("function '{}' is not defined", entry)),
+        };
+        self.push_frame(HALT, 0)?;
+        self.frames.push(Frame {
+            function: entry.to_string(),
+            call_site: None,
+            num_args: 0,
+            num_locals: 0,
+        });
+        self.pc = target;
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.get(name).copied()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted || self.pc >= self.program.len()
+    }
+
+    pub fn current(&self) -> Option<&Instruction> {
+        if self.is_halted() {
+            None
+        } else {
+            self.program.get(self.pc)
+        }
+    }
+
+    // "File.vm:line" of the command at pc
+    pub fn location(&self, pc: usize) -> String {
+        match self.program.get(pc) {
+            Some(instruction) => format!("{}.vm:{}", self.files[instruction.file], instruction.command.line),
+            None => String::from("end of program"),
+        }
+    }
+
+    // RAM address of segment[index] for the current command; constant has none
+    pub fn address(&self, segment: &str, index: i16) -> Result<usize, String> {
+        let base = |register: usize| self.ram[register] as u16 as usize;
+        let address = match segment {
+            "local" => base(1) + index as usize,
+            "argument" => base(2) + index as usize,
+            "this" => base(3) + index as usize,
+            "that" => base(4) + index as usize,
+            "pointer" => 3 + index as usize,
+            "temp" => 5 + index as usize,
+            "static" => {
+                let file = self.program.get(self.pc).map_or(0, |instruction| instruction.file);
+                match self.statics.get(&(file, index)) {
+                    Some(&address) => address,
+                    None => return Err(format!("static {} is not used by {}.vm", index, self.files[file])),
+                }
+            },
+            _ => return Err(format!("segment '{}' has no address", segment)),
+        };
+        if address >= RAM_SIZE {
+            return Err(format!("{} {} is at address {}, outside the RAM", segment, index, address));
+        }
+        Ok(address)
+    }
+
+    // the statics of a file, as (index, address) in index order
+    pub fn statics(&self, file: usize) -> Vec<(i16, usize)> {
+        let mut statics: Vec<(i16, usize)> = self.statics.iter()
+            .filter(|((f, _), _)| *f == file)
+            .map(|(&(_, index), &address)| (index, address))
+            .collect();
+        statics.sort();
+        statics
+    }
+
+    // executes the command at pc
+    pub fn step(&mut self) -> Result<(), String> {
+        if self.is_halted() {
+            return Err(String::from("the program has halted"));
+        }
+        let command = self.program[self.pc].command.clone();
+        self.steps += 1;
+        let mut next = self.pc + 1;
+        match command.command_type {
+            CommandType::Arithmetic => {
+                let op = command.arg1.as_str();
+                if op == "neg" || op == "not" {
+                    let x = self.pop()?;
+                    self.push(if op == "neg" { x.wrapping_neg() } else { !x })?;
+                } else {
+                    let y = self.pop()?;
+                    let x = self.pop()?;
+                    let result = match op {
+                        "add" => x.wrapping_add(y),
+                        "sub" => x.wrapping_sub(y),
+                        "and" => x & y,
+                        "or" => x | y,
+                        // true is -1, false is 0; gt and lt compare the wrapped
+                        // 16-bit difference with 0, as the Hack ALU does
+                        "eq" => -((x == y) as i16),
+                        "gt" => -((x.wrapping_sub(y) > 0) as i16),
+                        "lt" => -((x.wrapping_sub(y) < 0) as i16),
+                        _ => extensions::evaluate(op, x, y),
+                    };
+                    self.push(result)?;
+                }
+            },
+            CommandType::Push => {
+                let value = match command.arg1.as_str() {
+                    "constant" => command.arg2,
+                    segment => self.ram[self.address(segment, command.arg2)?],
+                };
+                self.push(value)?;
+            },
+            CommandType::Pop => {
+                let address = self.address(&command.arg1, command.arg2)?;
+                self.ram[address] = self.pop()?;
+            },
+            CommandType::Label => {},
+            CommandType::Goto => {
+                next = self.jumps[self.pc];
+                if next + 1 == self.pc {
+                    self.halted = true;
+                    next = self.pc;
+                }
+            },
+            CommandType::If => {
+                if self.pop()? != 0 {
+                    next = self.jumps[self.pc];
+                }
+            },
+            CommandType::Function => {
+                // falling into a function outside any call starts at the top level
+                match self.frames.last_mut() {
+                    Some(frame) if frame.function == command.arg1 => frame.num_locals = command.arg2,
+                    _ => self.frames.push(Frame {
+                        function: command.arg1.clone(),
+                        call_site: None,
+                        num_args: 0,
+                        num_locals: command.arg2,
+                    }),
+                }
+                for _ in 0..command.arg2 {
+                    self.push(0)?;
+                }
+            },
+            CommandType::Call if self.call_native(self.pc) => {},
+            CommandType::Call => {
+                self.push_frame(next as i16, command.arg2)?;
+                self.frames.push(Frame {
+                    function: command.arg1.clone(),
+                    call_site: Some(self.pc),
+                    num_args: command.arg2,
+                    num_locals: 0,
+                });
+                next = self.jumps[self.pc];
+            },
+            CommandType::Return => {
+                let frame = self.ram[1] as u16 as usize;
+                let read = |ram: &[i16], offset: usize| match frame.checked_sub(offset) {
+                    Some(address) => Ok(ram[address]),
+                    None => Err(format!("LCL is {}, too low for a frame", frame)),
+                };
+                let return_address = read(&self.ram, 5)?;
+                let value = self.pop()?;
+                let arg = self.address("argument", 0)?;
+                self.ram[arg] = value;
+                self.ram[0] = arg as i16 + 1;
+                self.ram[4] = read(&self.ram, 1)?;
+                self.ram[3] = read(&self.ram, 2)?;
+                self.ram[2] = read(&self.ram, 3)?;
+                self.ram[1] = read(&self.ram, 4)?;
+                self.frames.pop();
+                if return_address < 0 || return_address as usize > self.program.len() {
+                    self.halted = true;
+                    next = self.pc;
+                } else {
+                    next = return_address as usize;
+                }
+            },
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    // runs the built-in the call at pc makes, replacing its arguments on the
+    // stack with the value; false, with nothing changed, when there is none or
+    // the function's code has to run
+    fn call_native(&mut self, pc: usize) -> bool {
+        let (native, builtins) = match (self.natives.get(pc), &self.builtins) {
+            (Some(&Some(native)), Some(builtins)) => (native, builtins),
+            _ => return false,
+        };
+        let sp = self.ram[0] as u16 as usize;
+        if sp < native.arity || sp >= RAM_SIZE {
+            return false;
+        }
+        let args = self.ram[sp - native.arity..sp].to_vec();
+        match builtins.call(native, &mut self.ram, &args) {
+            Some(value) => {
+                self.ram[sp - native.arity] = value;
+                self.ram[0] = (sp - native.arity) as i16 + 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // pushes the return address and the caller's LCL, ARG, THIS and THAT, then
+    // points ARG at the arguments and LCL at the new frame
+    fn push_frame(&mut self, return_address: i16, num_args: i16) -> Result<(), String> {
+        self.push(return_address)?;
+        for register in 1..5 {
+            self.push(self.ram[register])?;
+        }
+        self.ram[2] = self.ram[0] - 5 - num_args;
+        self.ram[1] = self.ram[0];
+        Ok(())
+    }
+
+    fn push(&mut self, value: i16) -> Result<(), String> {
+        let sp = self.ram[0];
+        if sp < 0 || sp as usize >= RAM_SIZE {
+            return Err(format!("SP is {}, outside the RAM", sp));
+        }
+        self.ram[sp as usize] = value;
+        self.ram[0] = sp + 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i16, String> {
+        let sp = self.ram[0];
+        if sp <= 0 || sp as usize > RAM_SIZE {
+            return Err(format!("SP is {}, cannot pop", sp));
+        }
+        self.ram[0] = sp - 1;
+        Ok(self.ram[sp as usize - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{self, Cpu};
+    use crate::backend;
+    use crate::code_writer::CodeWriter;
+    use crate::emulator::Emulator;
+    use crate::parser;
+
+    // push x: constants are non-negative, so negative ones are 0 - |x|
+    fn push(x: i16) -> String {
+        match x {
+            i16::MIN => String::from("push constant 0\npush constant 32767\nsub\npush constant 1\nsub\n"),
+            x if x < 0 => format!("push constant 0\npush constant {}\nsub\n", -x),
+            x => format!("push constant {}\n", x),
+        }
+    }
+
+    // the top of the stack after interpreting the commands
+    fn interpret(source: &str) -> i16 {
+        let file = parser::parse_source("Test", source, false).unwrap();
+        let mut vm = Interpreter::new(&[file]).unwrap();
+        vm.bootstrap(DEFAULT_SP, None).unwrap();
+        while !vm.is_halted() {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.ram[0], DEFAULT_SP + 1);
+        vm.ram[DEFAULT_SP as usize]
+    }
+
+    // the top of the stack after running the translated commands
+    fn emulate(source: &str) -> i16 {
+        let file = parser::parse_source("Test", source, false).unwrap();
+        let mut w = CodeWriter::new(vec![]);
+        backend::translate(&mut w, &[file], None, false).unwrap();
+        let asm = String::from_utf8(w.into_inner()).unwrap();
+        let mut emulator = Emulator::new(assembler::assemble(&asm, Cpu::Standard).unwrap(), Cpu::Standard);
+        emulator.ram[0] = DEFAULT_SP;
+        assert!(emulator.run(100_000).unwrap());
+        emulator.ram[DEFAULT_SP as usize]
+    }
+
+    // gt and lt look at the sign of x - y, which wraps for operands far apart
+    #[test]
+    fn comparisons_wrap_as_on_hack() {
+        assert_eq!(interpret(&format!("{}{}gt\n", push(20000), push(-20000))), 0);
+        assert_eq!(interpret(&format!("{}{}lt\n", push(20000), push(-20000))), -1);
+        assert_eq!(interpret(&format!("{}{}gt\n", push(i16::MIN), push(1))), -1);
+
+        let values = [0, 1, -1, 2, -2, 16384, -16384, 20000, -20000, i16::MAX, i16::MIN];
+        for x in values {
+            for y in values {
+                for op in ["eq", "gt", "lt"] {
+                    let source = format!("{}{}{}\n", push(x), push(y), op);
+                    assert_eq!(interpret(&source), emulate(&source), "{} {} {}", x, op, y);
+                }
+            }
+        }
+    }
+}
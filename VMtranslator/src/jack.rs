@@ -0,0 +1,29 @@
+use crate::parser::VmFile;
+
+// Jack compiler front end: a .jack class is tokenized, parsed and compiled
+// straight to the commands a .vm file would hold, so that read_program takes Jack
+// classes alongside VM code and a directory of them goes to Hack in one step.
+//
+// The commands carry the lines of the Jack source they were compiled from.
+
+pub mod codegen;
+pub mod parser;
+pub mod tokenizer;
+
+// a message and the line of the source it is about
+pub type Error = (usize, String);
+
+// compiles the class in source, which must be the class name
+pub fn compile(name: &str, source: &str) -> Result<VmFile, String> {
+    let error = |(line, message): Error| format!("{}.jack:{}: {}", name, line, message);
+    let tokens = tokenizer::tokenize(source).map_err(error)?;
+    let class = parser::parse(&tokens).map_err(error)?;
+    if class.name != name {
+        return Err(error((class.line, format!("class '{}' must be in a file named {}.jack", class.name, class.name))));
+    }
+    let commands = codegen::generate(&class).map_err(error)?;
+    Ok(VmFile {
+        name: name.to_string(),
+        commands,
+    })
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use crate::parser::{Command, CommandType};
+use super::parser::{Call, Class, Expression, Statement, Subroutine, SubroutineKind, Term, VarKind};
+use super::Error;
+
+// VM code for a Jack class, in the usual layout: statics and fields by order of
+// declaration, the object in pointer 0 (this), arrays through pointer 1 (that),
+// and labels IF_ELSEn, IF_ENDn, WHILEn and WHILE_ENDn numbered per subroutine.
+//
+// A call f(...) without a receiver calls the class's function f if there is
+// one, and otherwise method f on this.
+
+// a variable: its type, segment and index
+#[derive(Clone)]
+struct Variable {
+    var_type: String,
+    segment: &'static str,
+    index: i16,
+}
+
+pub fn generate(class: &Class) -> Result<Vec<Command>, Error> {
+    let mut class_vars = HashMap::new();
+    let (mut statics, mut fields) = (0, 0);
+    for var in class.vars.iter() {
+        for name in var.names.iter() {
+            let (segment, count) = match var.kind {
+                VarKind::Static => ("static", &mut statics),
+                VarKind::Field => ("this", &mut fields),
+            };
+            let variable = Variable { var_type: var.var_type.clone(), segment, index: *count };
+            if class_vars.insert(name.clone(), variable).is_some() {
+                return Err((var.line, format!("'{}' is declared twice", name)));
+            }
+            *count += 1;
+        }
+    }
+    let mut functions = HashMap::new();
+    for subroutine in class.subroutines.iter() {
+        if functions.insert(subroutine.name.clone(), subroutine.kind).is_some() {
+            return Err((subroutine.line, format!("subroutine '{}' is declared twice", subroutine.name)));
+        }
+    }
+
+    let mut generator = Generator {
+        class: &class.name,
+        class_vars,
+        fields,
+        functions,
+        vars: HashMap::new(),
+        kind: SubroutineKind::Function,
+        labels: 0,
+        line: 0,
+        commands: vec![],
+    };
+    for subroutine in class.subroutines.iter() {
+        generator.subroutine(subroutine)?;
+    }
+    Ok(generator.commands)
+}
+
+struct Generator<'a> {
+    class: &'a str,
+    class_vars: HashMap<String, Variable>,
+    fields: i16,
+    functions: HashMap<String, SubroutineKind>, // the class's subroutines
+    vars: HashMap<String, Variable>,            // arguments and locals
+    kind: SubroutineKind,                       // of the subroutine being compiled
+    labels: usize,
+    line: usize, // of the statement being compiled
+    commands: Vec<Command>,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, command_type: CommandType, arg1: &str, arg2: i16) {
+        self.commands.push(Command { command_type, arg1: arg1.to_string(), arg2, line: self.line });
+    }
+
+    fn arithmetic(&mut self, operator: &str) {
+        self.emit(CommandType::Arithmetic, operator, 0);
+    }
+
+    fn push(&mut self, segment: &str, index: i16) {
+        self.emit(CommandType::Push, segment, index);
+    }
+
+    fn pop(&mut self, segment: &str, index: i16) {
+        self.emit(CommandType::Pop, segment, index);
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, Error> {
+        Err((self.line, message))
+    }
+
+    fn lookup(&self, name: &str) -> Result<Variable, Error> {
+        let variable = match self.vars.get(name).or_else(|| self.class_vars.get(name)) {
+            Some(variable) => variable.clone(),
+            None => return self.error(format!("'{}' is not defined", name)),
+        };
+        if variable.segment == "this" && self.kind == SubroutineKind::Function {
+            return self.error(format!("field '{}' is used in a function", name));
+        }
+        Ok(variable)
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) -> Result<(), Error> {
+        self.line = subroutine.line;
+        self.kind = subroutine.kind;
+        self.labels = 0;
+        self.vars.clear();
+        let declare = |vars: &mut HashMap<String, Variable>, (var_type, name): &(String, String), segment, index| {
+            let variable = Variable { var_type: var_type.clone(), segment, index };
+            match vars.insert(name.clone(), variable) {
+                Some(_) => Err((subroutine.line, format!("'{}' is declared twice", name))),
+                None => Ok(()),
+            }
+        };
+        // a method's object is argument 0
+        let first = (subroutine.kind == SubroutineKind::Method) as i16;
+        for (i, parameter) in subroutine.parameters.iter().enumerate() {
+            declare(&mut self.vars, parameter, "argument", first + i as i16)?;
+        }
+        for (i, local) in subroutine.locals.iter().enumerate() {
+            declare(&mut self.vars, local, "local", i as i16)?;
+        }
+
+        let name = format!("{}.{}", self.class, subroutine.name);
+        self.emit(CommandType::Function, &name, subroutine.locals.len() as i16);
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                self.push("constant", self.fields);
+                self.emit(CommandType::Call, "Memory.alloc", 1);
+                self.pop("pointer", 0);
+            },
+            SubroutineKind::Method => {
+                self.push("argument", 0);
+                self.pop("pointer", 0);
+            },
+            SubroutineKind::Function => {},
+        }
+        self.statements(&subroutine.body)
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), Error> {
+        for statement in statements.iter() {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::Let { line, name, index, value } => {
+                self.line = *line;
+                let variable = self.lookup(name)?;
+                match index {
+                    Some(index) => {
+                        self.push(variable.segment, variable.index);
+                        self.expression(index)?;
+                        self.arithmetic("add");
+                        self.expression(value)?;
+                        // the value may itself use that, so the address waits in temp 0
+                        self.pop("temp", 0);
+                        self.pop("pointer", 1);
+                        self.push("temp", 0);
+                        self.pop("that", 0);
+                    },
+                    None => {
+                        self.expression(value)?;
+                        self.pop(variable.segment, variable.index);
+                    },
+                }
+            },
+            Statement::If { line, condition, then, otherwise } => {
+                self.line = *line;
+                let n = self.label();
+                self.expression(condition)?;
+                self.arithmetic("not");
+                self.emit(CommandType::If, &format!("IF_ELSE{}", n), 0);
+                self.statements(then)?;
+                self.line = *line;
+                match otherwise {
+                    Some(otherwise) => {
+                        self.emit(CommandType::Goto, &format!("IF_END{}", n), 0);
+                        self.emit(CommandType::Label, &format!("IF_ELSE{}", n), 0);
+                        self.statements(otherwise)?;
+                        self.line = *line;
+                        self.emit(CommandType::Label, &format!("IF_END{}", n), 0);
+                    },
+                    None => self.emit(CommandType::Label, &format!("IF_ELSE{}", n), 0),
+                }
+            },
+            Statement::While { line, condition, body } => {
+                self.line = *line;
+                let n = self.label();
+                self.emit(CommandType::Label, &format!("WHILE{}", n), 0);
+                self.expression(condition)?;
+                self.arithmetic("not");
+                self.emit(CommandType::If, &format!("WHILE_END{}", n), 0);
+                self.statements(body)?;
+                self.line = *line;
+                self.emit(CommandType::Goto, &format!("WHILE{}", n), 0);
+                self.emit(CommandType::Label, &format!("WHILE_END{}", n), 0);
+            },
+            Statement::Do { line, call } => {
+                self.line = *line;
+                self.call(call)?;
+                self.pop("temp", 0);
+            },
+            Statement::Return { line, value } => {
+                self.line = *line;
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.push("constant", 0),
+                }
+                self.emit(CommandType::Return, "", 0);
+            },
+        }
+        Ok(())
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), Error> {
+        self.term(&expression.term)?;
+        for (op, term) in expression.operations.iter() {
+            self.term(term)?;
+            match op {
+                '*' => self.emit(CommandType::Call, "Math.multiply", 2),
+                '/' => self.emit(CommandType::Call, "Math.divide", 2),
+                '+' => self.arithmetic("add"),
+                '-' => self.arithmetic("sub"),
+                '&' => self.arithmetic("and"),
+                '|' => self.arithmetic("or"),
+                '<' => self.arithmetic("lt"),
+                '>' => self.arithmetic("gt"),
+                _ => self.arithmetic("eq"),
+            }
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, term: &Term) -> Result<(), Error> {
+        match term {
+            Term::Integer(n) => self.push("constant", *n),
+            Term::Str(s) => {
+                self.push("constant", s.len() as i16);
+                self.emit(CommandType::Call, "String.new", 1);
+                for c in s.chars() {
+                    self.push("constant", c as i16);
+                    self.emit(CommandType::Call, "String.appendChar", 2);
+                }
+            },
+            Term::True => {
+                self.push("constant", 0);
+                self.arithmetic("not");
+            },
+            Term::False | Term::Null => self.push("constant", 0),
+            Term::This => {
+                if self.kind == SubroutineKind::Function {
+                    return self.error(String::from("'this' is used in a function"));
+                }
+                self.push("pointer", 0);
+            },
+            Term::Variable(name) => {
+                let variable = self.lookup(name)?;
+                self.push(variable.segment, variable.index);
+            },
+            Term::Index(name, index) => {
+                let variable = self.lookup(name)?;
+                self.push(variable.segment, variable.index);
+                self.expression(index)?;
+                self.arithmetic("add");
+                self.pop("pointer", 1);
+                self.push("that", 0);
+            },
+            Term::Call(call) => self.call(call)?,
+            Term::Parenthesized(expression) => self.expression(expression)?,
+            Term::Unary(op, term) => {
+                self.term(term)?;
+                self.arithmetic(if *op == '-' { "neg" } else { "not" });
+            },
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, call: &Call) -> Result<(), Error> {
+        let (function, object) = match &call.receiver {
+            None => match self.functions.get(&call.name) {
+                Some(SubroutineKind::Method) | None => {
+                    if self.kind == SubroutineKind::Function {
+                        return self.error(format!("method '{}' is called from a function", call.name));
+                    }
+                    self.push("pointer", 0);
+                    (format!("{}.{}", self.class, call.name), true)
+                },
+                Some(_) => (format!("{}.{}", self.class, call.name), false),
+            },
+            Some(receiver) => match self.vars.get(receiver).or_else(|| self.class_vars.get(receiver)) {
+                Some(_) => {
+                    let variable = self.lookup(receiver)?;
+                    if matches!(variable.var_type.as_str(), "int" | "char" | "boolean") {
+                        return self.error(format!("'{}' is {} {}, not an object", receiver,
+                            if variable.var_type == "int" { "an" } else { "a" }, variable.var_type));
+                    }
+                    self.push(variable.segment, variable.index);
+                    (format!("{}.{}", variable.var_type, call.name), true)
+                },
+                // a class name
+                None => (format!("{}.{}", receiver, call.name), false),
+            },
+        };
+        for argument in call.arguments.iter() {
+            self.expression(argument)?;
+        }
+        self.emit(CommandType::Call, &function, call.arguments.len() as i16 + object as i16);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jack::{parser, tokenizer};
+
+    // the commands of the class as VM code, or the error with its line
+    fn compile(source: &str) -> Result<Vec<String>, Error> {
+        let class = parser::parse(&tokenizer::tokenize(source).unwrap()).unwrap();
+        generate(&class).map(|commands| commands.iter().map(|cmd| cmd.to_string()).collect())
+    }
+
+    #[test]
+    fn array_assignment_keeps_the_address_in_temp() {
+        let commands = compile("class A { function void f(Array a, int i) { let a[i] = a[i + 1]; return; } }").unwrap();
+        assert_eq!(commands, [
+            "function A.f 0",
+            "push argument 0", "push argument 1", "add",
+            "push argument 0", "push argument 1", "push constant 1", "add", "add",
+            "pop pointer 1", "push that 0",
+            "pop temp 0", "pop pointer 1", "push temp 0", "pop that 0",
+            "push constant 0", "return",
+        ]);
+    }
+
+    #[test]
+    fn constructors_allocate_the_fields() {
+        let commands = compile("class P { field int x, y; static int n;
+            constructor P new(int ax) { let x = ax; let n = n + 1; return this; } }").unwrap();
+        assert_eq!(commands, [
+            "function P.new 0",
+            "push constant 2", "call Memory.alloc 1", "pop pointer 0",
+            "push argument 0", "pop this 0",
+            "push static 0", "push constant 1", "add", "pop static 0",
+            "push pointer 0", "return",
+        ]);
+    }
+
+    #[test]
+    fn calls_dispatch_on_the_receiver() {
+        let commands = compile("class C { field int v; field Point p;
+            method int get() { return v; }
+            function int twice(int x) { return x + x; }
+            method void f(C c) {
+                do get(); do twice(1); do c.get(); do p.move(2); do Math.abs(3);
+                return;
+            } }").unwrap();
+        assert_eq!(commands[0..4], ["function C.get 0", "push argument 0", "pop pointer 0", "push this 0"]);
+        let f = commands.iter().position(|cmd| cmd == "function C.f 0").unwrap();
+        assert_eq!(commands[f + 3..commands.len() - 2], [
+            "push pointer 0", "call C.get 1", "pop temp 0",
+            "push constant 1", "call C.twice 1", "pop temp 0",
+            "push argument 1", "call C.get 1", "pop temp 0",
+            "push this 1", "push constant 2", "call Point.move 2", "pop temp 0",
+            "push constant 3", "call Math.abs 1", "pop temp 0",
+        ]);
+    }
+
+    #[test]
+    fn labels_are_numbered_per_subroutine() {
+        let commands = compile("class L {
+            function void f(int x) { while (x) { if (x) { let x = 0; } else { let x = 1; } } if (x) { } return; }
+            function void g() { if (true) { } return; } }").unwrap();
+        let labels: Vec<&str> = commands.iter()
+            .filter(|cmd| cmd.starts_with("label") || cmd.contains("goto"))
+            .map(|cmd| cmd.as_str())
+            .collect();
+        assert_eq!(labels, [
+            "label WHILE0", "if-goto WHILE_END0",
+            "if-goto IF_ELSE1", "goto IF_END1", "label IF_ELSE1", "label IF_END1",
+            "goto WHILE0", "label WHILE_END0",
+            "if-goto IF_ELSE2", "label IF_ELSE2",
+            "if-goto IF_ELSE0", "label IF_ELSE0",
+        ]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |source: &str| compile(source).unwrap_err();
+        assert_eq!(error("class E {\n field int x;\n static int x; }"), (3, String::from("'x' is declared twice")));
+        assert_eq!(error("class E {\n function void f(int a) {\n var int a; return; } }"), (2, String::from("'a' is declared twice")));
+        assert_eq!(error("class E { function void f() {}\n method void f() {} }"), (2, String::from("subroutine 'f' is declared twice")));
+        assert_eq!(error("class E { field int x;\n function int f() {\n return x; } }"), (3, String::from("field 'x' is used in a function")));
+        assert_eq!(error("class E { function E f() {\n return this; } }"), (2, String::from("'this' is used in a function")));
+        assert_eq!(error("class E { method void m() { return; }\n function void f() {\n do m(); return; } }"), (3, String::from("method 'm' is called from a function")));
+        assert_eq!(error("class E { function void f(int n) {\n do n.go(); return; } }"), (2, String::from("'n' is an int, not an object")));
+        assert_eq!(error("class E { function void f() {\n let y = 1; return; } }"), (2, String::from("'y' is not defined")));
+    }
+}
@@ -0,0 +1,383 @@
+use super::tokenizer::Token;
+use super::Error;
+
+// The syntax tree of a Jack class. Statements keep the line they start on,
+// which the code generator gives to the commands it emits for them.
+
+pub struct Class {
+    pub name: String,
+    pub line: usize,
+    pub vars: Vec<ClassVar>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VarKind {
+    Static,
+    Field,
+}
+
+pub struct ClassVar {
+    pub kind: VarKind,
+    pub var_type: String,
+    pub names: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    pub return_type: String,
+    pub name: String,
+    pub parameters: Vec<(String, String)>, // type and name
+    pub locals: Vec<(String, String)>,
+    pub body: Vec<Statement>,
+    pub line: usize,
+}
+
+pub enum Statement {
+    Let { line: usize, name: String, index: Option<Expression>, value: Expression },
+    If { line: usize, condition: Expression, then: Vec<Statement>, otherwise: Option<Vec<Statement>> },
+    While { line: usize, condition: Expression, body: Vec<Statement> },
+    Do { line: usize, call: Call },
+    Return { line: usize, value: Option<Expression> },
+}
+
+// a term followed by binary operations, applied left to right as Jack has no
+// precedence
+pub struct Expression {
+    pub term: Term,
+    pub operations: Vec<(char, Term)>,
+}
+
+pub enum Term {
+    Integer(i16),
+    Str(String),
+    True,
+    False,
+    Null,
+    This,
+    Variable(String),
+    Index(String, Box<Expression>),
+    Call(Call),
+    Parenthesized(Box<Expression>),
+    Unary(char, Box<Term>), // '-' or '~'
+}
+
+// f(...), x.f(...) or Class.f(...)
+pub struct Call {
+    pub receiver: Option<String>,
+    pub name: String,
+    pub arguments: Vec<Expression>,
+}
+
+const OPERATORS: &str = "+-*/&|<>=";
+
+pub fn parse(tokens: &[(Token, usize)]) -> Result<Class, Error> {
+    let mut parser = Parser { tokens, position: 0 };
+    let class = parser.class()?;
+    match parser.peek() {
+        None => Ok(class),
+        Some(token) => Err((parser.line(), format!("unexpected {} after the class", token))),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    // line of the next token, or of the last one at the end
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position).or(self.tokens.last()) {
+            Some(&(_, line)) => line,
+            None => 1,
+        }
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, Error> {
+        match self.tokens.get(self.position) {
+            Some((token, _)) => {
+                self.position += 1;
+                Ok(token.clone())
+            },
+            None => Err((self.line(), format!("expected {} but the file ends", expected))),
+        }
+    }
+
+    fn unexpected<T>(&self, token: &Token, expected: &str) -> Result<T, Error> {
+        Err((self.line(), format!("expected {} but got {}", expected, token)))
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Keyword(k)) if *k == keyword)
+    }
+
+    fn symbol(&mut self, symbol: char) -> Result<(), Error> {
+        let expected = format!("'{}'", symbol);
+        match self.next(&expected)? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, &expected)
+            },
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        let expected = format!("'{}'", keyword);
+        match self.next(&expected)? {
+            Token::Keyword(k) if k == keyword => Ok(()),
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, &expected)
+            },
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<String, Error> {
+        match self.next(what)? {
+            Token::Identifier(name) => Ok(name),
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, what)
+            },
+        }
+    }
+
+    // int, char, boolean or a class name, and void where allowed
+    fn var_type(&mut self, void: bool) -> Result<String, Error> {
+        let expected = if void { "a type or 'void'" } else { "a type" };
+        match self.next(expected)? {
+            Token::Keyword(k) if matches!(k, "int" | "char" | "boolean") || (void && k == "void") => Ok(k.to_string()),
+            Token::Identifier(name) => Ok(name),
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, expected)
+            },
+        }
+    }
+
+    fn class(&mut self) -> Result<Class, Error> {
+        let line = self.line();
+        self.keyword("class")?;
+        let name = self.identifier("a class name")?;
+        self.symbol('{')?;
+        let mut vars = vec![];
+        while self.is_keyword("static") || self.is_keyword("field") {
+            let line = self.line();
+            let kind = if self.is_keyword("static") { VarKind::Static } else { VarKind::Field };
+            self.position += 1;
+            let var_type = self.var_type(false)?;
+            let names = self.names()?;
+            vars.push(ClassVar { kind, var_type, names, line });
+        }
+        let mut subroutines = vec![];
+        while !self.is_symbol('}') {
+            subroutines.push(self.subroutine()?);
+        }
+        self.symbol('}')?;
+        Ok(Class { name, line, vars, subroutines })
+    }
+
+    // name {, name} ;
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        let mut names = vec![self.identifier("a variable name")?];
+        while self.is_symbol(',') {
+            self.position += 1;
+            names.push(self.identifier("a variable name")?);
+        }
+        self.symbol(';')?;
+        Ok(names)
+    }
+
+    fn subroutine(&mut self) -> Result<Subroutine, Error> {
+        let line = self.line();
+        let kind = match self.next("a subroutine")? {
+            Token::Keyword("constructor") => SubroutineKind::Constructor,
+            Token::Keyword("function") => SubroutineKind::Function,
+            Token::Keyword("method") => SubroutineKind::Method,
+            token => {
+                self.position -= 1;
+                return self.unexpected(&token, "'constructor', 'function', 'method' or '}'");
+            },
+        };
+        let return_type = self.var_type(true)?;
+        let name = self.identifier("a subroutine name")?;
+        self.symbol('(')?;
+        let mut parameters = vec![];
+        while !self.is_symbol(')') {
+            if !parameters.is_empty() {
+                self.symbol(',')?;
+            }
+            let var_type = self.var_type(false)?;
+            parameters.push((var_type, self.identifier("a parameter name")?));
+        }
+        self.symbol(')')?;
+        self.symbol('{')?;
+        let mut locals = vec![];
+        while self.is_keyword("var") {
+            self.position += 1;
+            let var_type = self.var_type(false)?;
+            for name in self.names()? {
+                locals.push((var_type.clone(), name));
+            }
+        }
+        let body = self.statements()?;
+        self.symbol('}')?;
+        Ok(Subroutine { kind, return_type, name, parameters, locals, body, line })
+    }
+
+    // statements up to the closing '}', which is left for the caller
+    fn statements(&mut self) -> Result<Vec<Statement>, Error> {
+        let mut statements = vec![];
+        while !self.is_symbol('}') {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, Error> {
+        self.symbol('{')?;
+        let statements = self.statements()?;
+        self.symbol('}')?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        let line = self.line();
+        match self.next("a statement")? {
+            Token::Keyword("let") => {
+                let name = self.identifier("a variable name")?;
+                let index = if self.is_symbol('[') {
+                    self.position += 1;
+                    let index = self.expression()?;
+                    self.symbol(']')?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.symbol('=')?;
+                let value = self.expression()?;
+                self.symbol(';')?;
+                Ok(Statement::Let { line, name, index, value })
+            },
+            Token::Keyword("if") => {
+                self.symbol('(')?;
+                let condition = self.expression()?;
+                self.symbol(')')?;
+                let then = self.block()?;
+                let otherwise = if self.is_keyword("else") {
+                    self.position += 1;
+                    Some(self.block()?)
+                } else {
+                    None
+                };
+                Ok(Statement::If { line, condition, then, otherwise })
+            },
+            Token::Keyword("while") => {
+                self.symbol('(')?;
+                let condition = self.expression()?;
+                self.symbol(')')?;
+                let body = self.block()?;
+                Ok(Statement::While { line, condition, body })
+            },
+            Token::Keyword("do") => {
+                let name = self.identifier("a subroutine call")?;
+                let call = self.call(name)?;
+                self.symbol(';')?;
+                Ok(Statement::Do { line, call })
+            },
+            Token::Keyword("return") => {
+                let value = if self.is_symbol(';') { None } else { Some(self.expression()?) };
+                self.symbol(';')?;
+                Ok(Statement::Return { line, value })
+            },
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, "a statement")
+            },
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, Error> {
+        let term = self.term()?;
+        let mut operations = vec![];
+        while let Some(&Token::Symbol(op)) = self.peek() {
+            if !OPERATORS.contains(op) {
+                break;
+            }
+            self.position += 1;
+            operations.push((op, self.term()?));
+        }
+        Ok(Expression { term, operations })
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
+        match self.next("an expression")? {
+            Token::Integer(n) => Ok(Term::Integer(n)),
+            Token::Str(s) => Ok(Term::Str(s)),
+            Token::Keyword("true") => Ok(Term::True),
+            Token::Keyword("false") => Ok(Term::False),
+            Token::Keyword("null") => Ok(Term::Null),
+            Token::Keyword("this") => Ok(Term::This),
+            Token::Symbol('(') => {
+                let expression = self.expression()?;
+                self.symbol(')')?;
+                Ok(Term::Parenthesized(Box::new(expression)))
+            },
+            Token::Symbol(op) if op == '-' || op == '~' => Ok(Term::Unary(op, Box::new(self.term()?))),
+            Token::Identifier(name) => {
+                if self.is_symbol('[') {
+                    self.position += 1;
+                    let index = self.expression()?;
+                    self.symbol(']')?;
+                    Ok(Term::Index(name, Box::new(index)))
+                } else if self.is_symbol('(') || self.is_symbol('.') {
+                    Ok(Term::Call(self.call(name)?))
+                } else {
+                    Ok(Term::Variable(name))
+                }
+            },
+            token => {
+                self.position -= 1;
+                self.unexpected(&token, "an expression")
+            },
+        }
+    }
+
+    // the rest of a call that starts with name
+    fn call(&mut self, name: String) -> Result<Call, Error> {
+        let (receiver, name) = if self.is_symbol('.') {
+            self.position += 1;
+            (Some(name), self.identifier("a subroutine name")?)
+        } else {
+            (None, name)
+        };
+        self.symbol('(')?;
+        let mut arguments = vec![];
+        while !self.is_symbol(')') {
+            if !arguments.is_empty() {
+                self.symbol(',')?;
+            }
+            arguments.push(self.expression()?);
+        }
+        self.symbol(')')?;
+        Ok(Call { receiver, name, arguments })
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+use super::Error;
+
+const KEYWORDS: [&str; 21] = [
+    "class", "constructor", "function", "method", "field", "static", "var",
+    "int", "char", "boolean", "void", "true", "false", "null", "this",
+    "let", "do", "if", "else", "while", "return",
+];
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Keyword(&'static str),
+    Symbol(char),
+    Identifier(String),
+    Integer(i16),
+    Str(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Keyword(keyword) => write!(f, "'{}'", keyword),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::Identifier(name) => write!(f, "'{}'", name),
+            Token::Integer(n) => write!(f, "'{}'", n),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+        }
+    }
+}
+
+// the tokens of a Jack source with the line each starts on; comments are //
+// to the end of the line and /* */, which includes the /** */ of documentation
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            let start = line;
+            i += 2;
+            loop {
+                match chars.get(i) {
+                    Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                    Some('\n') => line += 1,
+                    Some(_) => {},
+                    None => return Err((start, String::from("comment is not closed"))),
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if chars.get(i) != Some(&'"') {
+                return Err((line, String::from("string constant is not closed on its line")));
+            }
+            let s: String = chars[start..i].iter().collect();
+            if let Some(c) = s.chars().find(|c| !(' '..='~').contains(c)) {
+                return Err((line, format!("'{}' is not in the Hack character set", c)));
+            }
+            tokens.push((Token::Str(s), line));
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            match digits.parse::<i16>() {
+                Ok(n) => tokens.push((Token::Integer(n), line)),
+                Err(_) if digits.chars().all(|c| c.is_ascii_digit()) => {
+                    return Err((line, format!("integer constant {} is out of range (0..32767)", digits)));
+                },
+                Err(_) => return Err((line, format!("'{}' is not a valid integer constant", digits))),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            match KEYWORDS.iter().find(|&&keyword| keyword == word) {
+                Some(keyword) => tokens.push((Token::Keyword(keyword), line)),
+                None => tokens.push((Token::Identifier(word), line)),
+            }
+        } else if SYMBOLS.contains(c) {
+            tokens.push((Token::Symbol(c), line));
+            i += 1;
+        } else {
+            return Err((line, format!("unexpected character '{}'", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_keep_their_lines() {
+        let source = "class Main {\n  /** doc\n  comment */ field int x; // x\n  let s = \"a b\";\n  let y = a[12] - ~x;\n}\n";
+        let tokens = tokenize(source).unwrap();
+        assert_eq!(tokens[..3], [
+            (Token::Keyword("class"), 1),
+            (Token::Identifier(String::from("Main")), 1),
+            (Token::Symbol('{'), 1),
+        ]);
+        assert_eq!(tokens[3..7], [
+            (Token::Keyword("field"), 3),
+            (Token::Keyword("int"), 3),
+            (Token::Identifier(String::from("x")), 3),
+            (Token::Symbol(';'), 3),
+        ]);
+        assert_eq!(tokens[10], (Token::Str(String::from("a b")), 4));
+        assert_eq!(tokens[17], (Token::Integer(12), 5));
+        assert_eq!(tokens.last(), Some(&(Token::Symbol('}'), 6)));
+    }
+
+    #[test]
+    fn integers_range_over_0_to_32767() {
+        assert_eq!(tokenize("32767").unwrap(), [(Token::Integer(i16::MAX), 1)]);
+        assert_eq!(tokenize("\n32768"), Err((2, String::from("integer constant 32768 is out of range (0..32767)"))));
+        assert_eq!(tokenize("12ab"), Err((1, String::from("'12ab' is not a valid integer constant"))));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(tokenize("x\n/* never\nclosed"), Err((2, String::from("comment is not closed"))));
+        assert_eq!(tokenize("\"open\nx\""), Err((1, String::from("string constant is not closed on its line"))));
+        assert_eq!(tokenize("\"caf\u{e9}\""), Err((1, String::from("'\u{e9}' is not in the Hack character set"))));
+        assert_eq!(tokenize("x\n\ny # 1"), Err((3, String::from("unexpected character '#'"))));
+    }
+}
@@ -0,0 +1,30 @@
+// The translator's building blocks, shared by the VMtranslator binary and the
+// tools in src/bin.
+
+pub mod annotate;
+pub mod assembler;
+pub mod backend;
+pub mod backtrace;
+pub mod builtins;
+pub mod c_backend;
+pub mod code_writer;
+pub mod devices;
+pub mod emulator;
+pub mod extensions;
+pub mod inliner;
+pub mod interpreter;
+pub mod jack;
+pub mod json;
+pub mod lifter;
+pub mod optimizer;
+pub mod os;
+pub mod parser;
+pub mod profiler;
+pub mod program;
+pub mod repl;
+pub mod riscv_writer;
+pub mod source_map;
+pub mod stats;
+pub mod trace;
+pub mod wat_writer;
+pub mod x86_writer;
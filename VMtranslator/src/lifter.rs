@@ -0,0 +1,626 @@
+use std::collections::HashSet;
+
+use crate::assembler::Cpu;
+use crate::extensions;
+use crate::parser::{Command, CommandType, VmFile};
+
+// Lifts Hack assembly written by CodeWriter back to VM commands, for programs
+// that only come as .asm. Every command has a fixed instruction template, so the
+// code is matched template by template from the start of ROM; the labels give
+// the function names, and the statics `File.i` the files the functions were in,
+// with the class part of the function name as the fallback.
+//
+// The constant forms of the extended CPU do not always tell the command apart,
+// so they are lifted to one that computes the same: n shifts left to
+// `push constant n, shl` also for a multiplication by 2^n, shifts right by more
+// than 15 to 15 of them, and clearing the top of the stack to a multiplication
+// by 0. Code that matches no template (hand-written, from another translator's
+// templates, or changed after the translation) is kept as an unrecognized
+// region and matching resumes after it.
+
+// what a range of ROM was lifted to
+pub enum Lifted {
+    Commands(Vec<Command>), // one command, or call and return for a tail call
+    Bootstrap(Vec<(&'static str, i16)>, Option<String>), // register values and the function called
+    Halt,
+    Routine(&'static str),
+    Unrecognized(Vec<String>), // the instructions
+}
+
+pub struct Region {
+    pub start: usize, // ROM addresses, end exclusive; a label takes none
+    pub end: usize,
+    pub file: String, // of commands, empty for the rest
+    pub lifted: Lifted,
+}
+
+// name is the file of the commands that neither use statics nor are in a function
+pub fn lift(source: &str, name: &str) -> Vec<Region> {
+    let code = read(source);
+    let mut regions = vec![];
+    let mut return_labels = HashSet::new();
+    let mut function = String::new();
+    let mut unrecognized = None; // start of the region being collected
+    let mut address = 0;
+    loop {
+        let found = if address < code.instructions.len() { match_at(&code, address) } else { None };
+        let labels = &code.labels[address];
+        // an unrecognized region ends where a template or a label starts
+        if found.is_some() || !labels.is_empty() || address == code.instructions.len() {
+            if let Some(start) = unrecognized.take() {
+                let instructions = code.instructions[start..address].to_vec();
+                regions.push(Region { start, end: address, file: String::new(), lifted: Lifted::Unrecognized(instructions) });
+            }
+        }
+        // the label a function or routine starts with is part of its template
+        let owned = match &found {
+            Some((_, _, Lifted::Commands(commands))) if commands[0].command_type == CommandType::Function => Some(commands[0].arg1.as_str()),
+            Some((_, _, Lifted::Routine(routine))) => Some(*routine),
+            _ => None,
+        };
+        for label in labels.iter() {
+            if !return_labels.contains(label) && owned != Some(label.as_str()) {
+                let command = command(CommandType::Label, &local_label(&function, label), 0);
+                regions.push(Region { start: address, end: address, file: String::new(), lifted: Lifted::Commands(vec![command]) });
+            }
+        }
+        let (cursor, file, mut lifted) = match found {
+            Some(found) => found,
+            None if address == code.instructions.len() => break,
+            None => {
+                unrecognized.get_or_insert(address);
+                address += 1;
+                continue;
+            },
+        };
+        if let Some(label) = cursor.return_label {
+            return_labels.insert(label.to_string());
+        }
+        if let Lifted::Commands(commands) = &mut lifted {
+            for cmd in commands.iter_mut() {
+                match cmd.command_type {
+                    CommandType::Function => function = cmd.arg1.clone(),
+                    CommandType::Goto | CommandType::If => cmd.arg1 = local_label(&function, &cmd.arg1),
+                    _ => {},
+                }
+            }
+        }
+        regions.push(Region { start: address, end: cursor.pos, file, lifted });
+        address = cursor.pos;
+    }
+    assign_files(&mut regions, name);
+    regions
+}
+
+// the lifted commands by file, in ROM order
+pub fn files(regions: &[Region]) -> Vec<VmFile> {
+    let mut files: Vec<VmFile> = vec![];
+    for region in regions.iter() {
+        if let Lifted::Commands(commands) = &region.lifted {
+            match files.last_mut() {
+                Some(file) if file.name == region.file => file.commands.extend(commands.iter().cloned()),
+                _ => files.push(VmFile { name: region.file.clone(), commands: commands.clone() }),
+            }
+        }
+    }
+    files
+}
+
+// a function's commands go to the file of its first static, or else to the
+// class in its name; commands before the first function to name
+fn assign_files(regions: &mut [Region], name: &str) {
+    let starts: Vec<usize> = regions.iter().enumerate()
+        .filter(|(_, region)| matches!(&region.lifted, Lifted::Commands(c) if c[0].command_type == CommandType::Function))
+        .map(|(i, _)| i)
+        .collect();
+    let mut bounds = vec![0];
+    bounds.extend(starts.iter().copied().filter(|&i| i > 0));
+    bounds.push(regions.len());
+    for pair in bounds.windows(2) {
+        let group = &mut regions[pair[0]..pair[1]];
+        let file = match group.iter().find(|region| !region.file.is_empty()) {
+            Some(region) => region.file.clone(),
+            None => match &group[0].lifted {
+                Lifted::Commands(c) if c[0].command_type == CommandType::Function => match c[0].arg1.split_once('.') {
+                    Some((class, _)) => class.to_string(),
+                    None => name.to_string(),
+                },
+                _ => name.to_string(),
+            },
+        };
+        for region in group.iter_mut() {
+            region.file = match region.lifted {
+                Lifted::Commands(_) => file.clone(),
+                _ => String::new(),
+            };
+        }
+    }
+}
+
+// labels inside a function are written as function$label
+fn local_label(function: &str, label: &str) -> String {
+    match label.strip_prefix(function).and_then(|rest| rest.strip_prefix('$')) {
+        Some(local) if !function.is_empty() => local.to_string(),
+        _ => label.to_string(),
+    }
+}
+
+fn command(command_type: CommandType, arg1: &str, arg2: i16) -> Command {
+    Command { command_type, arg1: arg1.to_string(), arg2, line: 0 }
+}
+
+// the instructions without comments and spaces, and the labels before each
+struct Code {
+    instructions: Vec<String>,
+    labels: Vec<Vec<String>>, // one more than instructions, for labels at the end
+}
+
+fn read(source: &str) -> Code {
+    let mut code = Code { instructions: vec![], labels: vec![vec![]] };
+    for line in source.lines() {
+        let line = match line.find("//") {
+            Some(n) => &line[..n],
+            None => line,
+        };
+        let text: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+        if text.is_empty() {
+            continue;
+        }
+        match text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            Some(label) => code.labels.last_mut().unwrap().push(label.to_string()),
+            None => {
+                code.instructions.push(normalize(text));
+                code.labels.push(vec![]);
+            },
+        }
+    }
+    code
+}
+
+// the assembler takes the operands of +, & and | in either order, as in M=M&D
+// of older translations, so comps are matched as the Hack comp table writes
+// them: D before A or M, and a register before 1
+fn normalize(instruction: String) -> String {
+    let (dest, rest) = match instruction.split_once('=') {
+        Some((dest, rest)) => (format!("{}=", dest), rest),
+        None => (String::new(), instruction.as_str()),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+        Some((comp, jump)) => (comp, format!(";{}", jump)),
+        None => (rest, String::new()),
+    };
+    match comp.as_bytes() {
+        &[x, op, y] if matches!(op, b'+' | b'&' | b'|')
+            && matches!((x, y), (b'A' | b'M', b'D') | (b'1', b'D' | b'A' | b'M')) => {
+            format!("{}{}{}{}{}", dest, y as char, op as char, x as char, jump)
+        },
+        _ => instruction,
+    }
+}
+
+#[derive(Clone)]
+struct Cursor<'a> {
+    code: &'a Code,
+    start: usize,
+    pos: usize,
+    file: Option<&'a str>,         // of a static the template uses
+    return_label: Option<&'a str>, // the label a call returns to
+}
+
+impl<'a> Cursor<'a> {
+    // a label inside a template means something else jumps there, so it ends the match
+    fn next(&mut self) -> Option<&'a str> {
+        if self.pos > self.start && !self.code.labels[self.pos].is_empty() {
+            return None;
+        }
+        let instruction = self.code.instructions.get(self.pos)?;
+        self.pos += 1;
+        Some(instruction)
+    }
+
+    fn expect(&mut self, instructions: &[&str]) -> Option<()> {
+        for &expected in instructions.iter() {
+            if self.next()? != expected {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    // takes the instruction only if it is the one given
+    fn accept(&mut self, instruction: &str) -> bool {
+        let pos = self.pos;
+        if self.next() == Some(instruction) {
+            true
+        } else {
+            self.pos = pos;
+            false
+        }
+    }
+
+    fn number(&mut self) -> Option<i16> {
+        self.next()?.strip_prefix('@')?.parse::<i16>().ok().filter(|&n| n >= 0)
+    }
+
+    fn symbol(&mut self) -> Option<&'a str> {
+        let symbol = self.next()?.strip_prefix('@')?;
+        if symbol.is_empty() || symbol.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        Some(symbol)
+    }
+
+    // @address, at offset from the start of the template
+    fn address(&mut self, offset: usize) -> Option<()> {
+        (self.number()? as usize == self.start + offset).then_some(())
+    }
+
+    // @LCL, @ARG, @THIS or @THAT, as the segment
+    fn base(&mut self) -> Option<&'static str> {
+        match self.next()? {
+            "@LCL" => Some("local"),
+            "@ARG" => Some("argument"),
+            "@THIS" => Some("this"),
+            "@THAT" => Some("that"),
+            _ => None,
+        }
+    }
+
+    // A=M or A=M+1 followed by A=A+1s, as the index they add up to
+    fn offset(&mut self) -> Option<i16> {
+        let mut index = match self.next()? {
+            "A=M" => return Some(0),
+            "A=M+1" => 1,
+            _ => return None,
+        };
+        while self.accept("A=A+1") {
+            index += 1;
+        }
+        Some(index)
+    }
+
+    // @3..@12 and @File.i, as segment and index
+    fn direct(&mut self) -> Option<(&'static str, i16)> {
+        let pos = self.pos;
+        if let Some(address) = self.number() {
+            return match address {
+                3..=4 => Some(("pointer", address - 3)),
+                5..=12 => Some(("temp", address - 5)),
+                _ => None,
+            };
+        }
+        self.pos = pos;
+        let (file, index) = self.symbol()?.rsplit_once('.')?;
+        let index = index.parse::<i16>().ok().filter(|&i| i >= 0 && !file.is_empty())?;
+        self.file = Some(file);
+        Some(("static", index))
+    }
+}
+
+const PUSH_D: [&str; 5] = ["@SP", "A=M", "M=D", "@SP", "M=M+1"];
+const POP_D: [&str; 3] = ["@SP", "AM=M-1", "D=M"];
+const COPY_WORD: [&str; 8] = ["@R13", "M=M+1", "A=M-1", "D=M", "@R14", "M=M+1", "A=M-1", "M=D"];
+
+const RETURN: [&str; 52] = [
+    "@LCL", "D=M", "@R13", "M=D",
+    "@R13", "D=M", "@5", "A=D-A", "D=M", "@R14", "M=D",
+    "@SP", "AM=M-1", "D=M", "@ARG", "A=M", "M=D",
+    "@ARG", "D=M", "@SP", "M=D+1",
+    "@R13", "D=M", "@1", "A=D-A", "D=M", "@THAT", "M=D",
+    "@R13", "D=M", "@2", "A=D-A", "D=M", "@THIS", "M=D",
+    "@R13", "D=M", "@3", "A=D-A", "D=M", "@ARG", "M=D",
+    "@R13", "D=M", "@4", "A=D-A", "D=M", "@LCL", "M=D",
+    "@R14", "A=M", "0;JMP",
+];
+
+const ROUTINES: [&str; 4] = ["$mul", "$divmod", "$shl", "$shr"];
+
+type Matcher = fn(&mut Cursor) -> Option<Lifted>;
+
+const MATCHERS: [Matcher; 20] = [
+    bootstrap, function, call, tail_call, return_, routine, halt,
+    binary, unary, neg, routine_call, constant_operation,
+    push_constant, push_segment, push_direct,
+    pop_indirect, pop_segment, pop_direct, if_goto, goto,
+];
+
+// the template at address, with the end of its cursor and the file of its static
+fn match_at(code: &Code, address: usize) -> Option<(Cursor<'_>, String, Lifted)> {
+    MATCHERS.iter().find_map(|matcher| {
+        let mut cursor = Cursor { code, start: address, pos: address, file: None, return_label: None };
+        let lifted = matcher(&mut cursor)?;
+        let file = cursor.file.unwrap_or_default().to_string();
+        Some((cursor, file, lifted))
+    })
+}
+
+fn one(command_type: CommandType, arg1: &str, arg2: i16) -> Option<Lifted> {
+    Some(Lifted::Commands(vec![command(command_type, arg1, arg2)]))
+}
+
+// @value D=A @register M=D for SP and the registers set, then the call of the entry
+fn bootstrap(c: &mut Cursor) -> Option<Lifted> {
+    if c.start != 0 {
+        return None;
+    }
+    let mut registers = vec![];
+    for register in ["SP", "LCL", "ARG", "THIS", "THAT"] {
+        let mut t = c.clone();
+        let value = t.number().filter(|_| t.expect(&["D=A", &format!("@{}", register), "M=D"]).is_some());
+        match value {
+            Some(value) => {
+                *c = t;
+                registers.push((register, value));
+            },
+            None if register == "SP" => return None,
+            None => {},
+        }
+    }
+    let mut t = c.clone();
+    let entry = match call(&mut t) {
+        Some(Lifted::Commands(commands)) if commands[0].arg2 == 0 => {
+            *c = t;
+            Some(commands[0].arg1.clone())
+        },
+        _ => None,
+    };
+    Some(Lifted::Bootstrap(registers, entry))
+}
+
+// (f) then pushing 0 n times in a loop
+fn function(c: &mut Cursor) -> Option<Lifted> {
+    let name = c.code.labels[c.start].iter().rev().find(|label| !label.contains('$'))?;
+    let n = c.number()?;
+    c.expect(&["D=A"])?;
+    c.address(12)?;
+    c.expect(&["D;JEQ", "@SP", "A=M", "M=0", "@SP", "M=M+1", "D=D-1"])?;
+    c.address(2)?;
+    c.expect(&["0;JMP"])?;
+    one(CommandType::Function, name, n)
+}
+
+fn call(c: &mut Cursor) -> Option<Lifted> {
+    let return_label = c.symbol()?;
+    c.expect(&["D=A"])?;
+    c.expect(&PUSH_D)?;
+    for register in ["@LCL", "@ARG", "@THIS", "@THAT"] {
+        c.expect(&[register, "D=M"])?;
+        c.expect(&PUSH_D)?;
+    }
+    c.expect(&["@SP", "D=M"])?;
+    let n = c.number()?;
+    c.expect(&["D=D-A", "@5", "D=D-A", "@ARG", "M=D", "@SP", "D=M", "@LCL", "M=D"])?;
+    let name = c.symbol()?;
+    c.expect(&["0;JMP"])?;
+    if !c.code.labels[c.pos].iter().any(|label| label == return_label) {
+        return None;
+    }
+    c.return_label = Some(return_label);
+    one(CommandType::Call, name, n)
+}
+
+// the frame copied above the arguments, both moved down to ARG, and a jump
+fn tail_call(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&["@5", "D=A", "@LCL", "D=M-D", "@R13", "M=D", "@SP", "D=M", "@R14", "M=D"])?;
+    for _ in 0..5 {
+        c.expect(&COPY_WORD)?;
+    }
+    let n = c.number()?;
+    c.expect(&["D=A", "@SP", "D=M-D", "@R13", "M=D", "@ARG", "D=M", "@R14", "M=D"])?;
+    for _ in 0..n as i32 + 5 {
+        c.expect(&COPY_WORD)?;
+    }
+    c.expect(&["@ARG", "D=M"])?;
+    (c.number()? as i32 == n as i32 + 5).then_some(())?;
+    c.expect(&["D=D+A", "@LCL", "M=D", "@SP", "M=D"])?;
+    let name = c.symbol()?;
+    c.expect(&["0;JMP"])?;
+    Some(Lifted::Commands(vec![command(CommandType::Call, name, n), command(CommandType::Return, "", 0)]))
+}
+
+fn return_(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&RETURN)?;
+    one(CommandType::Return, "", 0)
+}
+
+// an extended arithmetic routine, compared with the code of either CPU
+fn routine(c: &mut Cursor) -> Option<Lifted> {
+    let labels = &c.code.labels[c.start];
+    let routine = *ROUTINES.iter().find(|routine| labels.iter().any(|label| label == *routine))?;
+    for cpu in [Cpu::Standard, Cpu::Extended] {
+        let instructions: Vec<String> = extensions::code(routine, cpu).lines()
+            .map(|l| l.chars().filter(|c| !c.is_whitespace()).collect::<String>())
+            .filter(|l| !l.is_empty() && !l.starts_with('(') && !l.starts_with("//"))
+            .collect();
+        if c.code.instructions[c.start..].starts_with(&instructions) {
+            c.pos = c.start + instructions.len();
+            return Some(Lifted::Routine(routine));
+        }
+    }
+    None
+}
+
+// the loop the program ends in
+fn halt(c: &mut Cursor) -> Option<Lifted> {
+    c.address(0)?;
+    c.expect(&["0;JMP"])?;
+    Some(Lifted::Halt)
+}
+
+// add, sub, and, or, and eq, gt, lt through a jump on x - y
+fn binary(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&POP_D)?;
+    c.expect(&["@SP", "AM=M-1"])?;
+    let operator = match c.next()? {
+        "M=D+M" => "add",
+        "M=M-D" => "sub",
+        "M=D&M" => "and",
+        "M=D|M" => "or",
+        "D=M-D" => {
+            c.address(13)?;
+            let operator = match c.next()? {
+                "D;JEQ" => "eq",
+                "D;JGT" => "gt",
+                "D;JLT" => "lt",
+                _ => return None,
+            };
+            c.expect(&["@SP", "A=M", "M=0"])?;
+            c.address(16)?;
+            c.expect(&["0;JMP", "@SP", "A=M", "M=-1"])?;
+            operator
+        },
+        _ => return None,
+    };
+    c.expect(&["@SP", "M=M+1"])?;
+    one(CommandType::Arithmetic, operator, 0)
+}
+
+// not, and neg as older translations wrote it
+fn unary(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&["@SP", "AM=M-1"])?;
+    let operator = match c.next()? {
+        "D=!M" => {
+            c.expect(&["M=D+1"])?;
+            "neg"
+        },
+        "M=!M" => "not",
+        _ => return None,
+    };
+    c.expect(&["@SP", "M=M+1"])?;
+    one(CommandType::Arithmetic, operator, 0)
+}
+
+fn neg(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&["@SP", "A=M-1", "M=-M"])?;
+    one(CommandType::Arithmetic, "neg", 0)
+}
+
+// x in R13, y in R14, the return address in R15, then the result replaces x
+fn routine_call(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&POP_D)?;
+    c.expect(&["@R14", "M=D", "@SP", "A=M-1", "D=M", "@R13", "M=D"])?;
+    c.address(16)?;
+    c.expect(&["D=A", "@R15", "M=D"])?;
+    let mut operator = match c.symbol()? {
+        "$mul" => "mul",
+        "$divmod" => "div",
+        "$shl" => "shl",
+        "$shr" => "shr",
+        _ => return None,
+    };
+    c.expect(&["0;JMP"])?;
+    if operator == "div" && c.accept("@R13") {
+        c.expect(&["D=M"])?;
+        operator = "mod";
+    }
+    c.expect(&["@SP", "A=M-1", "M=D"])?;
+    one(CommandType::Arithmetic, operator, 0)
+}
+
+// `push constant n` folded into the following mul, shl or shr
+fn constant_operation(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&["@SP", "A=M-1"])?;
+    let (operator, value) = if c.accept("M=0") {
+        ("mul", 0)
+    } else if c.accept("D=M") {
+        // D = 2D (+ x) per bit below the top one
+        let mut value: i16 = 1;
+        loop {
+            if c.accept("D=D<<") {
+                value = value.checked_mul(2)?;
+            } else if c.accept("D=D+M") {
+                value += 1;
+            } else {
+                break;
+            }
+        }
+        c.expect(&["M=D"])?;
+        ("mul", value)
+    } else if c.accept("M=M>>") {
+        let mut shifts = 1;
+        while c.accept("M=M>>") {
+            shifts += 1;
+        }
+        ("shr", shifts)
+    } else {
+        let mut shifts = 0;
+        while c.accept("M=M<<") {
+            shifts += 1;
+        }
+        ("shl", shifts)
+    };
+    Some(Lifted::Commands(vec![
+        command(CommandType::Push, "constant", value),
+        command(CommandType::Arithmetic, operator, 0),
+    ]))
+}
+
+fn push_constant(c: &mut Cursor) -> Option<Lifted> {
+    let value = c.number()?;
+    c.expect(&["D=A"])?;
+    c.expect(&PUSH_D)?;
+    one(CommandType::Push, "constant", value)
+}
+
+// local, argument, this and that, by increments or by adding the index
+fn push_segment(c: &mut Cursor) -> Option<Lifted> {
+    let segment = c.base()?;
+    let index = if c.accept("D=M") {
+        let index = c.number()?;
+        c.expect(&["A=D+A"])?;
+        index
+    } else {
+        c.offset()?
+    };
+    c.expect(&["D=M"])?;
+    c.expect(&PUSH_D)?;
+    one(CommandType::Push, segment, index)
+}
+
+// pointer, temp and static
+fn push_direct(c: &mut Cursor) -> Option<Lifted> {
+    let (segment, index) = c.direct()?;
+    c.expect(&["D=M"])?;
+    c.expect(&PUSH_D)?;
+    one(CommandType::Push, segment, index)
+}
+
+// local, argument, this and that with the address in R13
+fn pop_indirect(c: &mut Cursor) -> Option<Lifted> {
+    let segment = c.base()?;
+    c.expect(&["D=M"])?;
+    let index = c.number()?;
+    c.expect(&["D=D+A", "@R13", "M=D"])?;
+    c.expect(&POP_D)?;
+    c.expect(&["@R13", "A=M", "M=D"])?;
+    one(CommandType::Pop, segment, index)
+}
+
+// local, argument, this and that by increments once D holds the value
+fn pop_segment(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&POP_D)?;
+    let segment = c.base()?;
+    let index = c.offset()?;
+    c.expect(&["M=D"])?;
+    one(CommandType::Pop, segment, index)
+}
+
+fn pop_direct(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&POP_D)?;
+    let (segment, index) = c.direct()?;
+    c.expect(&["M=D"])?;
+    one(CommandType::Pop, segment, index)
+}
+
+fn if_goto(c: &mut Cursor) -> Option<Lifted> {
+    c.expect(&POP_D)?;
+    let label = c.symbol()?;
+    c.expect(&["D;JNE"])?;
+    one(CommandType::If, label, 0)
+}
+
+fn goto(c: &mut Cursor) -> Option<Lifted> {
+    let label = c.symbol()?;
+    c.expect(&["0;JMP"])?;
+    one(CommandType::Goto, label, 0)
+}
@@ -1,77 +1,206 @@
-mod parser;
-mod code_writer;
+mod cli;
 
 use std::env;
-use std::fs::File;
+use std::fs;
+use std::io::{self, Read, Write};
 use std::path::Path;
+use std::process;
 
-fn main() -> std::io::Result<()> {
+use cli::{Action, Emit, Options};
+use vmtranslator::assembler::Cpu;
+use vmtranslator::backend::{self, Backend};
+use vmtranslator::parser::VmFile;
+use vmtranslator::{annotate, assembler, c_backend, code_writer, inliner, json, optimizer, os, parser};
+use vmtranslator::{riscv_writer, source_map, stats, wat_writer, x86_writer};
+
+fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { panic!("usage: VMtranslator <filename>.vm | <dirname>"); }
-    
-    let arg_path = Path::new(&args[1]);
+    let options = match cli::parse_args(&args[1..]) {
+        Ok(Action::Translate(options)) => options,
+        Ok(Action::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Ok(Action::Version) => {
+            println!("VMtranslator {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("VMtranslator: {}", e);
+            eprintln!("try 'VMtranslator --help' for more information");
+            process::exit(2);
+        },
+    };
 
-    let mut fin_paths = vec![];
-    let fout_path = if arg_path.is_dir() {
-        for entry in arg_path.read_dir().expect("read_dir call failed") {
-            if let Ok(entry) = entry {
-                if entry.path().extension().unwrap() == "vm" {
-                    fin_paths.push(entry.path());
-                }
-            }
+    if let Err(e) = run(&options) {
+        eprintln!("VMtranslator: error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let (mut files, default_output) = read_input(&options.input, options.emit, options.extensions)?;
+    if options.os {
+        os::link(&mut files, &options.os_classes, options.extensions)?;
+    }
+    if let Some(threshold) = options.inline {
+        inliner::inline(&mut files, threshold);
+    }
+    if options.optimize {
+        for file in files.iter_mut() {
+            file.commands = optimizer::optimize(std::mem::take(&mut file.commands));
+        }
+    }
+
+    let output_path = options.output.as_deref().or(default_output.as_deref());
+    if options.stats && options.emit != Emit::Asm && options.emit != Emit::Hack {
+        return Err(String::from("--stats requires --emit asm or hack"));
+    }
+    if options.extensions && matches!(options.emit, Emit::X86 | Emit::C | Emit::Wat | Emit::Riscv) {
+        return Err(String::from("--extensions requires --emit asm, hack, ir or json"));
+    }
+    if options.cpu == Cpu::Extended && options.emit != Emit::Asm && options.emit != Emit::Hack {
+        return Err(String::from("--cpu extended requires --emit asm or hack"));
+    }
+    let map_path = if options.source_map {
+        if options.emit != Emit::Asm && options.emit != Emit::Hack {
+            return Err(String::from("--source-map requires --emit asm or hack"));
+        }
+        match output_path {
+            Some("-") | None => return Err(String::from("--source-map requires an output file")),
+            Some(path) => Some(Path::new(path).with_extension("map")),
         }
-        let dir_name = arg_path.file_name().unwrap();
-        arg_path.join(dir_name).with_extension("asm")
-    } else if arg_path.is_file() {
-        fin_paths.push((*arg_path).to_path_buf());
-        arg_path.with_extension("asm")
     } else {
-        panic!("file or directory not found.");
+        None
+    };
+
+    let output = match options.emit {
+        Emit::Asm | Emit::Hack => {
+            let (asm, map) = translate(&files, options);
+            if options.stats {
+                eprint!("{}", stats::report(&map));
+            }
+            if let Some(map_path) = map_path {
+                fs::write(&map_path, source_map::to_json(&map))
+                    .map_err(|e| format!("cannot write '{}': {}", map_path.display(), e))?;
+            }
+            if options.emit == Emit::Hack {
+                let code = assembler::assemble(&String::from_utf8(asm).unwrap(), options.cpu)?;
+                // the source map addresses come from CodeWriter's own instruction count
+                debug_assert_eq!(Some(code.len()), map.last().map(|e| e.end));
+                assembler::to_hack_text(&code).into_bytes()
+            } else {
+                asm
+            }
+        },
+        Emit::X86 | Emit::C | Emit::Wat | Emit::Riscv => translate_native(&files, options),
+        Emit::Ir => write_ir(&files).into_bytes(),
+        Emit::Json => write_json(&files).into_bytes(),
+    };
+
+    match output_path {
+        Some("-") | None => {
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            lock.write_all(&output).and_then(|_| lock.flush())
+                .map_err(|e| format!("cannot write to standard output: {}", e))
+        },
+        Some(path) => {
+            fs::write(path, &output).map_err(|e| format!("cannot write '{}': {}", path, e))
+        },
+    }
+}
+
+// returns the parsed files and the default output path (None for standard output)
+fn read_input(input: &str, emit: Emit, extensions: bool) -> Result<(Vec<VmFile>, Option<String>), String> {
+    if input == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)
+            .map_err(|e| format!("cannot read standard input: {}", e))?;
+        return Ok((vec![parser::parse_source("Stdin", &source, extensions)?], None));
+    }
+
+    let files = parser::read_program(input, extensions)?;
+    let arg_path = Path::new(input);
+    let fout_path = if arg_path.is_dir() {
+        let dir_name = match arg_path.canonicalize().ok().and_then(|p| p.file_name().map(|n| n.to_os_string())) {
+            Some(name) => name,
+            None => return Err(format!("cannot name the output for directory '{}'", input)),
+        };
+        arg_path.join(dir_name).with_extension(emit.extension())
+    } else {
+        arg_path.with_extension(emit.extension())
     };
+    Ok((files, Some(fout_path.to_string_lossy().to_string())))
+}
+
+// returns the assembly text and its source map
+fn translate(files: &[VmFile], options: &Options) -> (Vec<u8>, Vec<source_map::Entry>) {
+    if options.annotate_instructions {
+        let w = code_writer::CodeWriter::new(annotate::Explainer::new(vec![]));
+        let (out, map) = translate_with(w, files, options);
+        (out.into_inner(), map)
+    } else {
+        translate_with(code_writer::CodeWriter::new(vec![]), files, options)
+    }
+}
 
-    let fout = File::create(fout_path).unwrap();
-    let mut w = code_writer::CodeWriter::new(fout);
-    w.write_init();
+fn translate_with<W: Write>(mut w: code_writer::CodeWriter<W>, files: &[VmFile], options: &Options) -> (W, Vec<source_map::Entry>) {
+    w.set_annotate(options.annotate);
+    w.set_cpu(options.cpu);
+    // writing into memory cannot fail
+    backend::translate(&mut w, files, options.bootstrap.for_program(files, options.bootstrap_mode).as_ref(), options.tail_calls).unwrap();
+    let map = w.source_map().to_vec();
+    (w.into_inner(), map)
+}
 
-    for fin_path in fin_paths.iter() {
-        let fin = File::open(fin_path)?;
-        let mut p = parser::Parser::new(fin);
-        w.set_filename(fin_path.file_stem().unwrap().to_string_lossy().to_string());
+// translation through one of the backends for other targets
+fn translate_native(files: &[VmFile], options: &Options) -> Vec<u8> {
+    let mut out = vec![];
+    let mut w: Box<dyn Backend + '_> = match options.emit {
+        Emit::X86 => Box::new(x86_writer::X86Writer::new(&mut out)),
+        Emit::C => Box::new(c_backend::CWriter::new(&mut out)),
+        Emit::Wat => Box::new(wat_writer::WatWriter::new(&mut out)),
+        Emit::Riscv => Box::new(riscv_writer::RiscvWriter::new(&mut out)),
+        _ => unreachable!("not a native target"),
+    };
+    backend::translate(w.as_mut(), files, options.bootstrap.for_program(files, options.bootstrap_mode).as_ref(), options.tail_calls).unwrap();
+    drop(w);
+    out
+}
 
-        while p.has_more_commands() {
-            p.advance();
-            match p.command_type() {
-                parser::CommandType::Arithmetic => {
-                    w.write_arithmetic(p.arg1());
-                },
-                parser::CommandType::Push => {
-                    w.write_push_pop(String::from("push"), p.arg1(), p.arg2());
-                },
-                parser::CommandType::Pop => {
-                    w.write_push_pop(String::from("pop"), p.arg1(), p.arg2());
-                },
-                parser::CommandType::Label => {
-                    w.write_label(p.arg1());
-                },
-                parser::CommandType::Goto => {
-                    w.write_goto(p.arg1());
-                },
-                parser::CommandType::If => {
-                    w.write_if(p.arg1());
-                },
-                parser::CommandType::Function => {
-                    w.write_function(p.arg1(), p.arg2());
-                },
-                parser::CommandType::Return => {
-                    w.write_return();
-                },
-                parser::CommandType::Call => {
-                    w.write_call(p.arg1(), p.arg2());
-                },
-            }
+// the (possibly optimized) command stream in canonical VM syntax
+fn write_ir(files: &[VmFile]) -> String {
+    let mut s = String::new();
+    for file in files.iter() {
+        s.push_str(&format!("// {}.vm\n", file.name));
+        for cmd in file.commands.iter() {
+            s.push_str(&format!("{}\n", cmd));
         }
     }
+    s
+}
 
-    w.close();
-    Ok(())
+fn write_json(files: &[VmFile]) -> String {
+    let mut s = String::from("{\"files\":[");
+    for (i, file) in files.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&format!("\n{{\"name\":{},\"commands\":[", json::string(&file.name)));
+        for (j, cmd) in file.commands.iter().enumerate() {
+            if j > 0 {
+                s.push(',');
+            }
+            s.push_str(&format!("\n{{\"line\":{},\"type\":{},\"arg1\":{},\"arg2\":{},\"text\":{}}}",
+                cmd.line,
+                json::string(cmd.command_type.name()),
+                json::string(&cmd.arg1),
+                cmd.arg2,
+                json::string(&cmd.to_string())));
+        }
+        s.push_str("]}");
+    }
+    s.push_str("\n]}\n");
+    s
 }
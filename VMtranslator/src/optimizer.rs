@@ -0,0 +1,149 @@
+use crate::parser::{Command, CommandType};
+
+// VM-level peephole optimizations, enabled by `--optimize`.
+// Every rewrite keeps the observable behavior of the program: only the stack
+// traffic inside a basic block and code that can never run is touched.
+
+pub fn optimize(commands: Vec<Command>) -> Vec<Command> {
+    let mut commands = remove_unreachable(commands);
+    loop {
+        let before = commands.len();
+        commands = fold_constants(commands);
+        commands = remove_redundant(commands);
+        if commands.len() == before {
+            return commands;
+        }
+    }
+}
+
+// push constant a, push constant b, add|sub|and|or  =>  push constant (a op b)
+fn fold_constants(commands: Vec<Command>) -> Vec<Command> {
+    let mut out: Vec<Command> = vec![];
+    for cmd in commands.into_iter() {
+        if cmd.command_type == CommandType::Arithmetic && out.len() >= 2 {
+            let n = out.len();
+            let (x, y) = (&out[n - 2], &out[n - 1]);
+            if is_push_constant(x) && is_push_constant(y) {
+                let folded = match cmd.arg1.as_str() {
+                    "add" => Some(x.arg2 as i32 + y.arg2 as i32),
+                    "sub" => Some(x.arg2 as i32 - y.arg2 as i32),
+                    "and" => Some((x.arg2 & y.arg2) as i32),
+                    "or" => Some((x.arg2 | y.arg2) as i32),
+                    _ => None,
+                };
+                // push constant only takes 0..32767
+                if let Some(value) = folded.filter(|v| (0..=i16::MAX as i32).contains(v)) {
+                    out.pop();
+                    out.last_mut().unwrap().arg2 = value as i16;
+                    continue;
+                }
+            }
+        }
+        out.push(cmd);
+    }
+    out
+}
+
+// push s i, pop s i  =>  nothing;  goto L, label L  =>  label L
+fn remove_redundant(commands: Vec<Command>) -> Vec<Command> {
+    let mut out: Vec<Command> = vec![];
+    for cmd in commands.into_iter() {
+        if let Some(prev) = out.last() {
+            let same_location = prev.command_type == CommandType::Push
+                && cmd.command_type == CommandType::Pop
+                && prev.arg1 == cmd.arg1
+                && prev.arg2 == cmd.arg2;
+            let jump_to_next = prev.command_type == CommandType::Goto
+                && cmd.command_type == CommandType::Label
+                && prev.arg1 == cmd.arg1;
+            if same_location {
+                out.pop();
+                continue;
+            }
+            if jump_to_next {
+                out.pop();
+            }
+        }
+        out.push(cmd);
+    }
+    out
+}
+
+// commands after goto or return are dead until the next label or function
+fn remove_unreachable(commands: Vec<Command>) -> Vec<Command> {
+    let mut out = vec![];
+    let mut reachable = true;
+    for cmd in commands.into_iter() {
+        match cmd.command_type {
+            CommandType::Label | CommandType::Function => reachable = true,
+            _ => {},
+        }
+        let ends_block = matches!(cmd.command_type, CommandType::Goto | CommandType::Return);
+        if reachable {
+            out.push(cmd);
+        }
+        if ends_block {
+            reachable = false;
+        }
+    }
+    out
+}
+
+fn is_push_constant(cmd: &Command) -> bool {
+    cmd.command_type == CommandType::Push && cmd.arg1 == "constant"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    // the optimized commands, one per line
+    fn optimized(source: &str) -> String {
+        let file = parser::parse_source("Test", source, false).unwrap();
+        optimize(file.commands).iter().map(|cmd| format!("{}\n", cmd)).collect()
+    }
+
+    #[test]
+    fn constants_fold() {
+        assert_eq!(optimized("push constant 2\npush constant 3\nadd\npush constant 4\nsub\n"), "push constant 1\n");
+        assert_eq!(optimized("push constant 12\npush constant 10\nand\npush constant 1\nor\n"), "push constant 9\n");
+        // only the top two values are constants
+        let source = "push local 0\npush constant 2\nadd\n";
+        assert_eq!(optimized(source), source);
+        let source = "push constant 2\npush constant 3\neq\n";
+        assert_eq!(optimized(source), source);
+    }
+
+    #[test]
+    fn folds_outside_push_constant_are_kept() {
+        for source in ["push constant 1\npush constant 2\nsub\n", "push constant 32767\npush constant 1\nadd\n"] {
+            assert_eq!(optimized(source), source);
+        }
+    }
+
+    #[test]
+    fn push_pop_of_one_location_goes() {
+        assert_eq!(optimized("push local 0\npop local 0\npush constant 1\n"), "push constant 1\n");
+        let source = "push local 0\npop local 1\npush static 3\npop this 3\n";
+        assert_eq!(optimized(source), source);
+        // removing a pair can bring another together
+        assert_eq!(optimized("push local 0\npush local 1\npop local 1\npop local 0\n"), "");
+    }
+
+    #[test]
+    fn goto_the_next_label_goes() {
+        assert_eq!(optimized("goto NEXT\nlabel NEXT\npush constant 1\n"), "label NEXT\npush constant 1\n");
+        let source = "if-goto NEXT\nlabel NEXT\n";
+        assert_eq!(optimized(source), source);
+    }
+
+    // SKIPPED follows dead code but is jumped to
+    #[test]
+    fn unreachable_code_goes_and_labels_stay() {
+        let source = "function F.f 0\nlabel LOOP\npush argument 0\nif-goto SKIPPED\ngoto LOOP\npush constant 2\npop temp 0\n\
+                      label SKIPPED\npush argument 0\nreturn\npush constant 3\nfunction F.g 0\npush constant 4\nreturn\n";
+        assert_eq!(optimized(source), "function F.f 0\nlabel LOOP\npush argument 0\nif-goto SKIPPED\ngoto LOOP\n\
+                                       label SKIPPED\npush argument 0\nreturn\nfunction F.g 0\npush constant 4\nreturn\n");
+    }
+}
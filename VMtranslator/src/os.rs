@@ -0,0 +1,133 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::parser::{self, CommandType, VmFile};
+
+// The Jack OS, bundled as VM code and linked into the programs that need it.
+//
+// A program gets the OS classes whose functions it calls without defining the
+// class itself, and those the linked classes call in turn. A Jack program, one
+// that defines Main.main, also gets Sys unless it defines Sys.init: the bootstrap
+// code calls Sys.init, which initializes the other classes and calls Main.main.
+// Since Sys.init calls every class's init, linking Sys brings in the whole OS.
+//
+// A class of the program always wins over the bundled one, so the OS can be
+// replaced class by class, as when writing one's own OS.
+
+pub const CLASSES: [(&str, &str); 8] = [
+    ("Array", include_str!("os/Array.vm")),
+    ("Keyboard", include_str!("os/Keyboard.vm")),
+    ("Math", include_str!("os/Math.vm")),
+    ("Memory", include_str!("os/Memory.vm")),
+    ("Output", include_str!("os/Output.vm")),
+    ("Screen", include_str!("os/Screen.vm")),
+    ("String", include_str!("os/String.vm")),
+    ("Sys", include_str!("os/Sys.vm")),
+];
+
+// appends to files the OS classes they need, in the order of CLASSES; overrides
+// are .vm files that replace the bundled classes of the same name, an error
+// when the program does not need the class. Returns the classes linked from the
+// bundle.
+pub fn link(files: &mut Vec<VmFile>, overrides: &[String], extensions: bool) -> Result<Vec<String>, String> {
+    let mut replacements = vec![];
+    for path in overrides.iter() {
+        let source = fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))?;
+        let name = Path::new(path).file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if !CLASSES.iter().any(|(class, _)| *class == name) {
+            return Err(format!("'{}' does not replace an OS class", path));
+        }
+        replacements.push((path, parser::parse_source(&name, &source, extensions)?));
+    }
+
+    let defined: HashSet<String> = files.iter()
+        .flat_map(|file| file.commands.iter())
+        .filter(|cmd| cmd.command_type == CommandType::Function)
+        .filter_map(|cmd| class_of(&cmd.arg1).map(String::from))
+        .collect();
+    let defines = |function: &str| files.iter()
+        .flat_map(|file| file.commands.iter())
+        .any(|cmd| cmd.command_type == CommandType::Function && cmd.arg1 == function);
+
+    let mut needed: HashSet<String> = HashSet::new();
+    let mut pending: VecDeque<String> = calls(files).into_iter().collect();
+    if defines("Main.main") && !defines("Sys.init") {
+        pending.push_back(String::from("Sys.init"));
+    }
+    let mut linked = vec![];
+    let mut bundled = vec![];
+    while let Some(function) = pending.pop_front() {
+        let class = match class_of(&function) {
+            Some(class) if !defined.contains(class) && !needed.contains(class) => class.to_string(),
+            _ => continue,
+        };
+        let file = match replacements.iter().position(|(_, file)| file.name == class) {
+            Some(i) => replacements.remove(i).1,
+            None => match CLASSES.iter().find(|(name, _)| *name == class) {
+                // the bundled classes are known to parse
+                Some((name, source)) => {
+                    bundled.push(name.to_string());
+                    parser::parse_source(name, source, false).unwrap()
+                },
+                None => continue,
+            },
+        };
+        pending.extend(calls(std::slice::from_ref(&file)));
+        needed.insert(class);
+        linked.push(file);
+    }
+
+    // an override left over would silently not be what runs
+    if let Some((path, file)) = replacements.first() {
+        return Err(format!("'{}' is not linked: the program does not need the OS class {}", path, file.name));
+    }
+
+    linked.sort_by_key(|file| CLASSES.iter().position(|(name, _)| *name == file.name));
+    files.extend(linked);
+    Ok(bundled)
+}
+
+// "Math" of "Math.multiply"
+fn class_of(function: &str) -> Option<&str> {
+    function.split_once('.').map(|(class, _)| class)
+}
+
+// the functions called in files
+fn calls(files: &[VmFile]) -> Vec<String> {
+    files.iter()
+        .flat_map(|file| file.commands.iter())
+        .filter(|cmd| cmd.command_type == CommandType::Call)
+        .map(|cmd| cmd.arg1.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // links the program source, as Main, with an override of Math written to a
+    // temporary file; returns the names and lengths of the files
+    fn link_with_math(name: &str, source: &str) -> Result<Vec<(String, usize)>, String> {
+        let dir = std::env::temp_dir().join(format!("vmtranslator-os-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Math.vm");
+        fs::write(&path, "function Math.multiply 0\npush constant 0\nreturn\n").unwrap();
+        let mut files = vec![parser::parse_source("Main", source, false).unwrap()];
+        let result = link(&mut files, &[path.to_string_lossy().to_string()], false);
+        fs::remove_dir_all(&dir).unwrap();
+        result.map(|_| files.iter().map(|file| (file.name.clone(), file.commands.len())).collect())
+    }
+
+    #[test]
+    fn overrides_replace_the_bundled_class() {
+        let files = link_with_math("used", "function Main.f 0\npush constant 2\npush constant 3\ncall Math.multiply 2\nreturn\n").unwrap();
+        assert_eq!(files, [(String::from("Main"), 5), (String::from("Math"), 3)]);
+    }
+
+    #[test]
+    fn unused_overrides_are_an_error() {
+        let e = link_with_math("unused", "function Main.f 0\npush constant 2\nreturn\n").unwrap_err();
+        assert!(e.ends_with("Math.vm' is not linked: the program does not need the OS class Math"), "{}", e);
+    }
+}
@@ -1,7 +1,11 @@
-use std::io::{BufRead, BufReader};
-use std::fs::File;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
 
-#[derive(PartialEq)]
+use crate::jack;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum CommandType {
     Arithmetic,
     Push,
@@ -14,85 +18,195 @@ pub enum CommandType {
     Call,
 }
 
+impl CommandType {
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "add" | "sub" | "neg" |
+            "eq"  | "gt"  | "lt"  |
+            "and" | "or"  | "not" => Some(CommandType::Arithmetic),
+            // extended arithmetic, only valid with --extensions
+            "mul" | "div" | "mod" |
+            "shl" | "shr" => Some(CommandType::Arithmetic),
+            "push" => Some(CommandType::Push),
+            "pop" => Some(CommandType::Pop),
+            "label" => Some(CommandType::Label),
+            "goto" => Some(CommandType::Goto),
+            "if-goto" => Some(CommandType::If),
+            "function" => Some(CommandType::Function),
+            "return" => Some(CommandType::Return),
+            "call" => Some(CommandType::Call),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandType::Arithmetic => "arithmetic",
+            CommandType::Push => "push",
+            CommandType::Pop => "pop",
+            CommandType::Label => "label",
+            CommandType::Goto => "goto",
+            CommandType::If => "if-goto",
+            CommandType::Function => "function",
+            CommandType::Return => "return",
+            CommandType::Call => "call",
+        }
+    }
+
+    // number of arguments following the keyword
+    fn num_args(&self) -> usize {
+        match self {
+            CommandType::Arithmetic | CommandType::Return => 0,
+            CommandType::Label | CommandType::Goto | CommandType::If => 1,
+            CommandType::Push | CommandType::Pop |
+            CommandType::Function | CommandType::Call => 2,
+        }
+    }
+}
+
+// a fully parsed VM command, detached from the parser
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub command_type: CommandType,
+    pub arg1: String, // the operator itself for arithmetic, empty for return
+    pub arg2: i16,    // 0 for commands without arg2
+    pub line: usize,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.command_type {
+            CommandType::Arithmetic => write!(f, "{}", self.arg1),
+            CommandType::Push => write!(f, "push {} {}", self.arg1, self.arg2),
+            CommandType::Pop => write!(f, "pop {} {}", self.arg1, self.arg2),
+            CommandType::Label => write!(f, "label {}", self.arg1),
+            CommandType::Goto => write!(f, "goto {}", self.arg1),
+            CommandType::If => write!(f, "if-goto {}", self.arg1),
+            CommandType::Function => write!(f, "function {} {}", self.arg1, self.arg2),
+            CommandType::Return => write!(f, "return"),
+            CommandType::Call => write!(f, "call {} {}", self.arg1, self.arg2),
+        }
+    }
+}
+
+// the commands of one .vm file; `name` is the file stem used for statics
+pub struct VmFile {
+    pub name: String,
+    pub commands: Vec<Command>,
+}
+
 pub struct Parser {
-    commands: Vec<Vec<String>>,
+    commands: Vec<(usize, Vec<String>)>,
     current_cmd: Vec<String>,
+    current_line: usize,
+    extensions: bool,
 }
 
 impl Parser {
-    pub fn new(f: File) -> Self {
+    pub fn new<R: Read>(f: R) -> Self {
         let reader = BufReader::new(f);
-        let commands: Vec<Vec<String>> = reader.lines().map(|line| {
+        let commands: Vec<(usize, Vec<String>)> = reader.lines().enumerate().map(|(i, line)| {
             let line = line.unwrap();
             let l = line.as_str();
-            match l.find("//") {
+            let l = match l.find("//") {
                 Some(n) => { // cut off the comment part
                     l[..n].trim().to_string()
                 },
                 None => {
                     l.trim().to_string()
                 }
-            }
+            };
+            (i + 1, l)
         })
-        .filter(|l| l.as_str() != "")
-        .map(|l| l.split_whitespace().map(|s| String::from(s)).collect::<Vec<String>>())
+        .filter(|(_, l)| !l.is_empty())
+        .map(|(i, l)| (i, l.split_whitespace().map(String::from).collect::<Vec<String>>()))
         .collect();
 
-        let commands_rev: Vec<Vec<String>> = commands.into_iter().rev().collect();
+        let commands_rev: Vec<(usize, Vec<String>)> = commands.into_iter().rev().collect();
 
         Parser {
             commands: commands_rev,
             current_cmd: vec![],
+            current_line: 0,
+            extensions: false,
         }
     }
 
+    // accept mul, div, mod, shl and shr
+    pub fn set_extensions(&mut self, extensions: bool) {
+        self.extensions = extensions;
+    }
+
     pub fn has_more_commands(&mut self) -> bool {
-        self.commands.len() > 0
+        !self.commands.is_empty()
     }
 
     pub fn advance(&mut self) {
         if !self.has_more_commands() {
             panic!("cannot advance because no more commands");
         }
-        if self.has_more_commands() {
-            self.current_cmd = self.commands.pop().unwrap();
-        }
+        let (line, cmd) = self.commands.pop().unwrap();
+        self.current_line = line;
+        self.current_cmd = cmd;
     }
 
-    pub fn command_type(&self) -> CommandType {
-        match self.current_cmd[0].as_str() {
-            "add" | "sub" | "neg" |
-            "eq"  | "gt"  | "lt"  |
-            "and" | "or"  | "not" => {
-                CommandType::Arithmetic
-            },
-            "push" => {
-                CommandType::Push
-            },
-            "pop" => {
-                CommandType::Pop
-            },
-            "label" => {
-                CommandType::Label
-            },
-            "goto" => {
-                CommandType::Goto
-            },
-            "if-goto" => {
-                CommandType::If
-            },
-            "function" => {
-                CommandType::Function
+    // source line (1-based) of the current command
+    pub fn line(&self) -> usize {
+        self.current_line
+    }
+
+    // checks the current command so that command_type, arg1 and arg2 cannot panic
+    pub fn validate(&self) -> Result<(), String> {
+        let keyword = self.current_cmd[0].as_str();
+        let command_type = match CommandType::from_keyword(keyword) {
+            Some(t) => t,
+            None => return Err(format!("unknown command '{}'", keyword)),
+        };
+        let num_args = self.current_cmd.len() - 1;
+        if num_args != command_type.num_args() {
+            return Err(format!("'{}' expects {} argument(s) but got {}", keyword, command_type.num_args(), num_args));
+        }
+        match command_type {
+            CommandType::Push | CommandType::Pop => {
+                let segment = self.current_cmd[1].as_str();
+                let index = parse_index(&self.current_cmd[2])?;
+                let max = match segment {
+                    "constant" => {
+                        if command_type == CommandType::Pop {
+                            return Err(String::from("cannot pop to the constant segment"));
+                        }
+                        i16::MAX
+                    },
+                    "local" | "argument" | "this" | "that" | "static" => i16::MAX,
+                    "pointer" => 1,
+                    "temp" => 7,
+                    _ => return Err(format!("unknown segment '{}'", segment)),
+                };
+                if index > max {
+                    return Err(format!("index {} is out of range for segment '{}'", index, segment));
+                }
             },
-            "return" => {
-                CommandType::Return
+            CommandType::Function | CommandType::Call => {
+                check_symbol(&self.current_cmd[1])?;
+                parse_index(&self.current_cmd[2])?;
             },
-            "call" => {
-                CommandType::Call
+            CommandType::Label | CommandType::Goto | CommandType::If => {
+                check_symbol(&self.current_cmd[1])?;
             },
-            _ => {
-                panic!("no such command");
+            CommandType::Arithmetic => {
+                if !self.extensions && is_extension(keyword) {
+                    return Err(format!("'{}' is an extended command; enable it with --extensions", keyword));
+                }
             },
+            CommandType::Return => {},
+        }
+        Ok(())
+    }
+
+    pub fn command_type(&self) -> CommandType {
+        match CommandType::from_keyword(self.current_cmd[0].as_str()) {
+            Some(t) => t,
+            None => panic!("no such command"),
         }
     }
 
@@ -117,4 +231,108 @@ impl Parser {
         }
         self.current_cmd[2].parse::<i16>().unwrap()
     }
-}
\ No newline at end of file
+
+    // the current command as a detached value
+    pub fn command(&self) -> Command {
+        let command_type = self.command_type();
+        let arg1 = match command_type {
+            CommandType::Return => String::new(),
+            _ => self.arg1(),
+        };
+        let arg2 = match command_type {
+            CommandType::Push | CommandType::Pop |
+            CommandType::Function | CommandType::Call => self.arg2(),
+            _ => 0,
+        };
+        Command {
+            command_type,
+            arg1,
+            arg2,
+            line: self.current_line,
+        }
+    }
+}
+
+// reads all commands of a .vm source, reporting the first invalid one with its line
+pub fn parse_source(name: &str, source: &str, extensions: bool) -> Result<VmFile, String> {
+    let mut p = Parser::new(source.as_bytes());
+    p.set_extensions(extensions);
+    let mut commands = vec![];
+    while p.has_more_commands() {
+        p.advance();
+        if let Err(e) = p.validate() {
+            return Err(format!("{}.vm:{}: {}", name, p.line(), e));
+        }
+        commands.push(p.command());
+    }
+    Ok(VmFile {
+        name: name.to_string(),
+        commands,
+    })
+}
+
+// parses a .vm or .jack file, or every .vm and .jack file of a directory as one
+// program, in file name order; Jack classes are compiled, and a class's .jack
+// file is taken over the .vm file it would be compiled to
+pub fn read_program(input: &str, extensions: bool) -> Result<Vec<VmFile>, String> {
+    let arg_path = Path::new(input);
+    let mut fin_paths = vec![];
+    if arg_path.is_dir() {
+        let entries = arg_path.read_dir()
+            .map_err(|e| format!("cannot read directory '{}': {}", input, e))?;
+        for entry in entries.flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "vm" || ext == "jack") {
+                fin_paths.push(entry.path());
+            }
+        }
+        if fin_paths.is_empty() {
+            return Err(format!("no .vm or .jack files in directory '{}'", input));
+        }
+        let jack: Vec<_> = fin_paths.iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jack"))
+            .map(|path| path.with_extension("vm"))
+            .collect();
+        fin_paths.retain(|path| !jack.contains(path));
+        // read_dir order is platform dependent; keep the output reproducible
+        fin_paths.sort();
+    } else if arg_path.is_file() {
+        fin_paths.push(arg_path.to_path_buf());
+    } else {
+        return Err(format!("'{}': no such file or directory", input));
+    }
+
+    let mut files = vec![];
+    for fin_path in fin_paths.iter() {
+        let source = fs::read_to_string(fin_path)
+            .map_err(|e| format!("cannot read '{}': {}", fin_path.display(), e))?;
+        let name = fin_path.file_stem().unwrap().to_string_lossy().to_string();
+        if fin_path.extension().is_some_and(|ext| ext == "jack") {
+            files.push(jack::compile(&name, &source)?);
+        } else {
+            files.push(parse_source(&name, &source, extensions)?);
+        }
+    }
+    Ok(files)
+}
+
+pub fn is_extension(operator: &str) -> bool {
+    matches!(operator, "mul" | "div" | "mod" | "shl" | "shr")
+}
+
+fn parse_index(s: &str) -> Result<i16, String> {
+    match s.parse::<i16>() {
+        Ok(n) if n >= 0 => Ok(n),
+        _ => Err(format!("'{}' is not a valid index (expected 0..32767)", s)),
+    }
+}
+
+// labels and function names: letters, digits, '_', '.', '$', ':' and not starting with a digit
+fn check_symbol(s: &str) -> Result<(), String> {
+    let valid = !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid symbol", s))
+    }
+}
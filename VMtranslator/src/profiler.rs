@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use crate::source_map::Entry;
+use crate::stats;
+
+// Instruction counts of a translated program, collected while it runs in the
+// emulator (hackprof).
+//
+// Every instruction is charged to its source map entry, and to a node of the
+// call tree: the tree follows the calls as they happen, entering a child when a
+// `call` (or the bootstrap code) jumps to the start of a function and leaving it
+// when a `return` jumps to the address after the call. Code of another function
+// than the current node's, such as the runtime routines, the bootstrap code or a
+// function entered without a call, gets a child node of its own.
+
+#[derive(PartialEq, Clone, Copy)]
+enum Kind {
+    Call, // a call, or the bootstrap code, which calls the entry function
+    Return,
+    Other,
+}
+
+struct Node {
+    function: usize,
+    parent: usize,
+    children: HashMap<usize, usize>, // function -> node
+    own: u64,                        // instructions executed in this node itself
+    calls: u64,
+}
+
+pub struct Profile {
+    map: Vec<Entry>,
+    entry_at: Vec<usize>,
+    kinds: Vec<Kind>,           // per map entry
+    functions: Vec<String>,     // names, as in the --stats report
+    entry_function: Vec<usize>, // per map entry
+    function_start: Vec<bool>,  // per ROM address
+    counts: Vec<u64>,           // instructions executed per map entry
+    nodes: Vec<Node>,           // nodes[0] is the root
+    current: usize,
+    stack: Vec<(usize, usize)>, // calling node and return address of each call
+    total: u64,
+}
+
+impl Profile {
+    pub fn new(map: &[Entry], rom_size: usize) -> Self {
+        let mut entry_at = vec![0; rom_size];
+        let mut function_start = vec![false; rom_size];
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut functions = vec![String::from("(root)")];
+        let mut entry_function = vec![];
+        let mut kinds = vec![];
+        for (i, e) in map.iter().enumerate() {
+            entry_at[e.start..e.end].iter_mut().for_each(|a| *a = i);
+            if e.command.starts_with("function ") && e.start < rom_size {
+                function_start[e.start] = true;
+            }
+            let name = stats::function(e);
+            let id = *ids.entry(name.clone()).or_insert_with(|| {
+                functions.push(name);
+                functions.len() - 1
+            });
+            entry_function.push(id);
+            kinds.push(match e.command.split_whitespace().next() {
+                Some("call") | Some("bootstrap") => Kind::Call,
+                Some("return") => Kind::Return,
+                _ => Kind::Other,
+            });
+        }
+        let root = Node {
+            function: 0,
+            parent: 0,
+            children: HashMap::new(),
+            own: 0,
+            calls: 0,
+        };
+        Profile {
+            map: map.to_vec(),
+            entry_at,
+            kinds,
+            functions,
+            entry_function,
+            function_start,
+            counts: vec![0; map.len()],
+            nodes: vec![root],
+            current: 0,
+            stack: vec![],
+            total: 0,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // records the instruction at pc, which moved the program to next_pc
+    pub fn record(&mut self, pc: usize, next_pc: usize) {
+        let e = self.entry_at[pc];
+        self.counts[e] += 1;
+        self.total += 1;
+        let function = self.entry_function[e];
+        let node = if self.nodes[self.current].function == function {
+            self.current
+        } else {
+            self.child(self.current, function)
+        };
+        self.nodes[node].own += 1;
+
+        // the bootstrap code can end right before the function it calls, so
+        // entering a function is not always a jump
+        match self.kinds[e] {
+            Kind::Call if self.function_start.get(next_pc) == Some(&true) => {
+                let callee = self.entry_function[self.entry_at[next_pc]];
+                let child = self.child(self.current, callee);
+                self.nodes[child].calls += 1;
+                self.stack.push((self.current, self.map[e].end));
+                self.current = child;
+            },
+            // a built-in function returns from its first address
+            _ if self.function_start[pc] && self.stack.last().map(|&(_, address)| address) == Some(next_pc) => {
+                self.current = self.stack.pop().unwrap().0;
+            },
+            Kind::Return if next_pc != pc + 1 => {
+                // frames replaced by tail calls never see their return address,
+                // so the return may leave several of them
+                if let Some(depth) = self.stack.iter().rposition(|&(_, address)| address == next_pc) {
+                    self.current = self.stack[depth].0;
+                    self.stack.truncate(depth);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn child(&mut self, parent: usize, function: usize) -> usize {
+        if let Some(&child) = self.nodes[parent].children.get(&function) {
+            return child;
+        }
+        self.nodes.push(Node {
+            function,
+            parent,
+            children: HashMap::new(),
+            own: 0,
+            calls: 0,
+        });
+        let child = self.nodes.len() - 1;
+        self.nodes[parent].children.insert(function, child);
+        child
+    }
+
+    // instructions executed in each node and all of its descendants
+    fn inclusive(&self) -> Vec<u64> {
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|n| n.own).collect();
+        // children always come after their parent
+        for i in (1..self.nodes.len()).rev() {
+            inclusive[self.nodes[i].parent] += inclusive[i];
+        }
+        inclusive
+    }
+
+    // nodes in depth-first order, children by decreasing inclusive count, with
+    // their depth
+    fn walk(&self, inclusive: &[u64]) -> Vec<(usize, usize)> {
+        let mut order = vec![];
+        let mut pending = vec![(0, 0)];
+        while let Some((node, depth)) = pending.pop() {
+            order.push((node, depth));
+            let mut children: Vec<usize> = self.nodes[node].children.values().copied().collect();
+            children.sort_by(|a, b| inclusive[*a].cmp(&inclusive[*b])
+                .then(self.functions[self.nodes[*b].function].cmp(&self.functions[self.nodes[*a].function])));
+            pending.extend(children.into_iter().map(|child| (child, depth + 1)));
+        }
+        order
+    }
+
+    // per function: self and inclusive instructions, and calls; recursive calls
+    // count once towards the inclusive total
+    pub fn flat(&self) -> String {
+        let inclusive = self.inclusive();
+        let mut own = vec![0; self.functions.len()];
+        let mut total = vec![0; self.functions.len()];
+        let mut calls = vec![0; self.functions.len()];
+        let mut on_path = vec![0; self.functions.len()];
+        let mut path: Vec<usize> = vec![];
+        for (node, depth) in self.walk(&inclusive) {
+            while path.len() > depth {
+                on_path[self.nodes[path.pop().unwrap()].function] -= 1;
+            }
+            let n = &self.nodes[node];
+            own[n.function] += n.own;
+            calls[n.function] += n.calls;
+            if on_path[n.function] == 0 {
+                total[n.function] += inclusive[node];
+            }
+            on_path[n.function] += 1;
+            path.push(node);
+        }
+
+        let mut rows: Vec<usize> = (1..self.functions.len()).filter(|&f| total[f] > 0).collect();
+        rows.sort_by(|a, b| own[*b].cmp(&own[*a]).then(self.functions[*a].cmp(&self.functions[*b])));
+        let width = rows.iter().map(|&f| self.functions[f].len()).max().unwrap_or(0).max("function".len());
+        let mut s = format!("{} instructions executed\n\n", self.total);
+        s.push_str(&format!("{:<width$}  {:>12}  {:>6}  {:>12}  {:>6}  {:>8}\n",
+            "function", "self", "%", "inclusive", "%", "calls", width = width));
+        for f in rows {
+            s.push_str(&format!("{:<width$}  {:>12}  {:>6.1}  {:>12}  {:>6.1}  {:>8}\n",
+                self.functions[f], own[f], self.percent(own[f]), total[f], self.percent(total[f]), calls[f], width = width));
+        }
+        s
+    }
+
+    // the call tree, one line per node, indented by depth
+    pub fn tree(&self) -> String {
+        let inclusive = self.inclusive();
+        let mut s = format!("{:>12}  {:>6}  {:>12}  {:>8}  function\n", "inclusive", "%", "self", "calls");
+        for (node, depth) in self.walk(&inclusive) {
+            if node == 0 {
+                continue;
+            }
+            let n = &self.nodes[node];
+            s.push_str(&format!("{:>12}  {:>6.1}  {:>12}  {:>8}  {}{}\n", inclusive[node], self.percent(inclusive[node]),
+                n.own, n.calls, "  ".repeat(depth - 1), self.functions[n.function]));
+        }
+        s
+    }
+
+    // flamegraph.pl input: each call path with the instructions executed in it
+    pub fn folded(&self) -> String {
+        let inclusive = self.inclusive();
+        let mut s = String::new();
+        let mut path: Vec<&str> = vec![];
+        for (node, depth) in self.walk(&inclusive) {
+            path.truncate(depth.saturating_sub(1));
+            if node == 0 {
+                continue;
+            }
+            let n = &self.nodes[node];
+            path.push(&self.functions[n.function]);
+            if n.own > 0 {
+                s.push_str(&format!("{} {}\n", path.join(";"), n.own));
+            }
+        }
+        s
+    }
+
+    // per source line, busiest first; code outside the VM files by its name
+    pub fn lines(&self) -> String {
+        let mut lines: HashMap<String, (u64, &str)> = HashMap::new();
+        for (e, &count) in self.map.iter().zip(self.counts.iter()) {
+            if count == 0 {
+                continue;
+            }
+            let (name, command) = if e.file.is_empty() {
+                (stats::function(e), "")
+            } else {
+                (format!("{}:{}", e.file, e.line), e.command.as_str())
+            };
+            lines.entry(name).or_insert((0, command)).0 += count;
+        }
+        let mut rows: Vec<(String, (u64, &str))> = lines.into_iter().collect();
+        rows.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(&b.0)));
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("line".len());
+        let mut s = format!("{:<width$}  {:>12}  {:>6}  command\n", "line", "instructions", "%", width = width);
+        for (name, (count, command)) in rows.iter() {
+            let row = format!("{:<width$}  {:>12}  {:>6.1}  {}", name, count, self.percent(*count), command, width = width);
+            s.push_str(row.trim_end());
+            s.push('\n');
+        }
+        s
+    }
+
+    // per VM command kind, as in the --stats report
+    pub fn commands(&self) -> String {
+        let mut kinds: HashMap<String, u64> = HashMap::new();
+        for (e, &count) in self.map.iter().zip(self.counts.iter()) {
+            if count > 0 {
+                *kinds.entry(stats::kind(&e.command)).or_insert(0) += count;
+            }
+        }
+        let mut rows: Vec<(String, u64)> = kinds.into_iter().collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0).max("command".len());
+        let mut s = format!("{:<width$}  {:>12}  {:>6}\n", "command", "instructions", "%", width = width);
+        for (kind, count) in rows.iter() {
+            s.push_str(&format!("{:<width$}  {:>12}  {:>6.1}\n", kind, count, self.percent(*count), width = width));
+        }
+        s
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Options, Program};
+
+    // FibonacciElement computes fibonacci(4) in 9 calls, 4 deep
+    fn profile() -> Profile {
+        let options = Options { os: false, ..Options::default() };
+        let path = format!("{}/vm_code/FunctionCalls/FibonacciElement", env!("CARGO_MANIFEST_DIR"));
+        let program = Program::translate(&path, &options).unwrap();
+        let mut profile = Profile::new(&program.map, program.rom.len());
+        let mut emulator = program.emulator(&options);
+        while !emulator.is_halted() {
+            let pc = emulator.pc as usize;
+            emulator.step().unwrap();
+            profile.record(pc, emulator.pc as usize);
+        }
+        profile
+    }
+
+    #[test]
+    fn recursion_counts_once_towards_inclusive() {
+        assert_eq!(profile().flat(), "\
+1578 instructions executed
+
+function                self       %     inclusive       %     calls
+Main.fibonacci          1465    92.8          1465    92.8         9
+Sys.init                  60     3.8          1525    96.6         1
+(bootstrap)               53     3.4            53     3.4         0
+");
+    }
+
+    #[test]
+    fn tree_follows_the_calls() {
+        let profile = profile();
+        assert_eq!(profile.tree(), "   inclusive       %          self     calls  function
+        1525    96.6            60         1  Sys.init
+        1465    92.8           245         1    Main.fibonacci
+        1220    77.3           490         2      Main.fibonacci
+         730    46.3           536         4        Main.fibonacci
+         194    12.3           194         2          Main.fibonacci
+          53     3.4            53         0  (bootstrap)
+");
+        assert_eq!(profile.folded(), "\
+Sys.init 60
+Sys.init;Main.fibonacci 245
+Sys.init;Main.fibonacci;Main.fibonacci 490
+Sys.init;Main.fibonacci;Main.fibonacci;Main.fibonacci 536
+Sys.init;Main.fibonacci;Main.fibonacci;Main.fibonacci;Main.fibonacci 194
+(bootstrap) 53
+");
+    }
+}
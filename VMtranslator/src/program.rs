@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::assembler::{self, Cpu};
+use crate::backend;
+use crate::builtins::Builtins;
+use crate::code_writer::{Bootstrap, BootstrapMode, CodeWriter};
+use crate::emulator::Emulator;
+use crate::parser::{self, CommandType, VmFile};
+use crate::source_map::Entry;
+use crate::{inliner, optimizer, os};
+
+// A VM program translated and assembled in memory, for the tools that run the
+// Hack code and report on it in VM terms (hackdbg, hackprof, hacktrace).
+
+// the translation options these tools accept, documented by USAGE
+pub const USAGE: &str = "  --extensions       accept the extended arithmetic commands mul, div, mod,
+                     shl and shr
+  --cpu <kind>       standard (the default) or extended
+  --optimize, --inline, --tail-calls
+                     translate as VMtranslator does with these options
+  --bootstrap        always emit the bootstrap code
+  --no-bootstrap     never emit the bootstrap code, so that execution starts at
+                     the first command with all registers 0; by default it is
+                     emitted only when the program defines the entry function
+  --entry <function> function called by the bootstrap code (default Sys.init)
+  --sp <n>           initial SP set by the bootstrap code (default 256)
+  --lcl <n>, --arg <n>, --this <n>, --that <n>
+                     also initialize LCL, ARG, THIS or THAT
+  --no-os            do not link the bundled Jack OS classes the program needs
+  --os-class <path>  link <path> instead of the bundled OS class of the same
+                     name; may be repeated, and is an error when the
+                     program does not need that class
+  --builtins         run the functions of the bundled OS natively instead of
+                     emulating their code, except Keyboard's and Sys's
+";
+
+pub struct Options {
+    pub extensions: bool,
+    pub cpu: Cpu,
+    pub optimize: bool,
+    pub inline: bool,
+    pub tail_calls: bool,
+    pub bootstrap_mode: BootstrapMode,
+    pub bootstrap: Bootstrap,
+    pub os: bool,
+    pub os_classes: Vec<String>,
+    pub builtins: bool, // for emulator()
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            extensions: false,
+            cpu: Cpu::Standard,
+            optimize: false,
+            inline: false,
+            tail_calls: false,
+            bootstrap_mode: BootstrapMode::Auto,
+            bootstrap: Bootstrap::default(),
+            os: true,
+            os_classes: vec![],
+            builtins: false,
+        }
+    }
+}
+
+impl Options {
+    // takes arg, and its value from iter, if it is one of the options in USAGE;
+    // returns whether it was
+    pub fn parse<'a>(&mut self, arg: &str, iter: &mut impl Iterator<Item = &'a String>) -> Result<bool, String> {
+        match arg {
+            "--extensions" => self.extensions = true,
+            "--cpu" => match iter.next() {
+                Some(name) => self.cpu = Cpu::from_name(name)?,
+                None => return Err(String::from("option '--cpu' requires a kind")),
+            },
+            "--optimize" => self.optimize = true,
+            "--inline" => self.inline = true,
+            "--tail-calls" => self.tail_calls = true,
+            "--bootstrap" => self.bootstrap_mode = BootstrapMode::Always,
+            "--no-bootstrap" => self.bootstrap_mode = BootstrapMode::Never,
+            "--no-os" => self.os = false,
+            "--os-class" => match iter.next() {
+                Some(path) => self.os_classes.push(path.clone()),
+                None => return Err(String::from("option '--os-class' requires a path")),
+            },
+            "--builtins" => self.builtins = true,
+            _ => return self.bootstrap.parse(arg, iter),
+        }
+        Ok(true)
+    }
+}
+
+pub struct Program {
+    pub files: Vec<VmFile>, // after inlining and optimization
+    pub rom: Vec<u16>,
+    pub map: Vec<Entry>,
+    pub symbols: HashMap<String, u16>,
+    pub instructions: Vec<String>, // assembly text of each ROM address
+    pub bundled: Vec<String>,      // the OS classes linked from the bundle
+}
+
+impl Program {
+    // input is a .vm file or a directory, as for VMtranslator
+    pub fn translate(input: &str, options: &Options) -> Result<Self, String> {
+        let mut files = parser::read_program(input, options.extensions)?;
+        let bundled = if options.os {
+            os::link(&mut files, &options.os_classes, options.extensions)?
+        } else {
+            vec![]
+        };
+        if options.inline {
+            inliner::inline(&mut files, inliner::DEFAULT_THRESHOLD);
+        }
+        if options.optimize {
+            for file in files.iter_mut() {
+                file.commands = optimizer::optimize(std::mem::take(&mut file.commands));
+            }
+        }
+        let bootstrap = options.bootstrap.for_program(&files, options.bootstrap_mode);
+
+        let mut w = CodeWriter::new(vec![]);
+        w.set_cpu(options.cpu);
+        // writing into memory cannot fail
+        backend::translate(&mut w, &files, bootstrap.as_ref(), options.tail_calls).unwrap();
+        let map = w.source_map().to_vec();
+        let asm = String::from_utf8(w.into_inner()).unwrap();
+        let (rom, symbols) = assembler::assemble_with_symbols(&asm, options.cpu)?;
+        let instructions = asm.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('(') && !l.starts_with("//"))
+            .map(String::from)
+            .collect();
+        Ok(Program { files, rom, map, symbols, instructions, bundled })
+    }
+
+    // an emulator loaded with the program, running the built-ins if asked to
+    pub fn emulator(&self, options: &Options) -> Emulator {
+        let mut emulator = Emulator::new(self.rom.clone(), options.cpu);
+        if options.builtins {
+            let builtins = Builtins::new(&self.bundled, |class, index| {
+                self.symbols.get(&format!("{}.{}", class, index)).map(|&address| address as usize)
+            });
+            emulator.set_builtins(builtins, &self.symbols);
+        }
+        emulator
+    }
+
+    // the index in map of the entry each ROM address belongs to
+    pub fn entry_at(&self) -> Vec<usize> {
+        let mut entry_at = vec![0; self.rom.len()];
+        for (i, entry) in self.map.iter().enumerate() {
+            entry_at[entry.start..entry.end].iter_mut().for_each(|e| *e = i);
+        }
+        entry_at
+    }
+
+    // number of locals of each function
+    pub fn num_locals(&self) -> HashMap<String, i16> {
+        self.files.iter()
+            .flat_map(|file| file.commands.iter())
+            .filter(|cmd| cmd.command_type == CommandType::Function)
+            .map(|cmd| (cmd.arg1.clone(), cmd.arg2))
+            .collect()
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::emulator::RAM_SIZE;
+
+// Command loop shared by the debuggers. Commands are read from the script, if
+// given, or from standard input, one per line; an empty line repeats the previous
+// command. Commands that do not come from a terminal are echoed after the prompt,
+// so that a transcript reads like an interactive session.
+//
+// execute gets the command split into words and returns false to quit; its
+// errors are printed and the loop goes on.
+pub fn run(prompt: &str, script: Option<&str>, mut execute: impl FnMut(&[&str]) -> Result<bool, String>) -> Result<(), String> {
+    let input: Box<dyn BufRead> = match script {
+        Some(path) => {
+            let script = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read '{}': {}", path, e))?;
+            Box::new(io::Cursor::new(script))
+        },
+        None => Box::new(io::BufReader::new(io::stdin())),
+    };
+    let interactive = script.is_none() && io::stdin().is_terminal();
+
+    let mut previous = String::new();
+    let mut lines = input.lines();
+    loop {
+        if interactive {
+            print!("{} ", prompt);
+            io::stdout().flush().unwrap();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => return Err(format!("cannot read commands: {}", e)),
+            None => return Ok(()),
+        };
+        let line = match line.trim() {
+            "" => previous.clone(),
+            l => l.to_string(),
+        };
+        if !interactive {
+            println!("{} {}", prompt, line);
+        }
+        if line.is_empty() {
+            continue;
+        }
+        previous = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match execute(&words) {
+            Ok(true) => {},
+            Ok(false) => return Ok(()),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+// an optional repeat count, 1 by default
+pub fn count(args: &[&str]) -> Result<usize, String> {
+    match args.first() {
+        Some(n) => n.parse::<usize>().map_err(|_| format!("'{}' is not a count", n)),
+        None => Ok(1),
+    }
+}
+
+pub fn parse_address(text: Option<&&str>) -> Result<usize, String> {
+    match text.map(|t| t.parse::<usize>()) {
+        Some(Ok(n)) if n < RAM_SIZE => Ok(n),
+        Some(_) => Err(format!("expected an address in 0..{}", RAM_SIZE - 1)),
+        None => Err(String::from("an address is required")),
+    }
+}
+
+// `ram <addr> [<count>]` and `set <addr> <value>`, common to the debuggers;
+// returns false for other commands
+pub fn memory_command(ram: &mut [i16], words: &[&str]) -> Result<bool, String> {
+    let args = &words[1..];
+    match words[0] {
+        "ram" | "x" => {
+            let address = parse_address(args.first())?;
+            let count = match args.get(1) {
+                Some(n) => n.parse::<usize>().map_err(|_| format!("'{}' is not a count", n))?,
+                None => 1,
+            };
+            for (a, value) in ram.iter().enumerate().skip(address).take(count) {
+                println!("RAM[{}] = {}", a, value);
+            }
+        },
+        "set" => {
+            let address = parse_address(args.first())?;
+            let value = match args.get(1).map(|v| v.parse::<i16>()) {
+                Some(Ok(v)) => v,
+                _ => return Err(String::from("usage: set <addr> <value>, with a value in -32768..32767")),
+            };
+            ram[address] = value;
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// parses `<file>:<line>` into the file name without .vm or .jack and the line;
+// other specs, such as function names, give None
+pub fn parse_file_line(spec: &str) -> Option<(&str, usize)> {
+    let (file, line) = spec.rsplit_once(':')?;
+    let line = line.parse::<usize>().ok()?;
+    let file = file.strip_suffix(".vm").or_else(|| file.strip_suffix(".jack")).unwrap_or(file);
+    Some((file, line))
+}
+
+// breakpoints numbered from 1, at addresses in whatever the debugger steps through
+#[derive(Default)]
+pub struct Breakpoints {
+    slots: Vec<Option<(String, usize)>>, // spec and address; None once deleted
+}
+
+impl Breakpoints {
+    // returns the new breakpoint's number
+    pub fn add(&mut self, spec: &str, address: usize) -> usize {
+        self.slots.push(Some((spec.to_string(), address)));
+        self.slots.len()
+    }
+
+    // `delete [<n>]`: one breakpoint, or all of them
+    pub fn delete(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first() {
+            None => self.slots.iter_mut().for_each(|b| *b = None),
+            Some(n) => {
+                let slot = n.parse::<usize>().ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .and_then(|i| self.slots.get_mut(i))
+                    .filter(|b| b.is_some());
+                match slot {
+                    Some(b) => *b = None,
+                    None => return Err(format!("no breakpoint {}", n)),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // the number of a breakpoint at address
+    pub fn at(&self, address: usize) -> Option<usize> {
+        self.slots.iter()
+            .position(|b| b.as_ref().is_some_and(|(_, a)| *a == address))
+            .map(|i| i + 1)
+    }
+
+    // prints the breakpoints, with location naming each address
+    pub fn list(&self, location: impl Fn(usize) -> String) {
+        let mut any = false;
+        for (i, b) in self.slots.iter().enumerate() {
+            if let Some((spec, address)) = b {
+                println!("{:<4} {:<24} {}", i + 1, spec, location(*address));
+                any = true;
+            }
+        }
+        if !any {
+            println!("no breakpoints");
+        }
+    }
+}
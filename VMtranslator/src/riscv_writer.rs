@@ -0,0 +1,416 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::backend::{mangle, Backend, Location, Names};
+use crate::code_writer::Bootstrap;
+
+// RISC-V backend: translates the VM command stream into RV32I GNU assembly for
+// Linux user mode (`--emit riscv`), e.g.
+//   riscv64-unknown-elf-gcc -march=rv32i -mabi=ilp32 -nostdlib prog.rv32.s -o prog
+//   qemu-riscv32 prog [first [count]]
+//
+// The Hack RAM is a 32K array of halfwords in memory addressed through s0 (s1 holds
+// the 0x7fff address mask). The saved return address is an index into
+// rt_return_table instead of a ROM address, and returning to one outside the table
+// is an error.
+//
+// The program halts at `label L; goto L` or by falling off the end, and then
+// prints RAM[first..first+count] (default 0 16) through Linux system calls. Only
+// RV32I instructions are used: no multiply or divide.
+pub struct RiscvWriter<W: Write> {
+    writer: BufWriter<W>,
+    names: Names,
+}
+
+const RUNTIME: &str = "\
+rt_halt:
+    li s4, 0                    # first address
+    li s5, 16                   # count
+    li t0, 2
+    blt s2, t0, 1f
+    lw a0, 4(s3)
+    call rt_atoi
+    mv s4, a0
+    li t0, 3
+    blt s2, t0, 1f
+    lw a0, 8(s3)
+    call rt_atoi
+    mv s5, a0
+1:
+    blez s5, 2f
+    and s4, s4, s1
+    la a0, rt_text_ram
+    li a1, 4
+    call rt_write
+    mv a0, s4
+    call rt_write_int
+    la a0, rt_text_equals
+    li a1, 4
+    call rt_write
+    slli t0, s4, 1
+    add t0, s0, t0
+    lh a0, 0(t0)
+    call rt_write_int
+    la a0, rt_text_newline
+    li a1, 1
+    call rt_write
+    addi s4, s4, 1
+    addi s5, s5, -1
+    j 1b
+2:
+    li a0, 0
+    li a7, 93                   # exit
+    ecall
+
+# a corrupted frame's return address
+rt_bad_return:
+    li a0, 2
+    la a1, rt_text_bad_return
+    li a2, 23
+    li a7, 64                   # write
+    ecall
+    li a0, 1
+    li a7, 93                   # exit
+    ecall
+
+# a0 = buffer, a1 = length
+rt_write:
+    mv a2, a1
+    mv a1, a0
+    li a0, 1
+    li a7, 64                   # write
+    ecall
+    ret
+
+# prints a0 in decimal by repeated subtraction of powers of ten
+rt_write_int:
+    la t0, rt_buffer
+    bgez a0, 1f
+    li t1, 45                   # '-'
+    sb t1, 0(t0)
+    addi t0, t0, 1
+    neg a0, a0
+1:
+    la t2, rt_powers_of_ten
+    li t5, 0                    # a digit was written
+    li t6, 5
+2:
+    lw t3, 0(t2)
+    li t4, 0
+3:
+    blt a0, t3, 4f
+    sub a0, a0, t3
+    addi t4, t4, 1
+    j 3b
+4:
+    bnez t4, 5f
+    bnez t5, 5f
+    li t1, 1
+    beq t3, t1, 5f              # always write the last digit
+    j 6f
+5:
+    addi t4, t4, 48
+    sb t4, 0(t0)
+    addi t0, t0, 1
+    li t5, 1
+6:
+    addi t2, t2, 4
+    addi t6, t6, -1
+    bnez t6, 2b
+    la a1, rt_buffer
+    sub a2, t0, a1
+    li a0, 1
+    li a7, 64                   # write
+    ecall
+    ret
+
+# a0 = decimal string, returns its value
+rt_atoi:
+    li t0, 0
+1:
+    lbu t1, 0(a0)
+    addi t1, t1, -48
+    li t2, 10
+    bgeu t1, t2, 2f
+    slli t2, t0, 3
+    slli t3, t0, 1
+    add t0, t2, t3
+    add t0, t0, t1
+    addi a0, a0, 1
+    j 1b
+2:
+    mv a0, t0
+    ret
+
+    .section .rodata
+rt_text_ram:
+    .ascii \"RAM[\"
+rt_text_equals:
+    .ascii \"] = \"
+rt_text_newline:
+    .ascii \"\\n\"
+rt_text_bad_return:
+    .ascii \"invalid return address\\n\"
+    .p2align 2
+rt_powers_of_ten:
+    .word 10000, 1000, 100, 10, 1
+";
+
+impl<W: Write> RiscvWriter<W> {
+    pub fn new(out: W) -> Self {
+        let mut w = RiscvWriter {
+            writer: BufWriter::new(out),
+            names: Names::default(),
+        };
+        w.write_prologue();
+        w
+    }
+
+    fn write_prologue(&mut self) {
+        writeln!(self.writer, "    .text").unwrap();
+        writeln!(self.writer, "    .globl _start").unwrap();
+        writeln!(self.writer, "_start:").unwrap();
+        writeln!(self.writer, "    lw s2, 0(sp)").unwrap();   // argc
+        writeln!(self.writer, "    addi s3, sp, 4").unwrap(); // argv
+        writeln!(self.writer, "    la s0, rt_ram").unwrap();
+        writeln!(self.writer, "    li s1, 0x7fff").unwrap();
+    }
+
+    fn write_init(&mut self, bootstrap: &Bootstrap) {
+        let registers = [
+            (0, Some(bootstrap.sp)),
+            (1, bootstrap.lcl),
+            (2, bootstrap.arg),
+            (3, bootstrap.this),
+            (4, bootstrap.that),
+        ];
+        for (address, value) in registers.iter() {
+            if let Some(value) = value {
+                writeln!(self.writer, "    li t0, {}", value).unwrap();
+                writeln!(self.writer, "    sh t0, {}(s0)", address * 2).unwrap();
+            }
+        }
+        if let Some(entry) = &bootstrap.entry {
+            self.write_call(entry.clone(), 0);
+        }
+    }
+
+    // leaves the memory address of segment[index] in t4
+    fn segment_address(&mut self, segment: &str, index: i16) {
+        match self.names.locate(segment, index) {
+            Location::Based(base, index) => {
+                writeln!(self.writer, "    lhu t4, {}(s0)", base * 2).unwrap();
+                writeln!(self.writer, "    li t5, {}", index).unwrap();
+                writeln!(self.writer, "    add t4, t4, t5").unwrap();
+                writeln!(self.writer, "    and t4, t4, s1").unwrap();
+                writeln!(self.writer, "    slli t4, t4, 1").unwrap();
+                writeln!(self.writer, "    add t4, s0, t4").unwrap();
+            },
+            Location::Fixed(address) => {
+                writeln!(self.writer, "    li t4, {}", address * 2).unwrap();
+                writeln!(self.writer, "    add t4, s0, t4").unwrap();
+            },
+        }
+    }
+
+    fn push_t0(&mut self) {
+        writeln!(self.writer, "    lhu t1, 0(s0)").unwrap();
+        writeln!(self.writer, "    slli t2, t1, 1").unwrap();
+        writeln!(self.writer, "    add t2, s0, t2").unwrap();
+        writeln!(self.writer, "    sh t0, 0(t2)").unwrap();
+        writeln!(self.writer, "    addi t1, t1, 1").unwrap();
+        writeln!(self.writer, "    sh t1, 0(s0)").unwrap();
+    }
+
+    // leaves the popped value in t0 and its memory address in t2
+    fn pop_t0(&mut self) {
+        writeln!(self.writer, "    lhu t1, 0(s0)").unwrap();
+        writeln!(self.writer, "    addi t1, t1, -1").unwrap();
+        writeln!(self.writer, "    sh t1, 0(s0)").unwrap();
+        writeln!(self.writer, "    slli t2, t1, 1").unwrap();
+        writeln!(self.writer, "    add t2, s0, t2").unwrap();
+        writeln!(self.writer, "    lh t0, 0(t2)").unwrap();
+    }
+}
+
+impl<W: Write> Backend for RiscvWriter<W> {
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>) {
+        if let Some(bootstrap) = bootstrap {
+            self.write_init(bootstrap);
+        }
+    }
+
+    fn begin_file(&mut self, name: &str) {
+        self.names.begin_file(name);
+    }
+
+    fn write_arithmetic(&mut self, command: String) {
+        self.names.command();
+        match command.as_str() {
+            "add" | "sub" | "and" | "or" => {
+                self.pop_t0(); // y; t2 points at it and x is right below
+                writeln!(self.writer, "    lh t3, -2(t2)").unwrap();
+                writeln!(self.writer, "    {} t3, t3, t0", command).unwrap();
+                writeln!(self.writer, "    sh t3, -2(t2)").unwrap();
+            },
+            "neg" | "not" => {
+                writeln!(self.writer, "    lhu t1, 0(s0)").unwrap();
+                writeln!(self.writer, "    slli t2, t1, 1").unwrap();
+                writeln!(self.writer, "    add t2, s0, t2").unwrap();
+                writeln!(self.writer, "    lh t3, -2(t2)").unwrap();
+                writeln!(self.writer, "    {} t3, t3", command).unwrap();
+                writeln!(self.writer, "    sh t3, -2(t2)").unwrap();
+            },
+            "eq" | "gt" | "lt" => {
+                let set = match command.as_str() {
+                    "eq" => "seqz",
+                    "gt" => "sgtz",
+                    _ => "sltz",
+                };
+                self.pop_t0();
+                writeln!(self.writer, "    lh t3, -2(t2)").unwrap();
+                writeln!(self.writer, "    sub t3, t3, t0").unwrap();
+                writeln!(self.writer, "    slli t3, t3, 16").unwrap();
+                writeln!(self.writer, "    srai t3, t3, 16").unwrap();
+                writeln!(self.writer, "    {} t3, t3", set).unwrap();
+                writeln!(self.writer, "    neg t3, t3").unwrap(); // true is -1
+                writeln!(self.writer, "    sh t3, -2(t2)").unwrap();
+            },
+            _ => {
+                panic!("invalid arithmetic command");
+            },
+        }
+    }
+
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
+        self.names.command();
+        match command.as_str() {
+            "push" => {
+                if segment == "constant" {
+                    writeln!(self.writer, "    li t0, {}", index).unwrap();
+                } else {
+                    self.segment_address(&segment, index);
+                    writeln!(self.writer, "    lh t0, 0(t4)").unwrap();
+                }
+                self.push_t0();
+            },
+            "pop" => {
+                self.segment_address(&segment, index);
+                self.pop_t0();
+                writeln!(self.writer, "    sh t0, 0(t4)").unwrap();
+            },
+            _ => {
+                panic!("invalid command");
+            },
+        }
+    }
+
+    fn write_label(&mut self, label: String) {
+        let name = self.names.label(label);
+        writeln!(self.writer, "{}:", mangle(&name)).unwrap();
+    }
+
+    fn write_goto(&mut self, label: String) {
+        match self.names.goto(&label) {
+            Some(name) => writeln!(self.writer, "    j {}", mangle(&name)).unwrap(),
+            None => writeln!(self.writer, "    j rt_halt").unwrap(),
+        }
+    }
+
+    fn write_if(&mut self, label: String) {
+        self.names.command();
+        let name = self.names.scoped(&label);
+        self.pop_t0();
+        // branches only reach +-4KiB, so jump over an unconditional j instead
+        writeln!(self.writer, "    beqz t0, 1f").unwrap();
+        writeln!(self.writer, "    j {}", mangle(&name)).unwrap();
+        writeln!(self.writer, "1:").unwrap();
+    }
+
+    fn write_call(&mut self, function_name: String, num_args: i16) {
+        self.names.command();
+        // push return-address
+        writeln!(self.writer, "    li t0, {}", self.names.call_count).unwrap();
+        self.push_t0();
+        // push LCL, ARG, THIS, THAT
+        for address in 1..=4 {
+            writeln!(self.writer, "    lh t0, {}(s0)", address * 2).unwrap();
+            self.push_t0();
+        }
+        // ARG = SP - n - 5
+        writeln!(self.writer, "    lhu t1, 0(s0)").unwrap();
+        writeln!(self.writer, "    li t5, {}", num_args as i32 + 5).unwrap();
+        writeln!(self.writer, "    sub t1, t1, t5").unwrap();
+        writeln!(self.writer, "    sh t1, 4(s0)").unwrap();
+        // LCL = SP
+        writeln!(self.writer, "    lhu t1, 0(s0)").unwrap();
+        writeln!(self.writer, "    sh t1, 2(s0)").unwrap();
+        // goto f
+        writeln!(self.writer, "    j {}", mangle(&function_name)).unwrap();
+        writeln!(self.writer, "rt_ret_{}:", self.names.call_count).unwrap();
+        self.names.call_count += 1;
+    }
+
+    fn write_return(&mut self) {
+        self.names.command();
+        // FRAME = LCL (a1 = &RAM[FRAME]), RET = *(FRAME - 5)
+        writeln!(self.writer, "    lhu a1, 2(s0)").unwrap();
+        writeln!(self.writer, "    slli a1, a1, 1").unwrap();
+        writeln!(self.writer, "    add a1, s0, a1").unwrap();
+        writeln!(self.writer, "    lhu a2, -10(a1)").unwrap();
+        // *ARG = pop()
+        writeln!(self.writer, "    lhu t4, 4(s0)").unwrap();
+        writeln!(self.writer, "    slli t4, t4, 1").unwrap();
+        writeln!(self.writer, "    add t4, s0, t4").unwrap();
+        self.pop_t0();
+        writeln!(self.writer, "    sh t0, 0(t4)").unwrap();
+        // SP = ARG + 1
+        writeln!(self.writer, "    lhu t1, 4(s0)").unwrap();
+        writeln!(self.writer, "    addi t1, t1, 1").unwrap();
+        writeln!(self.writer, "    sh t1, 0(s0)").unwrap();
+        // THAT, THIS, ARG, LCL = *(FRAME - 1..4)
+        for (address, offset) in [(4, 2), (3, 4), (2, 6), (1, 8)] {
+            writeln!(self.writer, "    lh t0, -{}(a1)", offset).unwrap();
+            writeln!(self.writer, "    sh t0, {}(s0)", address * 2).unwrap();
+        }
+        // goto RET, if the frame holds one; branches only reach +-4KiB
+        writeln!(self.writer, "    la t0, rt_return_count").unwrap();
+        writeln!(self.writer, "    lw t0, 0(t0)").unwrap();
+        writeln!(self.writer, "    bltu a2, t0, 1f").unwrap();
+        writeln!(self.writer, "    j rt_bad_return").unwrap();
+        writeln!(self.writer, "1:").unwrap();
+        writeln!(self.writer, "    la t0, rt_return_table").unwrap();
+        writeln!(self.writer, "    slli a2, a2, 2").unwrap();
+        writeln!(self.writer, "    add t0, t0, a2").unwrap();
+        writeln!(self.writer, "    lw t0, 0(t0)").unwrap();
+        writeln!(self.writer, "    jr t0").unwrap();
+    }
+
+    fn write_function(&mut self, function_name: String, num_locals: i16) {
+        writeln!(self.writer, "{}:", mangle(&function_name)).unwrap();
+        self.names.begin_function(function_name);
+        // local variables initialization
+        writeln!(self.writer, "    li t0, 0").unwrap();
+        for _ in 0..num_locals {
+            self.push_t0();
+        }
+    }
+
+    fn end_program(&mut self) -> io::Result<()> {
+        // code falling off the end of the program halts as well
+        write!(self.writer, "{}", RUNTIME)?;
+        writeln!(self.writer, "    .p2align 2")?;
+        writeln!(self.writer, "rt_return_count:")?;
+        writeln!(self.writer, "    .word {}", self.names.call_count)?;
+        writeln!(self.writer, "rt_return_table:")?;
+        for i in 0..self.names.call_count {
+            writeln!(self.writer, "    .word rt_ret_{}", i)?;
+        }
+        writeln!(self.writer, "    .bss")?;
+        writeln!(self.writer, "    .p2align 2")?;
+        writeln!(self.writer, "rt_buffer:")?;
+        writeln!(self.writer, "    .zero 16")?;
+        writeln!(self.writer, "rt_ram:")?;
+        writeln!(self.writer, "    .zero 65536")?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,66 @@
+use crate::json;
+
+// One translated VM command and the ROM addresses [start, end) of its instructions.
+// The bootstrap code and the final halt loop get entries with an empty file.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub start: usize,
+    pub end: usize,
+    pub file: String,
+    pub function: String,
+    pub line: usize,
+    pub command: String,
+}
+
+// sidecar .map format written by `--source-map`
+pub fn to_json(entries: &[Entry]) -> String {
+    let rom_size = entries.last().map_or(0, |e| e.end);
+    let mut s = format!("{{\"version\":1,\"rom_size\":{},\"entries\":[", rom_size);
+    for (i, e) in entries.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&format!("\n{{\"start\":{},\"end\":{},\"file\":{},\"function\":{},\"line\":{},\"command\":{}}}",
+            e.start,
+            e.end,
+            json::string(&e.file),
+            json::string(&e.function),
+            e.line,
+            json::string(&e.command)));
+    }
+    s.push_str("\n]}\n");
+    s
+}
+
+// reads back a file written by to_json
+pub fn from_json(text: &str) -> Result<Vec<Entry>, String> {
+    let document = json::parse(text)?;
+    if document.get("version").and_then(|v| v.as_usize()) != Some(1) {
+        return Err(String::from("unsupported source map version"));
+    }
+    let items = document.get("entries").and_then(|e| e.as_array())
+        .ok_or("source map has no entries")?;
+    let mut entries = vec![];
+    for (i, item) in items.iter().enumerate() {
+        let number = |key: &str| item.get(key).and_then(|v| v.as_usize())
+            .ok_or(format!("source map entry {}: missing '{}'", i, key));
+        let string = |key: &str| item.get(key).and_then(|v| v.as_str()).map(String::from)
+            .ok_or(format!("source map entry {}: missing '{}'", i, key));
+        entries.push(Entry {
+            start: number("start")?,
+            end: number("end")?,
+            file: string("file")?,
+            function: string("function")?,
+            line: number("line")?,
+            command: string("command")?,
+        });
+    }
+    Ok(entries)
+}
+
+// the entry whose instructions include address; empty entries are never found
+pub fn find(entries: &[Entry], address: usize) -> Option<&Entry> {
+    // entries are in ROM order, and empty ones share their start with the next
+    let i = entries.partition_point(|e| e.end <= address);
+    entries.get(i).filter(|e| e.start <= address && address < e.end)
+}
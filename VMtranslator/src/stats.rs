@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::source_map::Entry;
+
+const ROM_SIZE: usize = 32768;
+
+// `--stats` report: emitted instructions grouped by function, file and VM command kind
+pub fn report(entries: &[Entry]) -> String {
+    let mut by_function: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut by_file: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut by_kind: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut total = 0;
+
+    for e in entries.iter() {
+        let size = e.end - e.start;
+        total += size;
+        let file = if e.file.is_empty() {
+            format!("({})", e.command)
+        } else {
+            e.file.clone()
+        };
+        add(&mut by_function, function(e), size);
+        add(&mut by_file, file, size);
+        add(&mut by_kind, kind(&e.command), size);
+    }
+
+    let mut s = String::new();
+    s.push_str(&format!("ROM usage: {} of {} instructions ({:.1}%)\n", total, ROM_SIZE,
+        total as f64 * 100.0 / ROM_SIZE as f64));
+    if total > ROM_SIZE {
+        s.push_str(&format!("warning: the program exceeds the ROM by {} instructions\n", total - ROM_SIZE));
+    }
+    s.push_str(&table("function", &by_function, total));
+    s.push_str(&table("file", &by_file, total));
+    s.push_str(&table("command", &by_kind, total));
+    s
+}
+
+fn add(counts: &mut HashMap<String, (usize, usize)>, key: String, size: usize) {
+    let entry = counts.entry(key).or_insert((0, 0));
+    entry.0 += size;
+    entry.1 += 1;
+}
+
+// the function an entry belongs to, with the code outside functions in groups
+// of its own
+pub fn function(e: &Entry) -> String {
+    if e.file.is_empty() {
+        // bootstrap, halt loop and runtime routines
+        format!("({})", e.command)
+    } else if e.function.is_empty() {
+        String::from("(top level)")
+    } else {
+        e.function.clone()
+    }
+}
+
+// push/pop are broken down by segment, everything else by keyword
+pub fn kind(command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        ["bootstrap"] | ["halt"] | ["runtime", ..] => format!("({})", command),
+        [op @ ("push" | "pop"), segment, ..] => format!("{} {}", op, segment),
+        [keyword, ..] => keyword.to_string(),
+        [] => String::new(),
+    }
+}
+
+fn table(title: &str, counts: &HashMap<String, (usize, usize)>, total: usize) -> String {
+    let mut rows: Vec<(&String, &(usize, usize))> = counts.iter().collect();
+    // largest first, ties by name so the report is stable
+    rows.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(b.0)));
+    let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0).max(title.len());
+
+    let mut s = format!("\n{:<width$}  {:>12}  {:>6}  {:>8}\n", title, "instructions", "%", "commands", width = width);
+    for (key, (size, count)) in rows.iter() {
+        let percent = if total == 0 { 0.0 } else { *size as f64 * 100.0 / total as f64 };
+        s.push_str(&format!("{:<width$}  {:>12}  {:>6.1}  {:>8}\n", key, size, percent, count, width = width));
+    }
+    s
+}
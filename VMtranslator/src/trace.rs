@@ -0,0 +1,219 @@
+use std::io::{self, Write};
+
+use crate::emulator::Emulator;
+use crate::json;
+use crate::source_map::Entry;
+use crate::stats;
+
+// Execution traces of a translated program running in the emulator (hacktrace).
+//
+// At VM granularity a record is written for each VM command once it has run,
+// which is when the program reaches the start of the next command or halts: a
+// call therefore ends at the first instruction of the called function, and a
+// command that jumps to a runtime routine ends when the routine returns. At Hack
+// granularity every instruction gets a record, with A and D as it left them.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Vm,
+    Hack,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    level: Level,
+    format: Format,
+    map: Vec<Entry>,
+    entry_at: Vec<usize>,
+    instructions: Vec<String>,
+    functions: Vec<String>, // only these, when not empty
+    depth: usize,           // stack values per VM record
+    pending: Option<(u64, usize)>, // cycle and map entry of the VM command running
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, level: Level, format: Format, map: &[Entry], instructions: &[String]) -> Self {
+        let mut entry_at = vec![0; instructions.len()];
+        for (i, entry) in map.iter().enumerate() {
+            entry_at[entry.start..entry.end].iter_mut().for_each(|e| *e = i);
+        }
+        Tracer {
+            out,
+            level,
+            format,
+            map: map.to_vec(),
+            entry_at,
+            instructions: instructions.to_vec(),
+            functions: vec![],
+            depth: 4,
+            pending: None,
+        }
+    }
+
+    // functions are matched by name, or all of a class by the class name alone
+    pub fn set_functions(&mut self, functions: &[String]) {
+        self.functions = functions.to_vec();
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    // the CSV header; nothing for JSON lines, and called before the first step
+    pub fn begin(&mut self, emulator: &Emulator) -> io::Result<()> {
+        if self.format == Format::Csv {
+            match self.level {
+                Level::Vm => writeln!(self.out, "cycle,file,line,function,command,sp,stack")?,
+                Level::Hack => writeln!(self.out, "cycle,pc,instruction,a,d,function,command")?,
+            }
+        }
+        self.pending = self.command_at(emulator.pc as usize).map(|e| (emulator.cycles, e));
+        Ok(())
+    }
+
+    // records the instruction at pc, which the emulator has just executed
+    pub fn record(&mut self, emulator: &Emulator, pc: usize) -> io::Result<()> {
+        match self.level {
+            Level::Hack => self.write_instruction(emulator, pc),
+            Level::Vm => {
+                let next = self.command_at(emulator.pc as usize);
+                if next.is_some() || emulator.is_halted() {
+                    if let Some((cycle, e)) = self.pending.take() {
+                        self.write_command(emulator, cycle, e)?;
+                    }
+                    self.pending = next.map(|e| (emulator.cycles, e));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // the map entry of the VM command starting at address
+    fn command_at(&self, address: usize) -> Option<usize> {
+        let &e = self.entry_at.get(address)?;
+        let entry = &self.map[e];
+        (entry.start == address && !entry.file.is_empty()).then_some(e)
+    }
+
+    fn selected(&self, entry: &Entry) -> bool {
+        if self.functions.is_empty() {
+            return true;
+        }
+        let function = stats::function(entry);
+        let class = function.split_once('.').map_or("", |(class, _)| class);
+        self.functions.iter().any(|f| *f == function || *f == class)
+    }
+
+    fn write_command(&mut self, emulator: &Emulator, cycle: u64, e: usize) -> io::Result<()> {
+        let entry = &self.map[e];
+        if !self.selected(entry) {
+            return Ok(());
+        }
+        let sp = emulator.ram[0] as u16 as usize;
+        // top first
+        let stack: Vec<String> = (sp.saturating_sub(self.depth)..sp.min(emulator.ram.len()))
+            .rev()
+            .map(|address| emulator.ram[address].to_string())
+            .collect();
+        match self.format {
+            Format::Jsonl => writeln!(self.out,
+                "{{\"cycle\":{},\"file\":{},\"line\":{},\"function\":{},\"command\":{},\"sp\":{},\"stack\":[{}]}}",
+                cycle, json::string(&entry.file), entry.line, json::string(&entry.function),
+                json::string(&entry.command), sp, stack.join(",")),
+            Format::Csv => writeln!(self.out, "{},{},{},{},{},{},{}",
+                cycle, csv(&entry.file), entry.line, csv(&entry.function), csv(&entry.command), sp, stack.join(" ")),
+        }
+    }
+
+    fn write_instruction(&mut self, emulator: &Emulator, pc: usize) -> io::Result<()> {
+        let entry = &self.map[self.entry_at[pc]];
+        if !self.selected(entry) {
+            return Ok(());
+        }
+        let cycle = emulator.cycles - 1;
+        let (a, d) = (emulator.a, emulator.d);
+        match self.format {
+            Format::Jsonl => writeln!(self.out,
+                "{{\"cycle\":{},\"pc\":{},\"instruction\":{},\"a\":{},\"d\":{},\"function\":{},\"command\":{}}}",
+                cycle, pc, json::string(&self.instructions[pc]), a, d,
+                json::string(&entry.function), json::string(&entry.command)),
+            Format::Csv => writeln!(self.out, "{},{},{},{},{},{},{}",
+                cycle, pc, csv(&self.instructions[pc]), a, d, csv(&entry.function), csv(&entry.command)),
+        }
+    }
+}
+
+// a CSV field, quoted when it has to be
+fn csv(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Options, Program};
+
+    // the first lines of FibonacciElement's trace
+    fn trace(level: Level, format: Format, functions: &[String], lines: usize) -> Vec<String> {
+        let options = Options { os: false, ..Options::default() };
+        let path = format!("{}/vm_code/FunctionCalls/FibonacciElement", env!("CARGO_MANIFEST_DIR"));
+        let program = Program::translate(&path, &options).unwrap();
+        let mut tracer = Tracer::new(vec![], level, format, &program.map, &program.instructions);
+        tracer.set_functions(functions);
+        let mut emulator = program.emulator(&options);
+        tracer.begin(&emulator).unwrap();
+        while !emulator.is_halted() {
+            let pc = emulator.pc as usize;
+            emulator.step().unwrap();
+            tracer.record(&emulator, pc).unwrap();
+        }
+        let text = String::from_utf8(tracer.into_inner()).unwrap();
+        text.lines().take(lines).map(String::from).collect()
+    }
+
+    // a call ends at the first instruction of the function, with the frame on
+    // the stack
+    #[test]
+    fn vm_records_follow_the_commands() {
+        assert_eq!(trace(Level::Vm, Format::Csv, &[], 6), [
+            "cycle,file,line,function,command,sp,stack",
+            "53,Sys.vm,11,Sys.init,function Sys.init 0,261,0 0 0 0",
+            "57,Sys.vm,12,Sys.init,push constant 4,262,4 0 0 0",
+            "64,Sys.vm,13,Sys.init,call Main.fibonacci 1,267,0 0 256 261",
+            "113,Main.vm,11,Main.fibonacci,function Main.fibonacci 0,267,0 0 256 261",
+            "117,Main.vm,12,Main.fibonacci,push argument 0,268,4 0 0 256",
+        ]);
+    }
+
+    #[test]
+    fn hack_records_have_a_and_d() {
+        assert_eq!(trace(Level::Hack, Format::Csv, &[], 4), [
+            "cycle,pc,instruction,a,d,function,command",
+            "0,0,@256,256,0,,bootstrap",
+            "1,1,D=A,256,256,,bootstrap",
+            "2,2,@SP,0,256,,bootstrap",
+        ]);
+    }
+
+    #[test]
+    fn functions_select_the_records() {
+        assert_eq!(trace(Level::Vm, Format::Jsonl, &[String::from("Main")], 2), [
+            r#"{"cycle":113,"file":"Main.vm","line":11,"function":"Main.fibonacci","command":"function Main.fibonacci 0","sp":267,"stack":[0,0,256,261]}"#,
+            r#"{"cycle":117,"file":"Main.vm","line":12,"function":"Main.fibonacci","command":"push argument 0","sp":268,"stack":[4,0,0,256]}"#,
+        ]);
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+
+use crate::backend::{Backend, Location, Names};
+use crate::code_writer::Bootstrap;
+
+// WebAssembly text backend (`--emit wat`).
+//
+// The module exports its linear memory as "ram" (one 64K page holding the 32K
+// Hack words, RAM[a] at byte 2a) and a function "run" that executes the program
+// until it halts. The saved return address is a block number.
+//
+// WebAssembly has no goto, so the program is cut into blocks at every label,
+// function entry and return point, and run by a dispatch loop: jumping sets $pc
+// to the target block and branches back to a br_table over all blocks.
+// Halting at `label L; goto L` or by falling off the end leaves the loop, so run()
+// returns.
+//
+// From JavaScript:
+//   const { instance } = await WebAssembly.instantiate(bytes);
+//   instance.exports.run();
+//   const ram = new Int16Array(instance.exports.ram.buffer);
+pub struct WatWriter<W: Write> {
+    writer: BufWriter<W>,
+    names: Names,
+    blocks: Vec<String>,        // code of each block, in program order
+    targets: HashMap<String, usize>, // jump target -> target number
+    target_blocks: Vec<Option<usize>>, // target number -> block, once defined
+}
+
+const RUNTIME: &str = "\
+  (memory (export \"ram\") 1)
+
+  ;; RAM[a], sign-extended
+  (func $get (param $a i32) (result i32)
+    (i32.load16_s (i32.shl (i32.and (local.get $a) (i32.const 0x7fff)) (i32.const 1))))
+
+  (func $set (param $a i32) (param $v i32)
+    (i32.store16 (i32.shl (i32.and (local.get $a) (i32.const 0x7fff)) (i32.const 1)) (local.get $v)))
+
+  (func $push (param $v i32)
+    (local $sp i32)
+    (local.set $sp (call $get (i32.const 0)))
+    (call $set (local.get $sp) (local.get $v))
+    (call $set (i32.const 0) (i32.add (local.get $sp) (i32.const 1))))
+
+  (func $pop (result i32)
+    (local $sp i32)
+    (local.set $sp (i32.sub (call $get (i32.const 0)) (i32.const 1)))
+    (call $set (i32.const 0) (local.get $sp))
+    (call $get (local.get $sp)))
+";
+
+impl<W: Write> WatWriter<W> {
+    pub fn new(out: W) -> Self {
+        WatWriter {
+            writer: BufWriter::new(out),
+            names: Names::default(),
+            blocks: vec![String::new()],
+            targets: HashMap::new(),
+            target_blocks: vec![Some(0)], // $pc starts at 0, the program's first block
+        }
+    }
+
+    fn write_init(&mut self, bootstrap: &Bootstrap) {
+        let registers = [
+            (0, Some(bootstrap.sp)),
+            (1, bootstrap.lcl),
+            (2, bootstrap.arg),
+            (3, bootstrap.this),
+            (4, bootstrap.that),
+        ];
+        for (address, value) in registers.iter() {
+            if let Some(value) = value {
+                self.emit(&format!("(call $set (i32.const {}) (i32.const {}))", address, value));
+            }
+        }
+        if let Some(entry) = &bootstrap.entry {
+            self.write_call(entry.clone(), 0);
+        }
+    }
+
+    fn emit(&mut self, code: &str) {
+        let block = self.blocks.last_mut().unwrap();
+        writeln!(block, "      {}", code).unwrap();
+    }
+
+    // the target number of a jump target, assigned on first use
+    fn target(&mut self, name: &str) -> usize {
+        if let Some(&t) = self.targets.get(name) {
+            return t;
+        }
+        let t = self.target_blocks.len();
+        self.targets.insert(name.to_string(), t);
+        self.target_blocks.push(None);
+        t
+    }
+
+    fn jump(&mut self, name: &str) -> String {
+        format!("(local.set $pc (i32.const {})) (br $dispatch)", self.target(name))
+    }
+
+    // starts a new block that control can enter from the dispatch loop
+    fn begin_block(&mut self, name: &str) {
+        let t = self.target(name);
+        self.blocks.push(String::new());
+        self.target_blocks[t] = Some(self.blocks.len() - 1);
+        let block = self.blocks.last_mut().unwrap();
+        writeln!(block, "      ;; {}", name).unwrap();
+    }
+
+    // expression for the RAM address of segment[index]
+    fn segment_address(&mut self, segment: &str, index: i16) -> String {
+        match self.names.locate(segment, index) {
+            Location::Based(base, index) => format!("(i32.add (call $get (i32.const {})) (i32.const {}))", base, index),
+            Location::Fixed(address) => format!("(i32.const {})", address),
+        }
+    }
+}
+
+impl<W: Write> Backend for WatWriter<W> {
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>) {
+        if let Some(bootstrap) = bootstrap {
+            self.write_init(bootstrap);
+        }
+    }
+
+    fn begin_file(&mut self, name: &str) {
+        self.names.begin_file(name);
+    }
+
+    fn write_arithmetic(&mut self, command: String) {
+        self.names.command();
+        let result = match command.as_str() {
+            "neg" => {
+                self.emit("(call $push (i32.sub (i32.const 0) (call $pop)))");
+                return;
+            },
+            "not" => {
+                self.emit("(call $push (i32.xor (call $pop) (i32.const -1)))");
+                return;
+            },
+            "add" => "(i32.add (local.get $x) (local.get $y))",
+            "sub" => "(i32.sub (local.get $x) (local.get $y))",
+            "and" => "(i32.and (local.get $x) (local.get $y))",
+            "or" => "(i32.or (local.get $x) (local.get $y))",
+            // true is -1
+            "eq" => "(i32.sub (i32.const 0) (i32.eqz (i32.extend16_s (i32.sub (local.get $x) (local.get $y)))))",
+            "gt" => "(i32.sub (i32.const 0) (i32.gt_s (i32.extend16_s (i32.sub (local.get $x) (local.get $y))) (i32.const 0)))",
+            "lt" => "(i32.sub (i32.const 0) (i32.lt_s (i32.extend16_s (i32.sub (local.get $x) (local.get $y))) (i32.const 0)))",
+            _ => panic!("invalid arithmetic command"),
+        };
+        self.emit("(local.set $y (call $pop))");
+        self.emit("(local.set $x (call $pop))");
+        self.emit(&format!("(call $push {})", result));
+    }
+
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
+        self.names.command();
+        match command.as_str() {
+            "push" => {
+                let value = if segment == "constant" {
+                    format!("(i32.const {})", index)
+                } else {
+                    format!("(call $get {})", self.segment_address(&segment, index))
+                };
+                self.emit(&format!("(call $push {})", value));
+            },
+            "pop" => {
+                // the address is computed before SP changes
+                let address = self.segment_address(&segment, index);
+                self.emit(&format!("(local.set $x {})", address));
+                self.emit("(call $set (local.get $x) (call $pop))");
+            },
+            _ => {
+                panic!("invalid command");
+            },
+        }
+    }
+
+    fn write_label(&mut self, label: String) {
+        let name = self.names.label(label);
+        self.begin_block(&name);
+    }
+
+    fn write_goto(&mut self, label: String) {
+        match self.names.goto(&label) {
+            Some(name) => {
+                let jump = self.jump(&name);
+                self.emit(&jump);
+            },
+            None => self.emit("(br $halt)"),
+        }
+    }
+
+    fn write_if(&mut self, label: String) {
+        self.names.command();
+        let name = self.names.scoped(&label);
+        let jump = self.jump(&name);
+        self.emit(&format!("(if (call $pop) (then {}))", jump));
+    }
+
+    fn write_call(&mut self, function_name: String, num_args: i16) {
+        self.names.command();
+        let return_point = format!("return {}", self.names.call_count);
+        self.names.call_count += 1;
+        let return_target = self.target(&return_point);
+        self.emit(&format!("(call $push (i32.const {}))", return_target)); // return-address
+        for address in 1..=4 {
+            self.emit(&format!("(call $push (call $get (i32.const {})))", address));
+        }
+        // ARG = SP - n - 5, LCL = SP
+        self.emit(&format!("(call $set (i32.const 2) (i32.sub (call $get (i32.const 0)) (i32.const {})))", num_args as i32 + 5));
+        self.emit("(call $set (i32.const 1) (call $get (i32.const 0)))");
+        let jump = self.jump(&function_name);
+        self.emit(&jump);
+        self.begin_block(&return_point);
+    }
+
+    fn write_return(&mut self) {
+        self.names.command();
+        self.emit("(local.set $frame (call $get (i32.const 1)))");
+        self.emit("(local.set $ret (call $get (i32.sub (local.get $frame) (i32.const 5))))");
+        self.emit("(call $set (call $get (i32.const 2)) (call $pop))");
+        self.emit("(call $set (i32.const 0) (i32.add (call $get (i32.const 2)) (i32.const 1)))");
+        for (address, offset) in [(4, 1), (3, 2), (2, 3), (1, 4)] {
+            self.emit(&format!("(call $set (i32.const {}) (call $get (i32.sub (local.get $frame) (i32.const {}))))", address, offset));
+        }
+        self.emit("(local.set $pc (local.get $ret))");
+        self.emit("(br $dispatch)");
+    }
+
+    fn write_function(&mut self, function_name: String, num_locals: i16) {
+        self.begin_block(&function_name);
+        self.names.begin_function(function_name);
+        for _ in 0..num_locals {
+            self.emit("(call $push (i32.const 0))");
+        }
+    }
+
+    fn end_program(&mut self) -> io::Result<()> {
+        self.emit("(br $halt)");
+        let n = self.blocks.len();
+
+        writeln!(self.writer, "(module")?;
+        write!(self.writer, "{}", RUNTIME)?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "  (func $run (export \"run\")")?;
+        writeln!(self.writer, "    (local $pc i32) (local $x i32) (local $y i32) (local $frame i32) (local $ret i32)")?;
+        writeln!(self.writer, "    loop $dispatch")?;
+        writeln!(self.writer, "    block $halt")?;
+        for i in (0..n).rev() {
+            writeln!(self.writer, "    block $b{}", i)?;
+        }
+        // targets that were never defined (calls to missing functions) halt
+        let table: Vec<String> = self.target_blocks.iter().map(|block| match block {
+            Some(b) => format!("$b{}", b),
+            None => String::from("$halt"),
+        }).collect();
+        writeln!(self.writer, "      (br_table {} $halt (local.get $pc))", table.join(" "))?;
+        for (i, code) in self.blocks.iter().enumerate() {
+            writeln!(self.writer, "    end ;; $b{}", i)?;
+            write!(self.writer, "{}", code)?;
+        }
+        writeln!(self.writer, "    end ;; $halt")?;
+        writeln!(self.writer, "    end ;; $dispatch")?;
+        writeln!(self.writer, "  )")?;
+        writeln!(self.writer, ")")?;
+        self.writer.flush()
+    }
+}
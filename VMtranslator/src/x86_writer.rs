@@ -0,0 +1,317 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::backend::{mangle, Backend, Location, Names};
+use crate::code_writer::Bootstrap;
+
+// x86-64 backend: translates the VM command stream into GNU assembly (Intel syntax)
+// for Linux, to be linked with the C library (`gcc prog.s -o prog`).
+//
+// The Hack RAM is simulated by a 32K array of 16-bit words addressed through rbx.
+// Return addresses saved in call frames are indices into rt_return_table instead of
+// ROM addresses; returning to one outside the table is an error.
+//
+// On halt the runtime prints RAM[first..first+count], with first and count taken
+// from the command line (default 0 and 16).
+pub struct X86Writer<W: Write> {
+    writer: BufWriter<W>,
+    names: Names,
+}
+
+impl<W: Write> X86Writer<W> {
+    pub fn new(out: W) -> Self {
+        let mut w = X86Writer {
+            writer: BufWriter::new(out),
+            names: Names::default(),
+        };
+        w.write_prologue();
+        w
+    }
+
+    fn write_prologue(&mut self) {
+        writeln!(self.writer, "    .intel_syntax noprefix").unwrap();
+        writeln!(self.writer, "    .text").unwrap();
+        writeln!(self.writer, "    .globl main").unwrap();
+        writeln!(self.writer, "main:").unwrap();
+        writeln!(self.writer, "    push rbx").unwrap();
+        writeln!(self.writer, "    push r12").unwrap();
+        writeln!(self.writer, "    push r13").unwrap();
+        writeln!(self.writer, "    push r14").unwrap();
+        writeln!(self.writer, "    push r15").unwrap();
+        writeln!(self.writer, "    mov r12d, edi").unwrap(); // argc
+        writeln!(self.writer, "    mov r13, rsi").unwrap();  // argv
+        writeln!(self.writer, "    lea rbx, [rip + rt_ram]").unwrap();
+    }
+
+    fn write_init(&mut self, bootstrap: &Bootstrap) {
+        let registers = [
+            (0, Some(bootstrap.sp)),
+            (1, bootstrap.lcl),
+            (2, bootstrap.arg),
+            (3, bootstrap.this),
+            (4, bootstrap.that),
+        ];
+        for (address, value) in registers.iter() {
+            if let Some(value) = value {
+                writeln!(self.writer, "    mov word ptr [rbx+{}], {}", address * 2, value).unwrap();
+            }
+        }
+        if let Some(entry) = &bootstrap.entry {
+            self.write_call(entry.clone(), 0);
+        }
+    }
+
+    // leaves the RAM address of segment[index] in edx
+    fn segment_address(&mut self, segment: &str, index: i16) {
+        match self.names.locate(segment, index) {
+            Location::Based(base, index) => {
+                writeln!(self.writer, "    movzx edx, word ptr [rbx+{}]", base * 2).unwrap();
+                writeln!(self.writer, "    add edx, {}", index).unwrap();
+                writeln!(self.writer, "    and edx, 0x7fff").unwrap();
+            },
+            Location::Fixed(address) => {
+                writeln!(self.writer, "    mov edx, {}", address).unwrap();
+            },
+        }
+    }
+
+    // SP-- and leave the new SP in rcx, so y is at [rbx+rcx*2] and x below it
+    fn pop_top(&mut self) {
+        writeln!(self.writer, "    dec word ptr [rbx]").unwrap();
+        writeln!(self.writer, "    movzx ecx, word ptr [rbx]").unwrap();
+    }
+
+    fn push_ax(&mut self) {
+        writeln!(self.writer, "    movzx ecx, word ptr [rbx]").unwrap();
+        writeln!(self.writer, "    mov word ptr [rbx+rcx*2], ax").unwrap();
+        writeln!(self.writer, "    inc word ptr [rbx]").unwrap();
+    }
+
+    fn pop_ax(&mut self) {
+        self.pop_top();
+        writeln!(self.writer, "    mov ax, word ptr [rbx+rcx*2]").unwrap();
+    }
+}
+
+impl<W: Write> Backend for X86Writer<W> {
+    fn begin_program(&mut self, bootstrap: Option<&Bootstrap>) {
+        if let Some(bootstrap) = bootstrap {
+            self.write_init(bootstrap);
+        }
+    }
+
+    fn begin_file(&mut self, name: &str) {
+        self.names.begin_file(name);
+    }
+
+    fn write_arithmetic(&mut self, command: String) {
+        self.names.command();
+        match command.as_str() {
+            "add" | "sub" | "and" | "or" => {
+                let op = match command.as_str() {
+                    "add" => "add",
+                    "sub" => "sub",
+                    "and" => "and",
+                    _ => "or",
+                };
+                self.pop_top();
+                writeln!(self.writer, "    mov ax, word ptr [rbx+rcx*2]").unwrap();     // y
+                writeln!(self.writer, "    {} word ptr [rbx+rcx*2-2], ax", op).unwrap(); // x op= y
+            },
+            "neg" => {
+                writeln!(self.writer, "    movzx ecx, word ptr [rbx]").unwrap();
+                writeln!(self.writer, "    neg word ptr [rbx+rcx*2-2]").unwrap();
+            },
+            "not" => {
+                writeln!(self.writer, "    movzx ecx, word ptr [rbx]").unwrap();
+                writeln!(self.writer, "    not word ptr [rbx+rcx*2-2]").unwrap();
+            },
+            "eq" | "gt" | "lt" => {
+                let set = match command.as_str() {
+                    "eq" => "sete",
+                    "gt" => "setg",
+                    _ => "setl",
+                };
+                self.pop_top();
+                writeln!(self.writer, "    mov dx, word ptr [rbx+rcx*2-2]").unwrap();
+                writeln!(self.writer, "    sub dx, word ptr [rbx+rcx*2]").unwrap();
+                writeln!(self.writer, "    test dx, dx").unwrap();
+                writeln!(self.writer, "    {} al", set).unwrap();
+                writeln!(self.writer, "    movzx eax, al").unwrap();
+                writeln!(self.writer, "    neg eax").unwrap(); // true is -1
+                writeln!(self.writer, "    mov word ptr [rbx+rcx*2-2], ax").unwrap();
+            },
+            _ => {
+                panic!("invalid arithmetic command");
+            },
+        }
+    }
+
+    fn write_push_pop(&mut self, command: String, segment: String, index: i16) {
+        self.names.command();
+        match command.as_str() {
+            "push" => {
+                if segment == "constant" {
+                    writeln!(self.writer, "    mov ax, {}", index).unwrap();
+                } else {
+                    self.segment_address(&segment, index);
+                    writeln!(self.writer, "    mov ax, word ptr [rbx+rdx*2]").unwrap();
+                }
+                self.push_ax();
+            },
+            "pop" => {
+                self.segment_address(&segment, index);
+                self.pop_ax();
+                writeln!(self.writer, "    mov word ptr [rbx+rdx*2], ax").unwrap();
+            },
+            _ => {
+                panic!("invalid command");
+            },
+        }
+    }
+
+    fn write_label(&mut self, label: String) {
+        let name = self.names.label(label);
+        writeln!(self.writer, "{}:", mangle(&name)).unwrap();
+    }
+
+    fn write_goto(&mut self, label: String) {
+        match self.names.goto(&label) {
+            Some(name) => writeln!(self.writer, "    jmp {}", mangle(&name)).unwrap(),
+            None => writeln!(self.writer, "    jmp rt_halt").unwrap(),
+        }
+    }
+
+    fn write_if(&mut self, label: String) {
+        self.names.command();
+        let name = self.names.scoped(&label);
+        self.pop_ax();
+        writeln!(self.writer, "    test ax, ax").unwrap();
+        writeln!(self.writer, "    jnz {}", mangle(&name)).unwrap();
+    }
+
+    fn write_call(&mut self, function_name: String, num_args: i16) {
+        self.names.command();
+        // push return-address
+        writeln!(self.writer, "    mov ax, {}", self.names.call_count).unwrap();
+        self.push_ax();
+        // push LCL, ARG, THIS, THAT
+        for address in 1..=4 {
+            writeln!(self.writer, "    mov ax, word ptr [rbx+{}]", address * 2).unwrap();
+            self.push_ax();
+        }
+        // ARG = SP - n - 5
+        writeln!(self.writer, "    movzx eax, word ptr [rbx]").unwrap();
+        writeln!(self.writer, "    sub eax, {}", num_args as i32 + 5).unwrap();
+        writeln!(self.writer, "    mov word ptr [rbx+4], ax").unwrap();
+        // LCL = SP
+        writeln!(self.writer, "    mov ax, word ptr [rbx]").unwrap();
+        writeln!(self.writer, "    mov word ptr [rbx+2], ax").unwrap();
+        // goto f
+        writeln!(self.writer, "    jmp {}", mangle(&function_name)).unwrap();
+        writeln!(self.writer, "rt_ret_{}:", self.names.call_count).unwrap();
+        self.names.call_count += 1;
+    }
+
+    fn write_return(&mut self) {
+        self.names.command();
+        // FRAME = LCL, RET = *(FRAME - 5)
+        writeln!(self.writer, "    movzx esi, word ptr [rbx+2]").unwrap();
+        writeln!(self.writer, "    movzx edi, word ptr [rbx+rsi*2-10]").unwrap();
+        // *ARG = pop(), SP = ARG + 1
+        self.pop_ax();
+        writeln!(self.writer, "    movzx ecx, word ptr [rbx+4]").unwrap();
+        writeln!(self.writer, "    mov word ptr [rbx+rcx*2], ax").unwrap();
+        writeln!(self.writer, "    lea eax, [rcx+1]").unwrap();
+        writeln!(self.writer, "    mov word ptr [rbx], ax").unwrap();
+        // THAT, THIS, ARG, LCL = *(FRAME - 1..4)
+        for (address, offset) in [(4, 2), (3, 4), (2, 6), (1, 8)] {
+            writeln!(self.writer, "    mov ax, word ptr [rbx+rsi*2-{}]", offset).unwrap();
+            writeln!(self.writer, "    mov word ptr [rbx+{}], ax", address * 2).unwrap();
+        }
+        // goto RET, if the frame holds one
+        writeln!(self.writer, "    cmp rdi, qword ptr [rip + rt_return_count]").unwrap();
+        writeln!(self.writer, "    jae rt_bad_return").unwrap();
+        writeln!(self.writer, "    lea rax, [rip + rt_return_table]").unwrap();
+        writeln!(self.writer, "    jmp qword ptr [rax+rdi*8]").unwrap();
+    }
+
+    fn write_function(&mut self, function_name: String, num_locals: i16) {
+        writeln!(self.writer, "{}:", mangle(&function_name)).unwrap();
+        self.names.begin_function(function_name);
+        // local variables initialization
+        writeln!(self.writer, "    xor eax, eax").unwrap();
+        for _ in 0..num_locals {
+            self.push_ax();
+        }
+    }
+
+    fn end_program(&mut self) -> io::Result<()> {
+        // runtime: print the requested RAM range and exit;
+        // code falling off the end of the program arrives here as well
+        writeln!(self.writer, "rt_halt:")?;
+        writeln!(self.writer, "    xor r14d, r14d")?;
+        writeln!(self.writer, "    mov r15d, 16")?;
+        writeln!(self.writer, "    cmp r12d, 2")?;
+        writeln!(self.writer, "    jl 1f")?;
+        writeln!(self.writer, "    mov rdi, qword ptr [r13+8]")?;
+        writeln!(self.writer, "    call atoi@PLT")?;
+        writeln!(self.writer, "    mov r14d, eax")?;
+        writeln!(self.writer, "    cmp r12d, 3")?;
+        writeln!(self.writer, "    jl 1f")?;
+        writeln!(self.writer, "    mov rdi, qword ptr [r13+16]")?;
+        writeln!(self.writer, "    call atoi@PLT")?;
+        writeln!(self.writer, "    mov r15d, eax")?;
+        writeln!(self.writer, "1:")?;
+        writeln!(self.writer, "    test r15d, r15d")?;
+        writeln!(self.writer, "    jle 2f")?;
+        writeln!(self.writer, "    and r14d, 0x7fff")?;
+        writeln!(self.writer, "    lea rdi, [rip + rt_format]")?;
+        writeln!(self.writer, "    mov esi, r14d")?;
+        writeln!(self.writer, "    movsx edx, word ptr [rbx+r14*2]")?;
+        writeln!(self.writer, "    xor eax, eax")?;
+        writeln!(self.writer, "    call printf@PLT")?;
+        writeln!(self.writer, "    inc r14d")?;
+        writeln!(self.writer, "    dec r15d")?;
+        writeln!(self.writer, "    jmp 1b")?;
+        writeln!(self.writer, "2:")?;
+        writeln!(self.writer, "    xor eax, eax")?;
+        writeln!(self.writer, "rt_exit:")?;
+        writeln!(self.writer, "    pop r15")?;
+        writeln!(self.writer, "    pop r14")?;
+        writeln!(self.writer, "    pop r13")?;
+        writeln!(self.writer, "    pop r12")?;
+        writeln!(self.writer, "    pop rbx")?;
+        writeln!(self.writer, "    ret")?;
+        // runtime: a corrupted frame's return address, edi, is reported on
+        // standard error with exit status 1
+        writeln!(self.writer, "rt_bad_return:")?;
+        writeln!(self.writer, "    mov edx, edi")?;
+        writeln!(self.writer, "    mov rax, qword ptr [rip + stderr@GOTPCREL]")?;
+        writeln!(self.writer, "    mov rdi, qword ptr [rax]")?;
+        writeln!(self.writer, "    lea rsi, [rip + rt_bad_return_format]")?;
+        writeln!(self.writer, "    xor eax, eax")?;
+        writeln!(self.writer, "    call fprintf@PLT")?;
+        writeln!(self.writer, "    mov eax, 1")?;
+        writeln!(self.writer, "    jmp rt_exit")?;
+
+        writeln!(self.writer, "    .section .rodata")?;
+        writeln!(self.writer, "rt_format:")?;
+        writeln!(self.writer, "    .string \"RAM[%d] = %d\\n\"")?;
+        writeln!(self.writer, "rt_bad_return_format:")?;
+        writeln!(self.writer, "    .string \"invalid return address %u\\n\"")?;
+        writeln!(self.writer, "    .data")?;
+        writeln!(self.writer, "    .p2align 3")?;
+        writeln!(self.writer, "rt_return_count:")?;
+        writeln!(self.writer, "    .quad {}", self.names.call_count)?;
+        writeln!(self.writer, "rt_return_table:")?;
+        for i in 0..self.names.call_count {
+            writeln!(self.writer, "    .quad rt_ret_{}", i)?;
+        }
+        writeln!(self.writer, "    .bss")?;
+        writeln!(self.writer, "    .p2align 4")?;
+        writeln!(self.writer, "rt_ram:")?;
+        writeln!(self.writer, "    .zero 65536")?;
+        writeln!(self.writer, "    .section .note.GNU-stack,\"\",@progbits")?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use vmtranslator::assembler::{self, Cpu};
+use vmtranslator::backend::{self, Backend};
+use vmtranslator::code_writer::{Bootstrap, BootstrapMode, CodeWriter};
+use vmtranslator::emulator::Emulator;
+use vmtranslator::parser::{self, VmFile};
+use vmtranslator::{c_backend, os, riscv_writer, wat_writer, x86_writer};
+
+// The native backends: their text for a small program, compared with the
+// snapshots in tests/programs/Native/expected, and where gcc is installed the
+// RAM the x86 and C programs end with, compared with the CPU emulator's.
+
+#[derive(Clone, Copy)]
+enum Target {
+    X86,
+    C,
+    Wat,
+    Riscv,
+}
+
+fn read(dir: &str) -> Vec<VmFile> {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), dir);
+    let mut files = parser::read_program(&path, false).unwrap();
+    os::link(&mut files, &[], false).unwrap();
+    files
+}
+
+// the registers the .tst scripts set, and a call of Sys.init if there is one
+fn bootstrap(files: &[VmFile]) -> Option<Bootstrap> {
+    let bootstrap = Bootstrap {
+        lcl: Some(300),
+        arg: Some(400),
+        this: Some(3000),
+        that: Some(3010),
+        ..Bootstrap::default()
+    };
+    bootstrap.for_program(files, BootstrapMode::Always)
+}
+
+fn translate(files: &[VmFile], target: Target) -> String {
+    let mut out = vec![];
+    let mut w: Box<dyn Backend + '_> = match target {
+        Target::X86 => Box::new(x86_writer::X86Writer::new(&mut out)),
+        Target::C => Box::new(c_backend::CWriter::new(&mut out)),
+        Target::Wat => Box::new(wat_writer::WatWriter::new(&mut out)),
+        Target::Riscv => Box::new(riscv_writer::RiscvWriter::new(&mut out)),
+    };
+    let bootstrap = Bootstrap::default().for_program(files, BootstrapMode::Auto);
+    backend::translate(w.as_mut(), files, bootstrap.as_ref(), false).unwrap();
+    drop(w);
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn output_matches_the_snapshots() {
+    let files = read("tests/programs/Native");
+    let expected = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/Native/expected");
+    for (target, extension) in [(Target::X86, "s"), (Target::C, "c"), (Target::Wat, "wat"), (Target::Riscv, "rv32.s")] {
+        let snapshot = fs::read_to_string(expected.join(format!("Sys.{}", extension))).unwrap();
+        let text = translate(&files, target);
+        for (i, (line, expected)) in text.lines().zip(snapshot.lines()).enumerate() {
+            assert_eq!(line, expected, "Sys.{}:{}", extension, i + 1);
+        }
+        assert_eq!(text.lines().count(), snapshot.lines().count(), "Sys.{}", extension);
+    }
+}
+
+// the RAM the translated Hack program ends with
+fn emulate(files: &[VmFile]) -> Vec<i16> {
+    let mut w = CodeWriter::new(vec![]);
+    backend::translate(&mut w, files, bootstrap(files).as_ref(), false).unwrap();
+    let asm = String::from_utf8(w.into_inner()).unwrap();
+    let mut emulator = Emulator::new(assembler::assemble(&asm, Cpu::Standard).unwrap(), Cpu::Standard);
+    assert!(emulator.run(10_000_000).unwrap());
+    emulator.ram
+}
+
+// the RAM the program built with gcc prints on halting, or None without gcc
+fn run_native(files: &[VmFile], target: Target, name: &str) -> Option<Vec<i16>> {
+    Command::new("gcc").arg("--version").output().ok()?;
+    let mut out = vec![];
+    let mut w: Box<dyn Backend + '_> = match target {
+        Target::X86 => Box::new(x86_writer::X86Writer::new(&mut out)),
+        _ => Box::new(c_backend::CWriter::new(&mut out)),
+    };
+    backend::translate(w.as_mut(), files, bootstrap(files).as_ref(), false).unwrap();
+    drop(w);
+
+    let dir = std::env::temp_dir().join(format!("vmtranslator-backends-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let extension = if matches!(target, Target::X86) { "s" } else { "c" };
+    let source = dir.join(format!("{}.{}", name, extension));
+    let binary = dir.join(format!("{}-{}", name, extension));
+    fs::write(&source, out).unwrap();
+    let gcc = Command::new("gcc").arg("-o").arg(&binary).arg(&source).output().unwrap();
+    assert!(gcc.status.success(), "gcc {}: {}", source.display(), String::from_utf8_lossy(&gcc.stderr));
+    let run = Command::new(&binary).args(["0", "32768"]).output().unwrap();
+    assert!(run.status.success(), "{}: {}", binary.display(), String::from_utf8_lossy(&run.stderr));
+    fs::remove_file(&source).unwrap();
+    fs::remove_file(&binary).unwrap();
+
+    // "RAM[a] = v" for every address
+    let ram = String::from_utf8(run.stdout).unwrap().lines()
+        .map(|line| line.split(" = ").nth(1).unwrap().parse::<i16>().unwrap())
+        .collect();
+    Some(ram)
+}
+
+// the saved return addresses are ROM addresses in Hack and indices natively, so
+// only RAM outside the stack is compared: the registers and temp, the statics,
+// the segments the scripts set up, and the heap; R13..R15 are the Hack
+// translation's own scratch registers
+const COMPARED: [(usize, usize); 5] = [(0, 13), (16, 256), (300, 310), (400, 410), (2048, 16384)];
+
+#[test]
+fn native_ram_matches_the_emulator() {
+    let programs = [
+        "vm_code/MemoryAccess/BasicTest",
+        "vm_code/MemoryAccess/PointerTest",
+        "vm_code/MemoryAccess/StaticTest",
+        "vm_code/StackArithmetic/StackTest",
+        "vm_code/FunctionCalls/FibonacciElement",
+        "vm_code/FunctionCalls/StaticsTest",
+        "tests/programs/Segments",
+        "tests/programs/Native",
+        "tests/programs/Jack",
+    ];
+    for dir in programs {
+        let files = read(dir);
+        let expected = emulate(&files);
+        let name = Path::new(dir).file_name().unwrap().to_string_lossy().to_string();
+        for target in [Target::X86, Target::C] {
+            let ram = match run_native(&files, target, &name) {
+                Some(ram) => ram,
+                None => return, // no gcc
+            };
+            for &(start, end) in COMPARED.iter() {
+                for address in start..end {
+                    assert_eq!(ram[address], expected[address], "{} ({}): RAM[{}]", dir,
+                        if matches!(target, Target::X86) { "x86" } else { "C" }, address);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::process::Command;
+
+// hackdbg running a script on FibonacciElement: breakpoints, the backtrace and
+// segments inside the recursion, and stepping back out of it.
+
+const SCRIPT: &str = "\
+break Main.fibonacci
+continue
+continue
+continue
+backtrace
+print argument
+finish
+stack
+delete
+next
+step
+
+c
+";
+
+const OUTPUT: &str = "\
+bootstrap (ROM[0]: @256)
+(hackdbg) break Main.fibonacci
+breakpoint 1 at Main.vm:11 (ROM[53])
+(hackdbg) continue
+breakpoint 1, Main.fibonacci
+Main.vm:11: function Main.fibonacci 0
+(hackdbg) continue
+breakpoint 1, Main.fibonacci
+Main.vm:11: function Main.fibonacci 0
+(hackdbg) continue
+breakpoint 1, Main.fibonacci
+Main.vm:11: function Main.fibonacci 0
+(hackdbg) backtrace
+#0   Main.fibonacci(0) at Main.vm:11
+#1   Main.fibonacci(2) at Main.vm:24
+#2   Main.fibonacci(4) at Main.vm:24
+#3   Sys.init() at Sys.vm:13
+(hackdbg) print argument
+argument 0 = 0 (RAM[273])
+(hackdbg) finish
+Main.vm:25: push argument 0
+(hackdbg) stack
+RAM[273] = 0
+(hackdbg) delete
+(hackdbg) next
+Main.vm:26: push constant 1
+(hackdbg) step
+Main.vm:27: sub
+(hackdbg) step
+Main.vm:28: call Main.fibonacci 1
+(hackdbg) c
+program halted after 1578 instructions
+";
+
+#[test]
+fn script_walks_the_recursion() {
+    let script = std::env::temp_dir().join(format!("hackdbg-{}.txt", std::process::id()));
+    fs::write(&script, SCRIPT).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_hackdbg"))
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/vm_code/FunctionCalls/FibonacciElement"))
+        .arg("--no-os")
+        .arg("-x")
+        .arg(&script)
+        .output()
+        .unwrap();
+    fs::remove_file(&script).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    for (i, (line, expected)) in stdout.lines().zip(OUTPUT.lines()).enumerate() {
+        assert_eq!(line, expected, "line {}", i + 1);
+    }
+    assert_eq!(stdout.lines().count(), OUTPUT.lines().count());
+}
@@ -0,0 +1,24 @@
+use vmtranslator::program::{Options, Program};
+
+// A directory of Jack classes compiled, linked with the bundled OS and run in
+// the emulator until Sys.halt.
+
+#[test]
+fn jack_program_runs_with_the_os() {
+    let options = Options::default();
+    let path = format!("{}/tests/programs/Jack", env!("CARGO_MANIFEST_DIR"));
+    let program = Program::translate(&path, &options).unwrap();
+    let mut emulator = program.emulator(&options);
+    assert!(emulator.run(10_000_000).unwrap(), "the program did not halt");
+
+    // the odd squares 1 + 9 + 25 + 49 + 81, and 1 for each of the five even ones
+    assert_eq!(emulator.ram[8000], 160);
+    assert_eq!(emulator.ram[8001], 81);
+    assert_eq!(emulator.ram[8002], 13);
+    assert_eq!(emulator.ram[8003], 16);
+    assert_eq!(emulator.ram[8004], 3);
+    assert_eq!(emulator.ram[8005], 5040);
+    assert_eq!(emulator.ram[8006], -14);
+    assert_eq!(emulator.ram[8007], 0);
+    assert_eq!(emulator.ram[8008], 5);
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vmtranslator::backend;
+use vmtranslator::code_writer::{Bootstrap, BootstrapMode, CodeWriter};
+use vmtranslator::lifter::{self, Lifted};
+use vmtranslator::parser::{self, VmFile};
+
+// hacklift run over the assembly of the nand2tetris test programs: the .asm
+// files committed next to them, and the programs translated again.
+
+// the files under dir, in a fixed order
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn with_extension(dir: &str, extension: &str) -> Vec<PathBuf> {
+    let mut files = vec![];
+    walk(&Path::new(env!("CARGO_MANIFEST_DIR")).join(dir), &mut files);
+    files.retain(|path| path.extension().is_some_and(|e| e == extension));
+    files
+}
+
+// each file's name and commands as --emit ir writes them, sorted by name
+fn ir(files: &[VmFile]) -> Vec<(String, Vec<String>)> {
+    let mut ir: Vec<(String, Vec<String>)> = files.iter()
+        .map(|file| (file.name.clone(), file.commands.iter().map(|cmd| cmd.to_string()).collect()))
+        .collect();
+    ir.sort();
+    ir
+}
+
+#[test]
+fn committed_asm_lifts_completely() {
+    let paths = with_extension("vm_code", "asm");
+    assert!(!paths.is_empty());
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        for region in lifter::lift(&source, &name) {
+            if let Lifted::Unrecognized(instructions) = region.lifted {
+                panic!("{}: ROM[{}..{}] unrecognized: {:?}", path.display(), region.start, region.end - 1, instructions);
+            }
+        }
+    }
+}
+
+#[test]
+fn translated_programs_lift_to_their_commands() {
+    let mut dirs: Vec<PathBuf> = with_extension("vm_code", "vm").into_iter()
+        .chain(with_extension("tests/programs", "vm"))
+        .map(|path| path.parent().unwrap().to_path_buf())
+        .collect();
+    dirs.dedup();
+    for dir in dirs {
+        let files = parser::read_program(&dir.to_string_lossy(), false).unwrap();
+        let bootstrap = Bootstrap::default().for_program(&files, BootstrapMode::Auto);
+        let mut w = CodeWriter::new(vec![]);
+        backend::translate(&mut w, &files, bootstrap.as_ref(), false).unwrap();
+        let asm = String::from_utf8(w.into_inner()).unwrap();
+
+        let name = dir.file_name().unwrap().to_string_lossy();
+        let regions = lifter::lift(&asm, &name);
+        assert!(regions.iter().all(|region| !matches!(region.lifted, Lifted::Unrecognized(_))), "{}", dir.display());
+        assert_eq!(ir(&lifter::files(&regions)), ir(&files), "{}", dir.display());
+    }
+}
@@ -0,0 +1,127 @@
+use vmtranslator::code_writer::BootstrapMode;
+use vmtranslator::emulator::Emulator;
+use vmtranslator::program::{Options, Program};
+
+// The nand2tetris test programs in vm_code, translated and run in the emulator
+// from the RAM their .tst scripts set, and checked against the values of their
+// .cmp files.
+
+const CYCLES: u64 = 1_000_000;
+
+// runs the program in dir (relative to the crate) from ram; bootstrap as the
+// .tst script translates it
+fn run(dir: &str, bootstrap: bool, ram: &[(usize, i16)]) -> (Program, Emulator) {
+    let bootstrap_mode = if bootstrap { BootstrapMode::Auto } else { BootstrapMode::Never };
+    let options = Options { bootstrap_mode, os: false, ..Options::default() };
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), dir);
+    let program = Program::translate(&path, &options).unwrap();
+    let mut emulator = program.emulator(&options);
+    for &(address, value) in ram.iter() {
+        emulator.ram[address] = value;
+    }
+    assert!(emulator.run(CYCLES).unwrap(), "{} did not halt", dir);
+    (program, emulator)
+}
+
+fn check(dir: &str, bootstrap: bool, ram: &[(usize, i16)], expected: &[(usize, i16)]) {
+    let (_, emulator) = run(dir, bootstrap, ram);
+    for &(address, value) in expected.iter() {
+        assert_eq!(emulator.ram[address], value, "{}: RAM[{}]", dir, address);
+    }
+}
+
+// SP, LCL, ARG, THIS and THAT as most scripts set them
+const SEGMENTS: [(usize, i16); 5] = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+
+#[test]
+fn basic_test() {
+    check("vm_code/MemoryAccess/BasicTest", false, &SEGMENTS, &[
+        (256, 472), (300, 10), (401, 21), (402, 22), (3006, 36), (3012, 42), (3015, 45), (11, 510),
+    ]);
+}
+
+#[test]
+fn pointer_test() {
+    check("vm_code/MemoryAccess/PointerTest", false, &[(0, 256)], &[
+        (256, 6084), (3, 3030), (4, 3040), (3032, 32), (3046, 46),
+    ]);
+}
+
+#[test]
+fn static_test() {
+    check("vm_code/MemoryAccess/StaticTest", false, &[(0, 256)], &[(256, 1110)]);
+}
+
+#[test]
+fn stack_test() {
+    check("vm_code/StackArithmetic/StackTest", false, &[(0, 256)], &[
+        (0, 266), (256, -1), (257, 0), (258, 0), (259, 0), (260, -1),
+        (261, 0), (262, -1), (263, 0), (264, 0), (265, -91),
+    ]);
+}
+
+#[test]
+fn basic_loop() {
+    check("vm_code/ProgramFlow/BasicLoop", false, &[(0, 256), (1, 300), (2, 400), (400, 3)], &[
+        (0, 257), (256, 6),
+    ]);
+}
+
+#[test]
+fn fibonacci_series() {
+    check("vm_code/ProgramFlow/FibonacciSeries", false, &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)], &[
+        (3000, 0), (3001, 1), (3002, 1), (3003, 2), (3004, 3), (3005, 5),
+    ]);
+}
+
+#[test]
+fn simple_function() {
+    // returns to ROM address 1000, past the end of the program
+    check("vm_code/FunctionCalls/SimpleFunction", false, &[
+        (0, 317), (1, 317), (2, 310), (3, 3000), (4, 4000),
+        (310, 1234), (311, 37), (312, 1000), (313, 305), (314, 300), (315, 3010), (316, 4010),
+    ], &[
+        (0, 311), (1, 305), (2, 300), (3, 3010), (4, 4010), (310, 1196),
+    ]);
+}
+
+#[test]
+fn nested_call() {
+    let mut ram = vec![(0, 261), (1, 261), (2, 256), (3, -3), (4, -4), (5, -1), (6, -1)];
+    // the frame of the call of Sys.init, then a stack of -1s to see locals cleared
+    ram.extend([(256, 1234), (257, -1), (258, -2), (259, -3), (260, -4)]);
+    ram.extend((261..300).map(|address| (address, -1)));
+    check("vm_code/FunctionCalls/NestedCall", false, &ram, &[
+        (0, 261), (1, 261), (2, 256), (3, 4000), (4, 5000), (5, 135), (6, 246),
+    ]);
+}
+
+#[test]
+fn fibonacci_element() {
+    check("vm_code/FunctionCalls/FibonacciElement", true, &[], &[(0, 262), (261, 3)]);
+}
+
+#[test]
+fn statics_test() {
+    check("vm_code/FunctionCalls/StaticsTest", true, &[], &[(0, 263), (261, -2), (262, 8)]);
+}
+
+// every form of the push and pop templates of local, argument, this and that
+#[test]
+fn segment_indexes() {
+    let bases = [(1, 300), (2, 400), (3, 3000), (4, 3010)];
+    let mut ram = vec![(0, 256)];
+    ram.extend(bases);
+    let (program, emulator) = run("tests/programs/Segments", false, &ram);
+    let mut n = 0;
+    for (k, &(_, base)) in bases.iter().enumerate() {
+        for index in [0, 1, 2, 6, 7] {
+            let value = 100 * (k as i16 + 1) + index;
+            assert_eq!(emulator.ram[(base + index) as usize], value, "pop to RAM[{}]", base + index);
+            let address = program.symbols[&format!("Segments.{}", n)] as usize;
+            assert_eq!(emulator.ram[address], value, "push from RAM[{}]", base + index);
+            n += 1;
+        }
+    }
+    assert_eq!(emulator.ram[0], 256);
+}
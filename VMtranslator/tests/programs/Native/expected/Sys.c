@@ -0,0 +1,104 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#pragma GCC diagnostic ignored "-Wunused-label"
+
+static int16_t ram[32768];
+
+#define SP   ram[0]
+#define LCL  ram[1]
+#define ARG  ram[2]
+#define THIS ram[3]
+#define THAT ram[4]
+#define AT(a) ram[(uint16_t)(a) & 0x7fff]
+#define PUSH(v) (AT(SP) = (int16_t)(v), SP++)
+#define POP() (SP--, AT(SP))
+#define TOP AT(SP - 1)
+
+int main(int argc, char **argv) {
+    int16_t x, y;
+    uint16_t frame, ret = 0;
+    (void)x; (void)y; (void)frame;
+    SP = 256;
+    PUSH(0);
+    PUSH(LCL); PUSH(ARG); PUSH(THIS); PUSH(THAT);
+    ARG = (int16_t)(SP - 0 - 5);
+    LCL = SP;
+    goto vm_Sys_2einit;
+rt_ret_0:;
+
+    /* function Sys.init */
+vm_Sys_2einit:;
+    for (x = 0; x < 1; x++) PUSH(0);
+    PUSH(5);
+    x = (int16_t)(LCL + 0); AT(x) = POP();
+vm_Sys_2einit_24LOOP:;
+    PUSH(AT(LCL + 0));
+    PUSH(3);
+    PUSH(1);
+    PUSH(LCL); PUSH(ARG); PUSH(THIS); PUSH(THAT);
+    ARG = (int16_t)(SP - 2 - 5);
+    LCL = SP;
+    goto vm_Sys_2estep;
+rt_ret_1:;
+    x = (int16_t)(16); AT(x) = POP();
+    PUSH(AT(LCL + 0));
+    PUSH(1);
+    y = POP(); TOP = (int16_t)(TOP - y);
+    x = (int16_t)(LCL + 0); AT(x) = POP();
+    PUSH(AT(LCL + 0));
+    if (POP() != 0) goto vm_Sys_2einit_24LOOP;
+    PUSH(AT(16));
+    TOP = (int16_t)-TOP;
+    PUSH(9);
+    y = POP(); x = (int16_t)(TOP - y); TOP = x > 0 ? -1 : 0;
+    TOP = ~TOP;
+    x = (int16_t)(6); AT(x) = POP();
+vm_Sys_2einit_24HALT:;
+    goto rt_halt;
+
+    /* function Sys.step */
+vm_Sys_2estep:;
+    PUSH(AT(ARG + 0));
+    PUSH(AT(ARG + 1));
+    y = POP(); TOP = (int16_t)(TOP + y);
+    PUSH(12);
+    y = POP(); TOP = TOP & y;
+    PUSH(1);
+    y = POP(); TOP = TOP | y;
+    PUSH(AT(ARG + 0));
+    PUSH(AT(ARG + 1));
+    y = POP(); x = (int16_t)(TOP - y); TOP = x > 0 ? -1 : 0;
+    PUSH(AT(ARG + 0));
+    PUSH(AT(ARG + 1));
+    y = POP(); x = (int16_t)(TOP - y); TOP = x == 0 ? -1 : 0;
+    y = POP(); TOP = (int16_t)(TOP + y);
+    x = (int16_t)(4); AT(x) = POP();
+    frame = LCL;
+    ret = AT(frame - 5);
+    AT(ARG) = POP();
+    SP = (int16_t)(ARG + 1);
+    THAT = AT(frame - 1);
+    THIS = AT(frame - 2);
+    ARG = AT(frame - 3);
+    LCL = AT(frame - 4);
+    goto rt_return;
+    goto rt_halt;
+
+rt_return:
+    switch (ret) {
+    case 0: goto rt_ret_0;
+    case 1: goto rt_ret_1;
+    default: fprintf(stderr, "invalid return address %u\n", ret); return 1;
+    }
+
+rt_halt:;
+    int first = argc > 1 ? atoi(argv[1]) : 0;
+    int count = argc > 2 ? atoi(argv[2]) : 16;
+    for (int i = 0; i < count; i++) {
+        int a = (first + i) & 0x7fff;
+        printf("RAM[%d] = %d\n", a, ram[a]);
+    }
+    return 0;
+}
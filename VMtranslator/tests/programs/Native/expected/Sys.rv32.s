@@ -0,0 +1,612 @@
+    .text
+    .globl _start
+_start:
+    lw s2, 0(sp)
+    addi s3, sp, 4
+    la s0, rt_ram
+    li s1, 0x7fff
+    li t0, 256
+    sh t0, 0(s0)
+    li t0, 0
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 2(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 4(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 6(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 8(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    li t5, 5
+    sub t1, t1, t5
+    sh t1, 4(s0)
+    lhu t1, 0(s0)
+    sh t1, 2(s0)
+    j vm_Sys_2einit
+rt_ret_0:
+vm_Sys_2einit:
+    li t0, 0
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    li t0, 5
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t4, 2(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+vm_Sys_2einit_24LOOP:
+    lhu t4, 2(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    li t0, 3
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    li t0, 1
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 2(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 4(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 6(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, 8(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    li t5, 7
+    sub t1, t1, t5
+    sh t1, 4(s0)
+    lhu t1, 0(s0)
+    sh t1, 2(s0)
+    j vm_Sys_2estep
+rt_ret_1:
+    li t4, 32
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+    lhu t4, 2(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    li t0, 1
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    sub t3, t3, t0
+    sh t3, -2(t2)
+    lhu t4, 2(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+    lhu t4, 2(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    beqz t0, 1f
+    j vm_Sys_2einit_24LOOP
+1:
+    li t4, 32
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t3, -2(t2)
+    neg t3, t3
+    sh t3, -2(t2)
+    li t0, 9
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    sub t3, t3, t0
+    slli t3, t3, 16
+    srai t3, t3, 16
+    sgtz t3, t3
+    neg t3, t3
+    sh t3, -2(t2)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t3, -2(t2)
+    not t3, t3
+    sh t3, -2(t2)
+    li t4, 12
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+vm_Sys_2einit_24HALT:
+    j rt_halt
+vm_Sys_2estep:
+    li t0, 0
+    lhu t4, 4(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t4, 4(s0)
+    li t5, 1
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    add t3, t3, t0
+    sh t3, -2(t2)
+    li t0, 12
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    and t3, t3, t0
+    sh t3, -2(t2)
+    li t0, 1
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    or t3, t3, t0
+    sh t3, -2(t2)
+    lhu t4, 4(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t4, 4(s0)
+    li t5, 1
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    sub t3, t3, t0
+    slli t3, t3, 16
+    srai t3, t3, 16
+    sgtz t3, t3
+    neg t3, t3
+    sh t3, -2(t2)
+    lhu t4, 4(s0)
+    li t5, 0
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t4, 4(s0)
+    li t5, 1
+    add t4, t4, t5
+    and t4, t4, s1
+    slli t4, t4, 1
+    add t4, s0, t4
+    lh t0, 0(t4)
+    lhu t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    sh t0, 0(t2)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    sub t3, t3, t0
+    slli t3, t3, 16
+    srai t3, t3, 16
+    seqz t3, t3
+    neg t3, t3
+    sh t3, -2(t2)
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    lh t3, -2(t2)
+    add t3, t3, t0
+    sh t3, -2(t2)
+    li t4, 8
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+    lhu a1, 2(s0)
+    slli a1, a1, 1
+    add a1, s0, a1
+    lhu a2, -10(a1)
+    lhu t4, 4(s0)
+    slli t4, t4, 1
+    add t4, s0, t4
+    lhu t1, 0(s0)
+    addi t1, t1, -1
+    sh t1, 0(s0)
+    slli t2, t1, 1
+    add t2, s0, t2
+    lh t0, 0(t2)
+    sh t0, 0(t4)
+    lhu t1, 4(s0)
+    addi t1, t1, 1
+    sh t1, 0(s0)
+    lh t0, -2(a1)
+    sh t0, 8(s0)
+    lh t0, -4(a1)
+    sh t0, 6(s0)
+    lh t0, -6(a1)
+    sh t0, 4(s0)
+    lh t0, -8(a1)
+    sh t0, 2(s0)
+    la t0, rt_return_count
+    lw t0, 0(t0)
+    bltu a2, t0, 1f
+    j rt_bad_return
+1:
+    la t0, rt_return_table
+    slli a2, a2, 2
+    add t0, t0, a2
+    lw t0, 0(t0)
+    jr t0
+rt_halt:
+    li s4, 0                    # first address
+    li s5, 16                   # count
+    li t0, 2
+    blt s2, t0, 1f
+    lw a0, 4(s3)
+    call rt_atoi
+    mv s4, a0
+    li t0, 3
+    blt s2, t0, 1f
+    lw a0, 8(s3)
+    call rt_atoi
+    mv s5, a0
+1:
+    blez s5, 2f
+    and s4, s4, s1
+    la a0, rt_text_ram
+    li a1, 4
+    call rt_write
+    mv a0, s4
+    call rt_write_int
+    la a0, rt_text_equals
+    li a1, 4
+    call rt_write
+    slli t0, s4, 1
+    add t0, s0, t0
+    lh a0, 0(t0)
+    call rt_write_int
+    la a0, rt_text_newline
+    li a1, 1
+    call rt_write
+    addi s4, s4, 1
+    addi s5, s5, -1
+    j 1b
+2:
+    li a0, 0
+    li a7, 93                   # exit
+    ecall
+
+# a corrupted frame's return address
+rt_bad_return:
+    li a0, 2
+    la a1, rt_text_bad_return
+    li a2, 23
+    li a7, 64                   # write
+    ecall
+    li a0, 1
+    li a7, 93                   # exit
+    ecall
+
+# a0 = buffer, a1 = length
+rt_write:
+    mv a2, a1
+    mv a1, a0
+    li a0, 1
+    li a7, 64                   # write
+    ecall
+    ret
+
+# prints a0 in decimal by repeated subtraction of powers of ten
+rt_write_int:
+    la t0, rt_buffer
+    bgez a0, 1f
+    li t1, 45                   # '-'
+    sb t1, 0(t0)
+    addi t0, t0, 1
+    neg a0, a0
+1:
+    la t2, rt_powers_of_ten
+    li t5, 0                    # a digit was written
+    li t6, 5
+2:
+    lw t3, 0(t2)
+    li t4, 0
+3:
+    blt a0, t3, 4f
+    sub a0, a0, t3
+    addi t4, t4, 1
+    j 3b
+4:
+    bnez t4, 5f
+    bnez t5, 5f
+    li t1, 1
+    beq t3, t1, 5f              # always write the last digit
+    j 6f
+5:
+    addi t4, t4, 48
+    sb t4, 0(t0)
+    addi t0, t0, 1
+    li t5, 1
+6:
+    addi t2, t2, 4
+    addi t6, t6, -1
+    bnez t6, 2b
+    la a1, rt_buffer
+    sub a2, t0, a1
+    li a0, 1
+    li a7, 64                   # write
+    ecall
+    ret
+
+# a0 = decimal string, returns its value
+rt_atoi:
+    li t0, 0
+1:
+    lbu t1, 0(a0)
+    addi t1, t1, -48
+    li t2, 10
+    bgeu t1, t2, 2f
+    slli t2, t0, 3
+    slli t3, t0, 1
+    add t0, t2, t3
+    add t0, t0, t1
+    addi a0, a0, 1
+    j 1b
+2:
+    mv a0, t0
+    ret
+
+    .section .rodata
+rt_text_ram:
+    .ascii "RAM["
+rt_text_equals:
+    .ascii "] = "
+rt_text_newline:
+    .ascii "\n"
+rt_text_bad_return:
+    .ascii "invalid return address\n"
+    .p2align 2
+rt_powers_of_ten:
+    .word 10000, 1000, 100, 10, 1
+    .p2align 2
+rt_return_count:
+    .word 2
+rt_return_table:
+    .word rt_ret_0
+    .word rt_ret_1
+    .bss
+    .p2align 2
+rt_buffer:
+    .zero 16
+rt_ram:
+    .zero 65536
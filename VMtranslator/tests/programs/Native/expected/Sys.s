@@ -0,0 +1,335 @@
+    .intel_syntax noprefix
+    .text
+    .globl main
+main:
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov r12d, edi
+    mov r13, rsi
+    lea rbx, [rip + rt_ram]
+    mov word ptr [rbx+0], 256
+    mov ax, 0
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+4]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+6]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+8]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx eax, word ptr [rbx]
+    sub eax, 5
+    mov word ptr [rbx+4], ax
+    mov ax, word ptr [rbx]
+    mov word ptr [rbx+2], ax
+    jmp vm_Sys_2einit
+rt_ret_0:
+vm_Sys_2einit:
+    xor eax, eax
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, 5
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx edx, word ptr [rbx+2]
+    add edx, 0
+    and edx, 0x7fff
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    mov word ptr [rbx+rdx*2], ax
+vm_Sys_2einit_24LOOP:
+    movzx edx, word ptr [rbx+2]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, 3
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, 1
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+4]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+6]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, word ptr [rbx+8]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx eax, word ptr [rbx]
+    sub eax, 7
+    mov word ptr [rbx+4], ax
+    mov ax, word ptr [rbx]
+    mov word ptr [rbx+2], ax
+    jmp vm_Sys_2estep
+rt_ret_1:
+    mov edx, 16
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    mov word ptr [rbx+rdx*2], ax
+    movzx edx, word ptr [rbx+2]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    mov ax, 1
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    sub word ptr [rbx+rcx*2-2], ax
+    movzx edx, word ptr [rbx+2]
+    add edx, 0
+    and edx, 0x7fff
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    mov word ptr [rbx+rdx*2], ax
+    movzx edx, word ptr [rbx+2]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    test ax, ax
+    jnz vm_Sys_2einit_24LOOP
+    mov edx, 16
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    neg word ptr [rbx+rcx*2-2]
+    mov ax, 9
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov dx, word ptr [rbx+rcx*2-2]
+    sub dx, word ptr [rbx+rcx*2]
+    test dx, dx
+    setg al
+    movzx eax, al
+    neg eax
+    mov word ptr [rbx+rcx*2-2], ax
+    movzx ecx, word ptr [rbx]
+    not word ptr [rbx+rcx*2-2]
+    mov edx, 6
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    mov word ptr [rbx+rdx*2], ax
+vm_Sys_2einit_24HALT:
+    jmp rt_halt
+vm_Sys_2estep:
+    xor eax, eax
+    movzx edx, word ptr [rbx+4]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx edx, word ptr [rbx+4]
+    add edx, 1
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    add word ptr [rbx+rcx*2-2], ax
+    mov ax, 12
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    and word ptr [rbx+rcx*2-2], ax
+    mov ax, 1
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    or word ptr [rbx+rcx*2-2], ax
+    movzx edx, word ptr [rbx+4]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx edx, word ptr [rbx+4]
+    add edx, 1
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov dx, word ptr [rbx+rcx*2-2]
+    sub dx, word ptr [rbx+rcx*2]
+    test dx, dx
+    setg al
+    movzx eax, al
+    neg eax
+    mov word ptr [rbx+rcx*2-2], ax
+    movzx edx, word ptr [rbx+4]
+    add edx, 0
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    movzx edx, word ptr [rbx+4]
+    add edx, 1
+    and edx, 0x7fff
+    mov ax, word ptr [rbx+rdx*2]
+    movzx ecx, word ptr [rbx]
+    mov word ptr [rbx+rcx*2], ax
+    inc word ptr [rbx]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov dx, word ptr [rbx+rcx*2-2]
+    sub dx, word ptr [rbx+rcx*2]
+    test dx, dx
+    sete al
+    movzx eax, al
+    neg eax
+    mov word ptr [rbx+rcx*2-2], ax
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    add word ptr [rbx+rcx*2-2], ax
+    mov edx, 4
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    mov word ptr [rbx+rdx*2], ax
+    movzx esi, word ptr [rbx+2]
+    movzx edi, word ptr [rbx+rsi*2-10]
+    dec word ptr [rbx]
+    movzx ecx, word ptr [rbx]
+    mov ax, word ptr [rbx+rcx*2]
+    movzx ecx, word ptr [rbx+4]
+    mov word ptr [rbx+rcx*2], ax
+    lea eax, [rcx+1]
+    mov word ptr [rbx], ax
+    mov ax, word ptr [rbx+rsi*2-2]
+    mov word ptr [rbx+8], ax
+    mov ax, word ptr [rbx+rsi*2-4]
+    mov word ptr [rbx+6], ax
+    mov ax, word ptr [rbx+rsi*2-6]
+    mov word ptr [rbx+4], ax
+    mov ax, word ptr [rbx+rsi*2-8]
+    mov word ptr [rbx+2], ax
+    cmp rdi, qword ptr [rip + rt_return_count]
+    jae rt_bad_return
+    lea rax, [rip + rt_return_table]
+    jmp qword ptr [rax+rdi*8]
+rt_halt:
+    xor r14d, r14d
+    mov r15d, 16
+    cmp r12d, 2
+    jl 1f
+    mov rdi, qword ptr [r13+8]
+    call atoi@PLT
+    mov r14d, eax
+    cmp r12d, 3
+    jl 1f
+    mov rdi, qword ptr [r13+16]
+    call atoi@PLT
+    mov r15d, eax
+1:
+    test r15d, r15d
+    jle 2f
+    and r14d, 0x7fff
+    lea rdi, [rip + rt_format]
+    mov esi, r14d
+    movsx edx, word ptr [rbx+r14*2]
+    xor eax, eax
+    call printf@PLT
+    inc r14d
+    dec r15d
+    jmp 1b
+2:
+    xor eax, eax
+rt_exit:
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    ret
+rt_bad_return:
+    mov edx, edi
+    mov rax, qword ptr [rip + stderr@GOTPCREL]
+    mov rdi, qword ptr [rax]
+    lea rsi, [rip + rt_bad_return_format]
+    xor eax, eax
+    call fprintf@PLT
+    mov eax, 1
+    jmp rt_exit
+    .section .rodata
+rt_format:
+    .string "RAM[%d] = %d\n"
+rt_bad_return_format:
+    .string "invalid return address %u\n"
+    .data
+    .p2align 3
+rt_return_count:
+    .quad 2
+rt_return_table:
+    .quad rt_ret_0
+    .quad rt_ret_1
+    .bss
+    .p2align 4
+rt_ram:
+    .zero 65536
+    .section .note.GNU-stack,"",@progbits
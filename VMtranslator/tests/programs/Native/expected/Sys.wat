@@ -0,0 +1,134 @@
+(module
+(memory (export "ram") 1)
+
+  ;; RAM[a], sign-extended
+  (func $get (param $a i32) (result i32)
+    (i32.load16_s (i32.shl (i32.and (local.get $a) (i32.const 0x7fff)) (i32.const 1))))
+
+  (func $set (param $a i32) (param $v i32)
+    (i32.store16 (i32.shl (i32.and (local.get $a) (i32.const 0x7fff)) (i32.const 1)) (local.get $v)))
+
+  (func $push (param $v i32)
+    (local $sp i32)
+    (local.set $sp (call $get (i32.const 0)))
+    (call $set (local.get $sp) (local.get $v))
+    (call $set (i32.const 0) (i32.add (local.get $sp) (i32.const 1))))
+
+  (func $pop (result i32)
+    (local $sp i32)
+    (local.set $sp (i32.sub (call $get (i32.const 0)) (i32.const 1)))
+    (call $set (i32.const 0) (local.get $sp))
+    (call $get (local.get $sp)))
+
+  (func $run (export "run")
+    (local $pc i32) (local $x i32) (local $y i32) (local $frame i32) (local $ret i32)
+    loop $dispatch
+    block $halt
+    block $b6
+    block $b5
+    block $b4
+    block $b3
+    block $b2
+    block $b1
+    block $b0
+      (br_table $b0 $b1 $b2 $b3 $b4 $b6 $b5 $halt (local.get $pc))
+    end ;; $b0
+      (call $set (i32.const 0) (i32.const 256))
+      (call $push (i32.const 1))
+      (call $push (call $get (i32.const 1)))
+      (call $push (call $get (i32.const 2)))
+      (call $push (call $get (i32.const 3)))
+      (call $push (call $get (i32.const 4)))
+      (call $set (i32.const 2) (i32.sub (call $get (i32.const 0)) (i32.const 5)))
+      (call $set (i32.const 1) (call $get (i32.const 0)))
+      (local.set $pc (i32.const 2)) (br $dispatch)
+    end ;; $b1
+      ;; return 0
+    end ;; $b2
+      ;; Sys.init
+      (call $push (i32.const 0))
+      (call $push (i32.const 5))
+      (local.set $x (i32.add (call $get (i32.const 1)) (i32.const 0)))
+      (call $set (local.get $x) (call $pop))
+    end ;; $b3
+      ;; Sys.init$LOOP
+      (call $push (call $get (i32.add (call $get (i32.const 1)) (i32.const 0))))
+      (call $push (i32.const 3))
+      (call $push (i32.const 4))
+      (call $push (call $get (i32.const 1)))
+      (call $push (call $get (i32.const 2)))
+      (call $push (call $get (i32.const 3)))
+      (call $push (call $get (i32.const 4)))
+      (call $set (i32.const 2) (i32.sub (call $get (i32.const 0)) (i32.const 7)))
+      (call $set (i32.const 1) (call $get (i32.const 0)))
+      (local.set $pc (i32.const 5)) (br $dispatch)
+    end ;; $b4
+      ;; return 1
+      (local.set $x (i32.const 16))
+      (call $set (local.get $x) (call $pop))
+      (call $push (call $get (i32.add (call $get (i32.const 1)) (i32.const 0))))
+      (call $push (i32.const 1))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.sub (local.get $x) (local.get $y)))
+      (local.set $x (i32.add (call $get (i32.const 1)) (i32.const 0)))
+      (call $set (local.get $x) (call $pop))
+      (call $push (call $get (i32.add (call $get (i32.const 1)) (i32.const 0))))
+      (if (call $pop) (then (local.set $pc (i32.const 3)) (br $dispatch)))
+      (call $push (call $get (i32.const 16)))
+      (call $push (i32.sub (i32.const 0) (call $pop)))
+      (call $push (i32.const 9))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.sub (i32.const 0) (i32.gt_s (i32.extend16_s (i32.sub (local.get $x) (local.get $y))) (i32.const 0))))
+      (call $push (i32.xor (call $pop) (i32.const -1)))
+      (local.set $x (i32.const 6))
+      (call $set (local.get $x) (call $pop))
+    end ;; $b5
+      ;; Sys.init$HALT
+      (br $halt)
+    end ;; $b6
+      ;; Sys.step
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 0))))
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 1))))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.add (local.get $x) (local.get $y)))
+      (call $push (i32.const 12))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.and (local.get $x) (local.get $y)))
+      (call $push (i32.const 1))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.or (local.get $x) (local.get $y)))
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 0))))
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 1))))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.sub (i32.const 0) (i32.gt_s (i32.extend16_s (i32.sub (local.get $x) (local.get $y))) (i32.const 0))))
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 0))))
+      (call $push (call $get (i32.add (call $get (i32.const 2)) (i32.const 1))))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.sub (i32.const 0) (i32.eqz (i32.extend16_s (i32.sub (local.get $x) (local.get $y))))))
+      (local.set $y (call $pop))
+      (local.set $x (call $pop))
+      (call $push (i32.add (local.get $x) (local.get $y)))
+      (local.set $x (i32.const 4))
+      (call $set (local.get $x) (call $pop))
+      (local.set $frame (call $get (i32.const 1)))
+      (local.set $ret (call $get (i32.sub (local.get $frame) (i32.const 5))))
+      (call $set (call $get (i32.const 2)) (call $pop))
+      (call $set (i32.const 0) (i32.add (call $get (i32.const 2)) (i32.const 1)))
+      (call $set (i32.const 4) (call $get (i32.sub (local.get $frame) (i32.const 1))))
+      (call $set (i32.const 3) (call $get (i32.sub (local.get $frame) (i32.const 2))))
+      (call $set (i32.const 2) (call $get (i32.sub (local.get $frame) (i32.const 3))))
+      (call $set (i32.const 1) (call $get (i32.sub (local.get $frame) (i32.const 4))))
+      (local.set $pc (local.get $ret))
+      (br $dispatch)
+      (br $halt)
+    end ;; $halt
+    end ;; $dispatch
+  )
+)
@@ -0,0 +1,50 @@
+use vmtranslator::emulator::{Emulator, SCREEN};
+use vmtranslator::program::{Options, Program};
+
+// tests/programs/TailCalls: a tail-recursive count 5000 calls deep, and a tail
+// call passing more arguments than the caller got
+
+const CYCLES: u64 = 10_000_000;
+
+fn run(tail_calls: bool) -> (Program, Emulator, bool) {
+    let options = Options { tail_calls, os: false, ..Options::default() };
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/TailCalls");
+    let program = Program::translate(dir, &options).unwrap();
+    let mut emulator = program.emulator(&options);
+    let halted = emulator.run(CYCLES).unwrap_or(false);
+    (program, emulator, halted)
+}
+
+fn static_value(program: &Program, emulator: &Emulator, name: &str) -> i16 {
+    emulator.ram[program.symbols[name] as usize]
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let (program, emulator, halted) = run(true);
+    assert!(halted);
+    assert_eq!(emulator.ram[0], 261); // Sys.init's frame only
+    assert_eq!(static_value(&program, &emulator, "Sys.0"), 5000);
+    assert_eq!(static_value(&program, &emulator, "Sys.1"), 37);
+}
+
+// the 5000 frames need about 45000 words: the stack leaves its 256..2047 and
+// runs through the heap into the screen memory map while count is still
+// recursing, and the program never gets to halt
+#[test]
+fn plain_calls_overflow_the_stack() {
+    let options = Options { os: false, ..Options::default() };
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/TailCalls");
+    let program = Program::translate(dir, &options).unwrap();
+    let mut emulator = program.emulator(&options);
+    while (emulator.ram[0] as u16 as usize) < SCREEN {
+        assert!(emulator.cycles < CYCLES && !emulator.is_halted(), "SP stayed at {}", emulator.ram[0]);
+        emulator.step().unwrap();
+    }
+    assert_eq!(static_value(&program, &emulator, "Sys.0"), 0);
+    assert!(emulator.ram[SCREEN - 2048..SCREEN].iter().any(|&word| word != 0));
+
+    let (_, emulator, halted) = run(false);
+    assert!(!halted);
+    assert!(emulator.ram[SCREEN..SCREEN + 8192].iter().any(|&word| word != 0));
+}